@@ -24,4 +24,62 @@ pub enum CustomError {
     Unauthorized,
     #[msg("Calculation failure")]
     CalculationFailure,
+    #[msg("Concentrated liquidity is disabled for this pool")]
+    ConcentratedLiquidityDisabled,
+    #[msg("Invalid tick range")]
+    InvalidTickRange,
+    #[msg("Accrued fees must be collected before swapping")]
+    FeesMustBeCollected,
+    #[msg("Output amount rounds down to zero")]
+    OutputTooSmall,
+    #[msg("Swap would move the price outside the allowed band")]
+    PriceOutOfBand,
+    #[msg("Token account is not a wrapped SOL account")]
+    NotNativeMint,
+    #[msg("Reserve ratio deviates from the expected ratio")]
+    RatioSkewed,
+    #[msg("Pool has no LP supply, use add_initial_liquidity")]
+    PoolNotSeeded,
+    #[msg("Maximum number of LP holders reached")]
+    HolderCapExceeded,
+    #[msg("Fee destination account is required for real-time fees")]
+    MissingFeeDestination,
+    #[msg("Flash swap was not repaid with enough input")]
+    FlashSwapNotRepaid,
+    #[msg("Swap output exceeds the pool's per-swap limit")]
+    OutputExceedsLimit,
+    #[msg("Swaps halted after a volume spike, pending admin review")]
+    VolumeSpikeHalt,
+    #[msg("Real-time fees and auto-compounding can't both be enabled")]
+    ConflictingFeeMode,
+    #[msg("Initial liquidity mints too few LP tokens")]
+    InitialLiquidityTooSmall,
+    #[msg("Initial liquidity must exceed the permanently locked minimum")]
+    InsufficientInitialLiquidity,
+    #[msg("Pool is too imbalanced; only actions that restore balance are allowed")]
+    PoolImbalanced,
+    #[msg("Transfer credited nothing to the pool vault")]
+    UnexpectedTransferFee,
+    #[msg("Flash loan was not repaid with the fee")]
+    FlashLoanNotRepaid,
+    #[msg("Pool still has outstanding liquidity")]
+    PoolNotEmpty,
+    #[msg("Swap moves the pool price more than the pool allows")]
+    PriceImpactTooHigh,
+    #[msg("Swap cooldown has not elapsed yet")]
+    CooldownActive,
+    #[msg("Swap cooldown account is required while the pool has a cooldown")]
+    MissingCooldownAccount,
+    #[msg("Token A mint must sort before token B mint")]
+    UnorderedMints,
+    #[msg("Pool already holds reserves; use add_liquidity")]
+    PoolAlreadySeeded,
+    #[msg("Pool is already executing an operation")]
+    ReentrancyDetected,
+    #[msg("Fee rate is not one of the supported fee tiers")]
+    InvalidFeeTier,
+    #[msg("Swap would move the price outside the pool's range")]
+    PriceRangeExhausted,
+    #[msg("Pool vaults are not the program's PDAs, so the pool can't be migrated")]
+    UnsupportedPoolVaults,
 }
\ No newline at end of file
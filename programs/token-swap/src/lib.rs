@@ -1,12 +1,74 @@
 use anchor_lang::prelude::{borsh::de, *};
 
-use anchor_spl::{associated_token::AssociatedToken, token_interface::{burn, mint_to, transfer_checked, sync_native as native_sync_native, SyncNative as NativeSyncNative, Burn, Mint, MintTo, TokenAccount, TokenInterface, TransferChecked}};
+use anchor_spl::{associated_token::AssociatedToken, token::spl_token::native_mint, token_interface::{burn, close_account, mint_to, set_authority, transfer_checked, sync_native as native_sync_native, SyncNative as NativeSyncNative, Burn, CloseAccount, Mint, MintTo, SetAuthority, TokenAccount, TokenInterface, TransferChecked, spl_token_2022::instruction::AuthorityType}};
 use crate::error::CustomError;
+use std::collections::BTreeSet;
 
 pub mod error;
+pub mod tick_math;
 
 declare_id!("AxqzHPnPm5Es17u3PuNHTvU2ivgYvZbzFgEgPiaH7Vj8");
 
+// Precision of the per-LP fee accumulators
+pub const FEES_PER_LP_SCALE: u128 = 1_000_000_000_000;
+
+// Fee rates (basis points) a pool can be created with; a pool's fee_tier indexes into this list
+pub const FEE_TIERS: [u64; 4] = [1, 5, 30, 100];
+
+// fee_tier of a pool created before fee tiers, whose pool_authority was derived without a tier seed
+pub const LEGACY_FEE_TIER: u8 = u8::MAX;
+
+// Bumped whenever SwapPool gains fields; pools created now start at this version
+pub const CURRENT_POOL_VERSION: u8 = 26;
+
+// Default floor on the LP minted by add_initial_liquidity
+pub const DEFAULT_MIN_INITIAL_LP: u64 = 1_000;
+
+// LP permanently locked with the pool authority on the first deposit, so the supply never returns to a
+// few units that a donation could inflate
+pub const MINIMUM_LIQUIDITY: u64 = 1_000;
+
+// Default cap on how far one swap may move the pool price
+pub const DEFAULT_MAX_PRICE_IMPACT_BPS: u64 = 5_000;
+
+// SwapPool.paused_ops bits
+pub const PAUSE_SWAPS: u8 = 1 << 0;
+pub const PAUSE_DEPOSITS: u8 = 1 << 1;
+pub const PAUSE_WITHDRAWALS: u8 = 1 << 2;
+pub const PAUSE_ALL: u8 = PAUSE_SWAPS | PAUSE_DEPOSITS | PAUSE_WITHDRAWALS;
+
+// SwapPool.curve_type values
+pub const CURVE_CONSTANT_PRODUCT: u8 = 0;
+pub const CURVE_STABLE: u8 = 1;
+
+// Upper bound on the StableSwap amplification coefficient
+pub const MAX_AMP: u64 = 10_000;
+
+// Upper bound on a caller-chosen LP mint decimals
+pub const MAX_LP_DECIMALS: u8 = 9;
+
+// Byte offset of SwapPool.admin: discriminator + six pubkeys + fee_rate + bump + paused_ops
+pub const SWAP_POOL_ADMIN_OFFSET: usize = 8 + 32 * 6 + 8 + 1 + 1;
+
+// Borrow a deposit/withdrawal context's token accounts as LiquidityAccounts, leaving its swap_pool free
+// to be borrowed mutably alongside
+macro_rules! liquidity_accounts {
+    ($accounts:expr) => {
+        LiquidityAccounts {
+            token_a_mint: &$accounts.token_a_mint,
+            token_b_mint: &$accounts.token_b_mint,
+            token_a_vault: &$accounts.token_a_vault,
+            token_b_vault: &$accounts.token_b_vault,
+            user_token_a: &$accounts.user_token_a,
+            user_token_b: &$accounts.user_token_b,
+            lp_mint: &$accounts.lp_mint,
+            pool_authority: &$accounts.pool_authority,
+            user_authority: &$accounts.user_authority,
+            token_program: &$accounts.token_program,
+        }
+    };
+}
+
 #[program]
 pub mod token_swap {
     use anchor_lang::Result;
@@ -16,35 +78,137 @@ pub mod token_swap {
     pub fn initialize_pool(
         ctx: Context<InitializePool>,
         fee_rate: u64,
-        bump: u8,
+        curve_type: u8,
+        amp: u64,
+        lp_decimals: Option<u8>,
     ) -> Result<()> {
+        #[cfg(feature = "verbose_logs")]
         msg!("Initializing token swap pool with simplified access");
+
+        // One pool per pair: the mints must differ and come in canonical order, smaller pubkey first
+        let (mint_a_key, mint_b_key) = (ctx.accounts.token_a_mint.key(), ctx.accounts.token_b_mint.key());
+        require!(mint_a_key != mint_b_key, CustomError::InvalidToken);
+        require!(mint_a_key < mint_b_key, CustomError::UnorderedMints);
     
         // Validate fee rate
         require!(fee_rate <= 1000, CustomError::FeeTooHigh);
+        // None keeps the LP mint at the larger of the two token decimals
+        require!(lp_decimals.map_or(true, |decimals| decimals <= MAX_LP_DECIMALS), CustomError::InvalidAmount);
+        // Pools of one pair are told apart by their fee tier, which is part of the authority seeds
+        let fee_tier = FEE_TIERS
+            .iter()
+            .position(|&tier_rate| tier_rate == fee_rate)
+            .ok_or(CustomError::InvalidFeeTier)? as u8;
+
+        // The stable curve compares raw amounts, so both sides must share decimals
+        match curve_type {
+            CURVE_CONSTANT_PRODUCT => {}
+            CURVE_STABLE => {
+                require!(amp > 0 && amp <= MAX_AMP, CustomError::InvalidAmount);
+                require!(
+                    ctx.accounts.token_a_mint.decimals == ctx.accounts.token_b_mint.decimals,
+                    CustomError::InvalidToken
+                );
+            }
+            _ => return Err(CustomError::InvalidAmount.into()),
+        }
+
+        // Derive the canonical authority ourselves rather than trusting a caller-supplied bump
+        let (expected_authority, authority_bump) = Pubkey::find_program_address(
+            &[
+                b"pool_authority".as_ref(),
+                ctx.accounts.token_a_mint.key().as_ref(),
+                ctx.accounts.token_b_mint.key().as_ref(),
+                &[fee_tier],
+            ],
+            ctx.program_id,
+        );
+        require!(ctx.accounts.pool_authority.key() == expected_authority, CustomError::InvalidSwapPool);
         
         // Get a reference to the swap pool
         let swap_pool = &mut ctx.accounts.swap_pool;
         
         // Copy data from accounts to the swap pool one by one very carefully
         let token_a_mint = ctx.accounts.token_a_mint.to_account_info().key();
+        #[cfg(feature = "verbose_logs")]
         msg!("Token A mint key copied: {}", token_a_mint);
         swap_pool.token_a_mint = token_a_mint;
         
         let token_b_mint = ctx.accounts.token_b_mint.to_account_info().key();
+        #[cfg(feature = "verbose_logs")]
         msg!("Token B mint key copied: {}", token_b_mint);
         swap_pool.token_b_mint = token_b_mint;
         
+        swap_pool.token_a_vault = ctx.accounts.token_a_vault.key();
         swap_pool.token_b_vault = ctx.accounts.token_b_vault.key();
         swap_pool.lp_mint = ctx.accounts.lp_mint.key();
         swap_pool.pool_authority = ctx.accounts.pool_authority.key();
         swap_pool.fee_rate = fee_rate;
-        swap_pool.bump = bump;
-        swap_pool.is_paused = false;
+        swap_pool.fee_tier = fee_tier;
+        swap_pool.bump = authority_bump; // Kept in step with authority_bump for older readers
+        swap_pool.paused_ops = 0;
         swap_pool.admin = ctx.accounts.admin.key();
         swap_pool.total_fees_a = 0;
         swap_pool.total_fees_b = 0;
+        swap_pool.burn_fee_bps = 0;
+        swap_pool.tick_spacing = 0;
+        swap_pool.max_accrued_fees = 0;
+        swap_pool.min_price = 0;
+        swap_pool.max_price = 0;
+        swap_pool.balance_incentive_bps = 0;
+        swap_pool.fees_per_lp_a = 0;
+        swap_pool.fees_per_lp_b = 0;
+        swap_pool.holder_count = 0;
+        swap_pool.max_holders = 0;
+        swap_pool.fee_destination_a = Pubkey::default();
+        swap_pool.fee_destination_b = Pubkey::default();
+        swap_pool.realtime_fees = false;
+        swap_pool.max_output_fraction_bps = 10000;
+        swap_pool.version = CURRENT_POOL_VERSION;
+        swap_pool.lbp_start_weight = 0;
+        swap_pool.lbp_end_weight = 0;
+        swap_pool.lbp_start_ts = 0;
+        swap_pool.lbp_end_ts = 0;
+        swap_pool.spike_bucket_secs = 0;
+        swap_pool.spike_multiple = 0;
+        swap_pool.volume_buckets = [0; 8];
+        swap_pool.volume_bucket_start = 0;
+        swap_pool.volume_bucket_head = 0;
+        swap_pool.volume_halted = false;
+        swap_pool.auto_compound_fees = false;
+        swap_pool.min_initial_lp = DEFAULT_MIN_INITIAL_LP;
+        swap_pool.fee_exemptions_enabled = false;
+        swap_pool.reserve_checkpoint_a = 0;
+        swap_pool.reserve_checkpoint_b = 0;
+        swap_pool.checkpoint_ts = 0;
+        swap_pool.protocol_max_slippage_bps = 10000;
+        swap_pool.cumulative_volume_a = 0;
+        swap_pool.cumulative_volume_b = 0;
+        swap_pool.max_imbalance_bps = 0;
+        swap_pool.protocol_fee_share = 10000;
+        swap_pool.lifetime_fees_a = 0;
+        swap_pool.lifetime_fees_b = 0;
+        swap_pool.vault_a_bump = ctx.bumps.token_a_vault;
+        swap_pool.vault_b_bump = ctx.bumps.token_b_vault;
+        swap_pool.authority_bump = authority_bump;
+        swap_pool.price_cumulative_a = 0;
+        swap_pool.price_cumulative_b = 0;
+        swap_pool.last_price_update = 0;
+        swap_pool.curve_type = curve_type;
+        swap_pool.amp = if curve_type == CURVE_STABLE { amp } else { 0 };
+        swap_pool.max_price_impact_bps = DEFAULT_MAX_PRICE_IMPACT_BPS;
+        swap_pool.swap_cooldown_secs = 0;
+        swap_pool.swap_count = 0;
+        swap_pool.liquidity_event_count = 0;
+        swap_pool.in_progress = false;
+        swap_pool.fee_authority = ctx.accounts.admin.key();
+        swap_pool.fee_vault_a = ctx.accounts.fee_vault_a.key();
+        swap_pool.fee_vault_b = ctx.accounts.fee_vault_b.key();
+        swap_pool.range_lower_tick = 0;
+        swap_pool.range_upper_tick = 0;
+        swap_pool.lp_decimals = ctx.accounts.lp_mint.decimals;
         
+        #[cfg(feature = "verbose_logs")]
         msg!("Token swap pool initialized");
     
         Ok(())
@@ -54,8 +218,56 @@ pub mod token_swap {
         ctx: Context<AddInitialLiquidity>,
         amount_a: u64,
         amount_b: u64,
-    ) -> Result<()> {
+        min_lp_out: u64,
+    ) -> Result<LiquidityResult> {
         require!(amount_a > 0 && amount_b > 0, CustomError::InvalidAmount);
+        // Only an empty pool can be seeded; once it holds reserves, deposits go through add_liquidity
+        require!(
+            ctx.accounts.token_a_vault.amount == 0 && ctx.accounts.token_b_vault.amount == 0,
+            CustomError::PoolAlreadySeeded
+        );
+        // Nothing has been held yet, so this only starts the TWAP clock at the seeded price
+        ctx.accounts.swap_pool.accumulate_prices(0, 0, Clock::get()?.unix_timestamp);
+        // Report a short balance with the program's own error rather than the token program's
+        require!(
+            ctx.accounts.user_token_a.amount >= amount_a && ctx.accounts.user_token_b.amount >= amount_b,
+            CustomError::InsufficientFunds
+        );
+        
+        // Initial LP tokens are the geometric mean of token amounts
+        // This encourages balanced liquidity provision
+        // Both amounts are first normalized to the LP mint's decimals so the result is in LP units;
+        // the LP mint may carry fewer decimals than the tokens, so the combined scale can shrink the product
+        let exponent = 2 * ctx.accounts.lp_mint.decimals as i32
+            - ctx.accounts.token_a_mint.decimals as i32
+            - ctx.accounts.token_b_mint.decimals as i32;
+        let scale = 10u128.checked_pow(exponent.unsigned_abs()).ok_or(CustomError::CalculationFailure)?;
+        let product = (amount_a as u128)
+            .checked_mul(amount_b as u128)
+            .and_then(|ab| if exponent >= 0 { ab.checked_mul(scale) } else { Some(ab / scale) })
+            .ok_or(CustomError::CalculationFailure)?;
+        let initial_lp_tokens = u64::try_from(isqrt(product)).map_err(|_| CustomError::CalculationFailure)?;
+
+        // A tiny initial supply makes every later deposit round badly, so require a meaningful seed
+        require!(initial_lp_tokens >= ctx.accounts.swap_pool.min_initial_lp, CustomError::InitialLiquidityTooSmall);
+        require!(initial_lp_tokens > MINIMUM_LIQUIDITY, CustomError::InsufficientInitialLiquidity);
+        let user_lp_tokens = initial_lp_tokens - MINIMUM_LIQUIDITY;
+        require!(user_lp_tokens >= min_lp_out, CustomError::SlippageExceeded);
+
+        // Record the deposit before any CPI so hooks never observe stale pool state
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = ctx.accounts.lp_mint.supply.checked_add(initial_lp_tokens).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
+        }
+
+        // Count first-time LP recipients against the pool's holder cap
+        let lp_holder = &mut ctx.accounts.lp_holder;
+        lp_holder.swap_pool = ctx.accounts.swap_pool.key();
+        lp_holder.owner = ctx.accounts.user_authority.key();
+        lp_holder.bump = ctx.bumps.lp_holder;
+        lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)?;
+        lp_holder.record_entry(amount_a, amount_b, user_lp_tokens)?;
+        ctx.accounts.swap_pool.register_lp_holder(lp_holder)?;
         
         // Transfer token A from user to pool
         let transfer_a_ctx = CpiContext::new(
@@ -91,17 +303,13 @@ pub mod token_swap {
             ctx.accounts.token_b_mint.decimals
         )?;
 
-        // Initial LP tokens are the geometric mean of token amounts
-        // This encourages balanced liquidity provision
-        let initial_lp_amount = (amount_a as f64).sqrt() * (amount_b as f64).sqrt();
-        let initial_lp_tokens = initial_lp_amount as u64;
-
         // Mint LP tokens to user
         let seeds = &[
             b"pool_authority".as_ref(),
             ctx.accounts.swap_pool.token_a_mint.as_ref(),
             ctx.accounts.swap_pool.token_b_mint.as_ref(),
-            &[ctx.accounts.swap_pool.bump],
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
         ];
         let signer = &[&seeds[..]];
 
@@ -117,10 +325,26 @@ pub mod token_swap {
 
         mint_to (
            mint_lp_ctx,
-           initial_lp_tokens, 
+           user_lp_tokens, 
+        )?;
+
+        // Lock the minimum liquidity in its dedicated account; no instruction ever signs it back out
+        let lock_lp_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                to: ctx.accounts.locked_lp_token.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer,
         );
-        
-        Ok(())
+
+        mint_to(lock_lp_ctx, MINIMUM_LIQUIDITY)?;
+
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        // The locked minimum never reaches the depositor, so it isn't part of the returned amount
+        Ok(LiquidityResult { amount_a, amount_b, lp_amount: user_lp_tokens })
     }
 
     pub fn add_liquidity(
@@ -128,98 +352,44 @@ pub mod token_swap {
         amount_a_desired: u64,
         amount_b_desired: u64,
         amount_a_min: u64,
-        amount_b_min: u64
-    ) -> Result<()> {
-        require!(!ctx.accounts.swap_pool.is_paused, CustomError::PoolPaused);
-        require!(amount_a_desired > 0 && amount_b_desired > 0, CustomError::InvalidAmount);
-
+        amount_b_min: u64,
+        expected_ratio: Option<u64>,
+        ratio_tolerance_bps: u64
+    ) -> Result<LiquidityResult> {
+        let (amount_a, amount_b, lp_to_mint) = deposit_liquidity(
+            &mut ctx.accounts.swap_pool,
+            &liquidity_accounts!(ctx.accounts),
+            (amount_a_desired, amount_b_desired),
+            (amount_a_min, amount_b_min),
+            expected_ratio,
+            ratio_tolerance_bps,
+        )?;
         let reserve_a = ctx.accounts.token_a_vault.amount;
         let reserve_b = ctx.accounts.token_b_vault.amount;
         let total_lp_supply = ctx.accounts.lp_mint.supply;
 
-        require!(reserve_a > 0 && reserve_b > 0, CustomError::InsufficientLiquidity);
-
-        // Calculate amounts to actually transfer based on current ratio
-        let amount_b_optimal = (amount_a_desired as u128)
-            .checked_mul(reserve_b as u128)
-            .unwrap()
-            .checked_div(reserve_a as u128)
-            .unwrap() as u64;
-
-        let (amount_a, amount_b) = if amount_b_desired >= amount_b_optimal {
-            let amount_a = amount_a_desired;
-            let amount_b = amount_b_optimal;
-
-            require!(amount_b >= amount_b_min, CustomError::SlippageExceeded);
-            (amount_a, amount_b)
-        } else {
-            let amount_a_optimal = (amount_b_desired as u128)
-                .checked_mul(reserve_a as u128)
-                .unwrap()
-                .checked_div(reserve_b as u128)
-                .unwrap() as u64;
-
-            let amount_a = amount_a_optimal;
-            let amount_b = amount_b_desired;
-
-            require!(amount_a >= amount_a_min, CustomError::SlippageExceeded);
-            (amount_a, amount_b)
-        };
-
-        // Transfer token A from user to pool
-        let transfer_a_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: ctx.accounts.user_token_a.to_account_info(),
-                to: ctx.accounts.token_a_vault.to_account_info(),
-                authority: ctx.accounts.user_authority.to_account_info(),
-                mint: ctx.accounts.token_a_mint.to_account_info(),
-            },
-        );
-        transfer_checked(
-            transfer_a_ctx,
-            amount_a,
-            ctx.accounts.token_a_mint.decimals
-        )?;
-
-        // Transfer token B from user to pool
-        let transfer_b_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: ctx.accounts.user_token_b.to_account_info(),
-                to: ctx.accounts.token_b_vault.to_account_info(),
-                authority: ctx.accounts.user_authority.to_account_info(),
-                mint: ctx.accounts.token_b_mint.to_account_info(),
-            },
-        );
-        transfer_checked(
-            transfer_b_ctx,
-            amount_b,
-            ctx.accounts.token_b_mint.decimals
-        )?;
-
-        // Calculate LP tokens to mint
-        // The formula uses the minimum ratio to ensure fair distribution
-        let lp_amount_a = (amount_a as u128)
-            .checked_mul(total_lp_supply as u128)
-            .unwrap()
-            .checked_div(reserve_a as u128)
-            .unwrap() as u64;
-
-        let lp_amount_b = (amount_b as u128)
-            .checked_mul(total_lp_supply as u128)
-            .unwrap()
-            .checked_div(reserve_b as u128)
-            .unwrap() as u64;
+        // Record the deposit before minting so hooks never observe stale pool state
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_add(lp_to_mint).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
+        }
 
-        let lp_to_mint = std::cmp::min(lp_amount_a, lp_amount_b);
+        // Count first-time LP recipients against the pool's holder cap
+        let lp_holder = &mut ctx.accounts.lp_holder;
+        lp_holder.swap_pool = ctx.accounts.swap_pool.key();
+        lp_holder.owner = ctx.accounts.user_authority.key();
+        lp_holder.bump = ctx.bumps.lp_holder;
+        lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)?;
+        lp_holder.record_entry(amount_a, amount_b, lp_to_mint)?;
+        ctx.accounts.swap_pool.register_lp_holder(lp_holder)?;
 
         // Mint LP tokens to user
         let seeds= &[
             b"pool_authority".as_ref(),
             ctx.accounts.swap_pool.token_a_mint.as_ref(),
             ctx.accounts.swap_pool.token_b_mint.as_ref(),
-            &[ctx.accounts.swap_pool.bump],
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
         ];
         let signer = &[&seeds[..]];
 
@@ -238,599 +408,5003 @@ pub mod token_swap {
             lp_to_mint,
         )?;
 
-        Ok(())
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        emit!(LiquidityAdded {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount: lp_to_mint,
+            amount_a,
+            amount_b,
+            reserve_a: reserve_a.checked_add(amount_a).ok_or(CustomError::CalculationFailure)?,
+            reserve_b: reserve_b.checked_add(amount_b).ok_or(CustomError::CalculationFailure)?,
+        });
+
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(LiquidityResult { amount_a, amount_b, lp_amount: lp_to_mint })
     }
 
-    pub fn remove_liquidity(
-        ctx: Context<RemoveLiquidity>,
-        lp_amount: u64,
+    // Same deposit as add_liquidity, but the position is represented by a 1-of-1 NFT
+    // The minted LP is escrowed by the pool authority and recorded on a PDA keyed by the NFT mint
+    pub fn add_liquidity_nft(
+        ctx: Context<AddLiquidityNft>,
+        amount_a_desired: u64,
+        amount_b_desired: u64,
         amount_a_min: u64,
-        amount_b_min: u64,
+        amount_b_min: u64
     ) -> Result<()> {
-        require!(!ctx.accounts.swap_pool.is_paused, CustomError::PoolPaused);
-        require!(lp_amount > 0, CustomError::InvalidAmount);
-
-        // Get current reserves and total supply
+        let (amount_a, amount_b, lp_to_mint) = deposit_liquidity(
+            &mut ctx.accounts.swap_pool,
+            &liquidity_accounts!(ctx.accounts),
+            (amount_a_desired, amount_b_desired),
+            (amount_a_min, amount_b_min),
+            None,
+            0,
+        )?;
+        require!(lp_to_mint > 0, CustomError::InvalidAmount);
         let reserve_a = ctx.accounts.token_a_vault.amount;
         let reserve_b = ctx.accounts.token_b_vault.amount;
         let total_lp_supply = ctx.accounts.lp_mint.supply;
 
-        // Calculate share of pool being withdrawn
-        let amount_a = (lp_amount as u128)
-            .checked_mul(reserve_a as u128)
-            .unwrap()
-            .checked_div(total_lp_supply as u128)
-            .unwrap() as u64;
-
-        let amount_b = (lp_amount as u128)
-            .checked_mul(reserve_b as u128)
-            .unwrap()
-            .checked_div(total_lp_supply as u128)
-            .unwrap() as u64;
+        // Record the deposit before minting so hooks never observe stale pool state
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_add(lp_to_mint).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
+        }
 
-        require!(amount_a >= amount_a_min, CustomError::SlippageExceeded);
-        require!(amount_b >= amount_b_min, CustomError::SlippageExceeded);
+        // The position is the holder: its fees and entry snapshot follow the NFT, not the wallet holding it
+        let lp_holder = &mut ctx.accounts.lp_holder;
+        lp_holder.swap_pool = ctx.accounts.swap_pool.key();
+        lp_holder.owner = ctx.accounts.nft_position.key();
+        lp_holder.bump = ctx.bumps.lp_holder;
+        lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, 0)?;
+        lp_holder.record_entry(amount_a, amount_b, lp_to_mint)?;
+        ctx.accounts.swap_pool.register_lp_holder(lp_holder)?;
 
-        // Burn LP tokens
         let seeds = &[
             b"pool_authority".as_ref(),
             ctx.accounts.swap_pool.token_a_mint.as_ref(),
             ctx.accounts.swap_pool.token_b_mint.as_ref(),
-            &[ctx.accounts.swap_pool.bump],
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
         ];
         let signer = &[&seeds[..]];
 
-        let burn_ctx = CpiContext::new_with_signer(
+        // LP backing the position stays in the pool authority's escrow
+        let mint_lp_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            Burn {
+            MintTo {
                 mint: ctx.accounts.lp_mint.to_account_info(),
-                from: ctx.accounts.user_lp_token.to_account_info(),
+                to: ctx.accounts.position_lp_vault.to_account_info(),
                 authority: ctx.accounts.pool_authority.to_account_info(),
             },
             signer
         );
-        burn(burn_ctx, lp_amount)?;
+        mint_to(mint_lp_ctx, lp_to_mint)?;
 
-        // Transfer tokens from pool to user
-        // Transfer token A
-        let transfer_a_ctx = CpiContext::new_with_signer(
+        // Mint the single position NFT to the user
+        let mint_nft_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: ctx.accounts.token_a_vault.to_account_info(),
-                to: ctx.accounts.user_token_a.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.position_mint.to_account_info(),
+                to: ctx.accounts.user_position_token.to_account_info(),
                 authority: ctx.accounts.pool_authority.to_account_info(),
-                mint: ctx.accounts.token_a_mint.to_account_info(),
             },
             signer
         );
-        transfer_checked(
-            transfer_a_ctx,
-            amount_a,
-            ctx.accounts.token_a_mint.decimals
-        )?;
+        mint_to(mint_nft_ctx, 1)?;
 
-        // Transfer token B
-        let transfer_b_ctx = CpiContext::new_with_signer(
+        // Drop the mint authority so the supply is fixed at one
+        let revoke_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: ctx.accounts.token_b_vault.to_account_info(),
-                to: ctx.accounts.user_token_b.to_account_info(),
-                authority: ctx.accounts.pool_authority.to_account_info(),
-                mint: ctx.accounts.token_b_mint.to_account_info(),
+            SetAuthority {
+                current_authority: ctx.accounts.pool_authority.to_account_info(),
+                account_or_mint: ctx.accounts.position_mint.to_account_info(),
             },
             signer
         );
-        transfer_checked(
-            transfer_b_ctx,
+        set_authority(revoke_ctx, AuthorityType::MintTokens, None)?;
+
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        let nft_position = &mut ctx.accounts.nft_position;
+        nft_position.swap_pool = ctx.accounts.swap_pool.key();
+        nft_position.position_mint = ctx.accounts.position_mint.key();
+        nft_position.liquidity = lp_to_mint;
+        nft_position.bump = ctx.bumps.nft_position;
+
+        emit!(LiquidityAdded {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount: lp_to_mint,
+            amount_a,
             amount_b,
-            ctx.accounts.token_b_mint.decimals
-        )?;
+            reserve_a: reserve_a.checked_add(amount_a).ok_or(CustomError::CalculationFailure)?,
+            reserve_b: reserve_b.checked_add(amount_b).ok_or(CustomError::CalculationFailure)?,
+        });
 
+        ctx.accounts.swap_pool.in_progress = false;
         Ok(())
     }
 
-    pub fn swap(
-        ctx: Context<Swap>,
-        amount_in: u64,
-        min_amount_out: u64
+    // Burn the position NFT and withdraw the LP share it represents
+    pub fn remove_liquidity_nft(
+        ctx: Context<RemoveLiquidityNft>,
+        amount_a_min: u64,
+        amount_b_min: u64,
     ) -> Result<()> {
-        require!(!ctx.accounts.swap_pool.is_paused, CustomError::PoolPaused);
-        require!(amount_in > 0, CustomError::InvalidAmount);
-
-        let swap_pool = &mut ctx.accounts.swap_pool;
-        let token_a_vault = &ctx.accounts.token_a_vault;
-        let token_b_vault = &ctx.accounts.token_b_vault;
-        let user_token_a = &ctx.accounts.user_token_a;
-        let user_token_b = &ctx.accounts.user_token_b;
-        let token_a_mint = &ctx.accounts.token_a_mint;
-        let token_b_mint = &ctx.accounts.token_b_mint;
-
-        let (input_amount, input_token_account, redeem_token_account, input_token_vault, redeem_token_vault, input_token_mint, redeem_token_mint, is_a_to_b) =
-        if user_token_a.mint == swap_pool.token_a_mint {
-            (amount_in, user_token_a, user_token_b, token_a_vault, token_b_vault, token_a_mint, token_b_mint, true)
-        } else if user_token_b.mint == swap_pool.token_b_mint {
-            (amount_in, user_token_b, user_token_a, token_b_vault, token_a_vault, token_b_mint, token_a_mint, false)
-        } else {
-            return Err(CustomError::InvalidToken.into());
-        };
-
-        let input_token_vault_amount = input_token_vault.amount;
-        let redeem_token_vault_amount = redeem_token_vault.amount;
-
-        let new_input_token_vault_amount = input_token_vault_amount.checked_add(input_amount)
-            .ok_or(CustomError::InvalidAmount)?;
-
-        let new_redeem_token_vault_amount = input_token_vault_amount.checked_mul(redeem_token_vault_amount).ok_or(CustomError::InvalidAmount)?.checked_div(new_input_token_vault_amount).ok_or(CustomError::InvalidAmount)?;
-
-        let amount_to_redeem = redeem_token_vault_amount.checked_sub(new_redeem_token_vault_amount)
-            .ok_or(CustomError::InvalidAmount)?;
-
-        let fee_amount = amount_to_redeem.checked_mul(swap_pool.fee_rate).ok_or(CustomError::InvalidAmount)?.checked_div(10000).ok_or(CustomError::InvalidAmount)?;
+        let lp_amount = ctx.accounts.nft_position.liquidity;
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
 
-        let final_amount_to_redeem = amount_to_redeem.checked_sub(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        let (amount_a, amount_b) = withdraw_liquidity(
+            &mut ctx.accounts.swap_pool,
+            &liquidity_accounts!(ctx.accounts),
+            lp_amount,
+            (amount_a_min, amount_b_min),
+        )?;
 
-        if is_a_to_b {
-            swap_pool.total_fees_b = swap_pool.total_fees_b.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;   
-        } else {
-            swap_pool.total_fees_a = swap_pool.total_fees_a.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        // Settle all LP bookkeeping before the burns and transfers
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_sub(lp_amount).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
         }
 
-        require!(final_amount_to_redeem >= min_amount_out, CustomError::SlippageExceeded);
+        // The whole position leaves at once, so it stops counting against the holder cap
+        ctx.accounts.lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, lp_amount)?;
+        let impermanent_loss_bps = ctx.accounts.lp_holder
+            .take_entry(lp_amount)
+            .and_then(|(held_a, held_b)| impermanent_loss_bps(held_a, held_b, amount_a, amount_b, reserve_a, reserve_b));
+        ctx.accounts.swap_pool.release_lp_holder(&mut ctx.accounts.lp_holder)?;
 
-        let transfer_from_user_cpi = CpiContext::new(
+        // The holder burns the NFT, proving ownership of the position
+        let burn_nft_ctx = CpiContext::new(
             ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: input_token_account.to_account_info(),
-                to: input_token_vault.to_account_info(),
+            Burn {
+                mint: ctx.accounts.position_mint.to_account_info(),
+                from: ctx.accounts.user_position_token.to_account_info(),
                 authority: ctx.accounts.user_authority.to_account_info(),
-                mint: input_token_mint.to_account_info(),
-            }
+            },
         );
-
-        transfer_checked(transfer_from_user_cpi, input_amount, input_token_mint.decimals)?;
+        burn(burn_nft_ctx, 1)?;
 
         let seeds = &[
             b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref(),
-            &[swap_pool.bump],
+            ctx.accounts.swap_pool.token_a_mint.as_ref(),
+            ctx.accounts.swap_pool.token_b_mint.as_ref(),
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
         ];
         let signer = &[&seeds[..]];
 
-        let transfer_to_user_cpi = CpiContext::new_with_signer(
+        // Burn the escrowed LP backing the position
+        let burn_lp_ctx = CpiContext::new_with_signer(
             ctx.accounts.token_program.to_account_info(),
-            TransferChecked {
-                from: redeem_token_vault.to_account_info(),
-                to: redeem_token_account.to_account_info(),
+            Burn {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                from: ctx.accounts.position_lp_vault.to_account_info(),
                 authority: ctx.accounts.pool_authority.to_account_info(),
-                mint: redeem_token_mint.to_account_info(),
             },
             signer
         );
+        burn(burn_lp_ctx, lp_amount)?;
 
-        transfer_checked(transfer_to_user_cpi, final_amount_to_redeem, redeem_token_mint.decimals)?;
+        pay_out_liquidity(&ctx.accounts.swap_pool, &liquidity_accounts!(ctx.accounts), amount_a, amount_b)?;
 
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        emit!(LiquidityRemoved {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount,
+            amount_a,
+            amount_b,
+            impermanent_loss_bps,
+            reserve_a: reserve_a - amount_a,
+            reserve_b: reserve_b - amount_b,
+        });
+
+        ctx.accounts.swap_pool.in_progress = false;
         Ok(())
     }
 
-    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
-        require!(ctx.accounts.fee_collector.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+    pub fn remove_liquidity(
+        ctx: Context<RemoveLiquidity>,
+        lp_amount: u64,
+        amount_a_min: u64,
+        amount_b_min: u64,
+    ) -> Result<LiquidityResult> {
+        // Get current reserves and total supply
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
 
-        let swap_pool = &mut ctx.accounts.swap_pool;
-        let fee_amount_a = swap_pool.total_fees_a;
-        let fee_amount_b = swap_pool.total_fees_b;
+        // Calculate share of pool being withdrawn
+        let (amount_a, amount_b) = withdraw_liquidity(
+            &mut ctx.accounts.swap_pool,
+            &liquidity_accounts!(ctx.accounts),
+            lp_amount,
+            (amount_a_min, amount_b_min),
+        )?;
 
-        // Reset fee counters
-        swap_pool.total_fees_a = 0;
-        swap_pool.total_fees_b = 0;
+        // Settle all LP bookkeeping before the burn and transfers
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_sub(lp_amount).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
+        }
 
-        if fee_amount_a > 0 {
-            let seeds = &[
-                b"pool_authority".as_ref(),
-                swap_pool.token_a_mint.as_ref(),
-                swap_pool.token_b_mint.as_ref(),
-                &[swap_pool.bump],
-            ];
-            let signer = &[&seeds[..]];
+        // Settle fee earnings on the balance held up to this burn
+        ctx.accounts.lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)?;
 
-            let transfer_a_cpi = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                TransferChecked {
-                    from: ctx.accounts.token_a_vault.to_account_info(),
-                    to: ctx.accounts.fee_collector_token_a.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                    mint: ctx.accounts.token_a_mint.to_account_info(),
-                },
-                signer
-            );
-            transfer_checked(
-                transfer_a_cpi,
-                fee_amount_a,
-                ctx.accounts.token_a_mint.decimals
-            )?;
+        // Compare what the deposit would be worth if held against what the LP pays out now
+        let impermanent_loss_bps = ctx.accounts.lp_holder
+            .take_entry(lp_amount)
+            .and_then(|(held_a, held_b)| impermanent_loss_bps(held_a, held_b, amount_a, amount_b, reserve_a, reserve_b));
+
+        // A holder who burned their whole balance no longer counts against the cap
+        if ctx.accounts.user_lp_token.amount == lp_amount {
+            ctx.accounts.swap_pool.release_lp_holder(&mut ctx.accounts.lp_holder)?;
         }
 
-        if fee_amount_b > 0 {
-            let seeds = &[
-                b"pool_authority".as_ref(),
-                swap_pool.token_a_mint.as_ref(),
-                swap_pool.token_b_mint.as_ref(),
-                &[swap_pool.bump],
-            ];
-            let signer = &[&seeds[..]];
+        // The LP account belongs to the user, so the user signs the burn
+        let burn_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                from: ctx.accounts.user_lp_token.to_account_info(),
+                authority: ctx.accounts.user_authority.to_account_info(),
+            },
+        );
+        burn(burn_ctx, lp_amount)?;
 
-            let transfer_b_cpi = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                TransferChecked {
-                    from: ctx.accounts.token_b_vault.to_account_info(),
-                    to: ctx.accounts.fee_collector_token_b.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                    mint: ctx.accounts.token_b_mint.to_account_info(),
-                },
-                signer
-            );
-            transfer_checked(
-                transfer_b_cpi,
-                fee_amount_b,
-                ctx.accounts.token_b_mint.decimals
-            )?;
-        }
+        // Transfer tokens from pool to user
+        pay_out_liquidity(&ctx.accounts.swap_pool, &liquidity_accounts!(ctx.accounts), amount_a, amount_b)?;
 
-        Ok(())
-    }
+        ctx.accounts.swap_pool.record_liquidity_event()?;
 
-    pub fn set_paused(ctx: Context<AdminAction>, paused: bool) -> Result<()> {
-        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        emit!(LiquidityRemoved {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount,
+            amount_a,
+            amount_b,
+            impermanent_loss_bps,
+            reserve_a: reserve_a - amount_a,
+            reserve_b: reserve_b - amount_b,
+        });
 
-        ctx.accounts.swap_pool.is_paused = paused;
-        Ok(())
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(LiquidityResult { amount_a, amount_b, lp_amount })
     }
 
-    pub fn update_fee_rate(ctx: Context<AdminAction>, new_fee_rate: u64) -> Result<()> {
-        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
-        require!(new_fee_rate <= 1000, CustomError::FeeTooHigh); // Max fee of 10%
+    // Withdraw the caller's entire LP position, optionally closing the emptied LP account
+    pub fn remove_all_liquidity(
+        ctx: Context<RemoveLiquidity>,
+        amount_a_min: u64,
+        amount_b_min: u64,
+        close_lp_account: bool,
+    ) -> Result<LiquidityResult> {
+        // Read the full balance on-chain so the client never has to compute it
+        let lp_amount = ctx.accounts.user_lp_token.amount;
+        require!(lp_amount > 0, CustomError::InsufficientFunds);
 
-        ctx.accounts.swap_pool.fee_rate = new_fee_rate;
-        Ok(())
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
+
+        let (amount_a, amount_b) = withdraw_liquidity(
+            &mut ctx.accounts.swap_pool,
+            &liquidity_accounts!(ctx.accounts),
+            lp_amount,
+            (amount_a_min, amount_b_min),
+        )?;
+
+        // Settle all LP bookkeeping before the burn and transfers
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_sub(lp_amount).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(Clock::get()?.unix_timestamp, lp_supply);
+        }
+
+        ctx.accounts.lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)?;
+        let impermanent_loss_bps = ctx.accounts.lp_holder
+            .take_entry(lp_amount)
+            .and_then(|(held_a, held_b)| impermanent_loss_bps(held_a, held_b, amount_a, amount_b, reserve_a, reserve_b));
+        ctx.accounts.lp_holder.clear_entry();
+        ctx.accounts.swap_pool.release_lp_holder(&mut ctx.accounts.lp_holder)?;
+
+        // Burn the whole LP balance, signed by the holder
+        let burn_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                from: ctx.accounts.user_lp_token.to_account_info(),
+                authority: ctx.accounts.user_authority.to_account_info(),
+            },
+        );
+        burn(burn_ctx, lp_amount)?;
+
+        pay_out_liquidity(&ctx.accounts.swap_pool, &liquidity_accounts!(ctx.accounts), amount_a, amount_b)?;
+
+        // The LP account is now empty, so its rent can be returned to the user
+        if close_lp_account {
+            let close_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: ctx.accounts.user_lp_token.to_account_info(),
+                    destination: ctx.accounts.user_authority.to_account_info(),
+                    authority: ctx.accounts.user_authority.to_account_info(),
+                },
+            );
+            close_account(close_ctx)?;
+        }
+
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        emit!(LiquidityRemoved {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount,
+            amount_a,
+            amount_b,
+            impermanent_loss_bps,
+            reserve_a: reserve_a - amount_a,
+            reserve_b: reserve_b - amount_b,
+        });
+
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(LiquidityResult { amount_a, amount_b, lp_amount })
     }
 
-    pub fn transfer_admin(ctx: Context<TransferAdmin>, new_admin: Pubkey) -> Result<()> {
-        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+    // Withdraw lp_amount entirely in one token: the proportional share of the other token is swapped
+    // into it on the curve left after the withdrawal, paying the normal swap fee and passing the same
+    // gates as swap
+    pub fn remove_liquidity_single(
+        ctx: Context<RemoveLiquidity>,
+        lp_amount: u64,
+        to_token_a: bool,
+        min_amount_out: u64,
+    ) -> Result<LiquidityResult> {
+        require!(!ctx.accounts.swap_pool.is_paused(PAUSE_WITHDRAWALS), CustomError::PoolPaused);
+        require!(lp_amount > 0, CustomError::InvalidAmount);
+        enter_pool(&mut ctx.accounts.swap_pool)?;
 
-        ctx.accounts.swap_pool.admin = new_admin;
-        Ok(())
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.swap_pool.accumulate_prices(reserve_a, reserve_b, now);
+
+        let (amount_a, amount_b) = withdrawal_amounts(lp_amount, total_lp_supply, reserve_a, reserve_b)?;
+
+        // The conversion trades against the reserves as they stand once the share has left
+        let (kept, converted, reserve_in, reserve_out) = if to_token_a {
+            (amount_a, amount_b, reserve_b - amount_b, reserve_a - amount_a)
+        } else {
+            (amount_b, amount_a, reserve_a - amount_a, reserve_b - amount_b)
+        };
+        if converted > 0 {
+            require!(!ctx.accounts.swap_pool.is_paused(PAUSE_SWAPS), CustomError::PoolPaused);
+            require!(!ctx.accounts.swap_pool.volume_halted, CustomError::VolumeSpikeHalt);
+            apply_swap_cooldown(
+                &ctx.accounts.swap_pool,
+                ctx.accounts.swap_cooldown.as_mut(),
+                ctx.bumps.swap_cooldown,
+                ctx.accounts.user_authority.key(),
+                now,
+            )?;
+        }
+        let (swap_output, fee_amount) = if converted > 0 {
+            pool_swap_output(
+                &ctx.accounts.swap_pool,
+                reserve_in,
+                reserve_out,
+                converted,
+                !to_token_a,
+                ctx.accounts.swap_pool.fee_rate,
+                now,
+            )?
+        } else {
+            (0, 0)
+        };
+        let amount_out = kept.checked_add(swap_output).ok_or(CustomError::CalculationFailure)?;
+        require!(amount_out >= min_amount_out, CustomError::SlippageExceeded);
+
+        // Fee accounting matches swap, real-time payouts included
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        let burn_amount = (fee_amount as u128)
+            .checked_mul(swap_pool.burn_fee_bps as u128)
+            .ok_or(CustomError::InvalidAmount)?
+            .checked_div(10000)
+            .ok_or(CustomError::InvalidAmount)? as u64;
+        let retained_fee = fee_amount.checked_sub(burn_amount).ok_or(CustomError::InvalidAmount)?;
+        let protocol_fee = (retained_fee as u128)
+            .checked_mul(swap_pool.protocol_fee_share as u128)
+            .ok_or(CustomError::InvalidAmount)?
+            .checked_div(10000)
+            .ok_or(CustomError::InvalidAmount)? as u64;
+        let accrued_fee = if !swap_pool.realtime_fees && !swap_pool.auto_compound_fees { protocol_fee } else { 0 };
+        // What leaves the vault for the protocol, paid out now in real-time mode or accrued to the fee vault
+        let protocol_out = if swap_pool.realtime_fees { protocol_fee } else { accrued_fee };
+        if to_token_a {
+            swap_pool.total_fees_a = swap_pool.total_fees_a.checked_add(accrued_fee).ok_or(CustomError::InvalidAmount)?;
+        } else {
+            swap_pool.total_fees_b = swap_pool.total_fees_b.checked_add(accrued_fee).ok_or(CustomError::InvalidAmount)?;
+        }
+        if to_token_a {
+            swap_pool.lifetime_fees_a = swap_pool.lifetime_fees_a.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        } else {
+            swap_pool.lifetime_fees_b = swap_pool.lifetime_fees_b.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        }
+
+        // The converted leg is a swap against the post-withdrawal reserves, so it answers to the same limits
+        if converted > 0 {
+            let (volume_a, volume_b) = if to_token_a { (swap_output, converted) } else { (converted, swap_output) };
+            record_swap_volume(swap_pool, volume_a, volume_b, now)?;
+
+            let leaving = swap_output
+                .checked_add(burn_amount)
+                .and_then(|amount| amount.checked_add(protocol_out))
+                .ok_or(CustomError::CalculationFailure)?;
+            let post_out = reserve_out.checked_sub(leaving).ok_or(CustomError::InvalidAmount)?;
+            let post_in = reserve_in.checked_add(converted).ok_or(CustomError::InvalidAmount)?;
+            let (before, after) = if to_token_a {
+                ((reserve_out, reserve_in), (post_out, post_in))
+            } else {
+                ((reserve_in, reserve_out), (post_in, post_out))
+            };
+            check_swap_limits(swap_pool, (reserve_in, reserve_out), (converted, swap_output), before, after)?;
+            check_imbalance(
+                swap_pool,
+                before,
+                after,
+                ctx.accounts.token_a_mint.decimals,
+                ctx.accounts.token_b_mint.decimals,
+            )?;
+        }
+
+        // Settle all LP bookkeeping before the burn and transfers
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_sub(lp_amount).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(now, lp_supply);
+        }
+
+        ctx.accounts.lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)?;
+        let impermanent_loss_bps = ctx.accounts.lp_holder
+            .take_entry(lp_amount)
+            .and_then(|(held_a, held_b)| impermanent_loss_bps(held_a, held_b, amount_a, amount_b, reserve_a, reserve_b));
+
+        if ctx.accounts.user_lp_token.amount == lp_amount {
+            ctx.accounts.swap_pool.release_lp_holder(&mut ctx.accounts.lp_holder)?;
+        }
+
+        let burn_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                from: ctx.accounts.user_lp_token.to_account_info(),
+                authority: ctx.accounts.user_authority.to_account_info(),
+            },
+        );
+        burn(burn_ctx, lp_amount)?;
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            ctx.accounts.swap_pool.token_a_mint.as_ref(),
+            ctx.accounts.swap_pool.token_b_mint.as_ref(),
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let (vault, user_token, mint) = if to_token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.user_token_a, &ctx.accounts.token_a_mint)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.user_token_b, &ctx.accounts.token_b_mint)
+        };
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: vault.to_account_info(),
+                to: user_token.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_ctx, amount_out, mint.decimals)?;
+
+        // Burned fee share comes out of the same vault, exactly as in swap
+        if burn_amount > 0 {
+            let burn_fee_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: mint.to_account_info(),
+                    from: vault.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                signer
+            );
+            burn(burn_fee_ctx, burn_amount)?;
+        }
+
+        // And the protocol share goes to the fee destination or the fee vault, also as in swap
+        if protocol_out > 0 {
+            let (fee_vault, fee_destination) = if to_token_a {
+                (&ctx.accounts.fee_vault_a, &ctx.accounts.fee_destination_a)
+            } else {
+                (&ctx.accounts.fee_vault_b, &ctx.accounts.fee_destination_b)
+            };
+            let fee_account = if ctx.accounts.swap_pool.realtime_fees {
+                fee_destination.as_ref().ok_or(CustomError::MissingFeeDestination)?.to_account_info()
+            } else {
+                fee_vault.to_account_info()
+            };
+            let transfer_fee_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault.to_account_info(),
+                    to: fee_account,
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    mint: mint.to_account_info(),
+                },
+                signer
+            );
+            transfer_checked(transfer_fee_ctx, protocol_out, mint.decimals)?;
+        }
+
+        let (paid_a, paid_b) = if to_token_a { (amount_out, 0) } else { (0, amount_out) };
+        // The burned fee and the protocol share leave the vault along with the payout
+        let fees_out = burn_amount.checked_add(protocol_out).ok_or(CustomError::CalculationFailure)?;
+        let (fees_out_a, fees_out_b) = if to_token_a { (fees_out, 0) } else { (0, fees_out) };
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+        ctx.accounts.swap_pool.in_progress = false;
+
+        emit!(LiquidityRemoved {
+            swap_pool: ctx.accounts.swap_pool.key(),
+            owner: ctx.accounts.user_authority.key(),
+            lp_amount,
+            amount_a: paid_a,
+            amount_b: paid_b,
+            impermanent_loss_bps,
+            reserve_a: reserve_a - paid_a - fees_out_a,
+            reserve_b: reserve_b - paid_b - fees_out_b,
+        });
+
+        Ok(LiquidityResult { amount_a: paid_a, amount_b: paid_b, lp_amount })
     }
 
-    // Get token prices
-    pub fn get_token_a_price(ctx: Context<GetPrice>) -> Result<u64> {
-        let token_a_amount = ctx.accounts.token_a_vault.amount;
-        let token_b_amount = ctx.accounts.token_b_vault.amount;
+    // Returns the amounts actually moved; Anchor writes them to the transaction's return data
+    // Swap token A for token B, or B for A when is_a_to_b is false
+    // With unwrap_sol set, a wrapped-SOL output account is closed afterwards so the user receives lamports
+    pub fn swap(
+        ctx: Context<Swap>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: bool,
+        unwrap_sol: bool,
+    ) -> Result<SwapResult> {
+        let amount_out = process_swap(ctx, amount_in, min_amount_out, is_a_to_b, unwrap_sol)?;
+        Ok(SwapResult { amount_in, amount_out })
+    }
 
-        require!(token_a_amount > 0, CustomError::InsufficientLiquidity);
+    // Exact-output swap: pull only the input needed for amount_out, failing if that exceeds max_amount_in
+    pub fn swap_exact_out(
+        ctx: Context<Swap>,
+        amount_out: u64,
+        max_amount_in: u64,
+        is_a_to_b: bool,
+    ) -> Result<SwapResult> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        let (reserve_in, reserve_out) = if is_a_to_b {
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+        } else {
+            (ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+        };
+        // Quote with the fee the swap itself will charge, exemption included
+        let fee_rate = if swap_pool.fee_exemptions_enabled && ctx.accounts.fee_exemption.is_some() {
+            0
+        } else {
+            swap_pool.fee_rate
+        };
 
-        // Price of toeken A in terms of token B (scaled by 10^6 for precision)
-        let price = (token_b_amount as u128)
-            .checked_mul(1_000_000)
-            .unwrap()
-            .checked_div(token_a_amount as u128)
-            .unwrap() as u64;
+        let amount_in = pool_swap_input(swap_pool, reserve_in, reserve_out, amount_out, fee_rate, Clock::get()?.unix_timestamp)?;
+        require!(amount_in <= max_amount_in, CustomError::SlippageExceeded);
 
-        Ok(price)
+        // amount_out doubles as the minimum, so the usual swap path enforces the exact output
+        let amount_out = process_swap(ctx, amount_in, amount_out, is_a_to_b, false)?;
+        Ok(SwapResult { amount_in, amount_out })
+    }
+
+    // Same as swap, but returns the output amount so CPI callers can read it with get_return_data
+    // Anchor serializes the returned u64 into the transaction's return data
+    pub fn swap_cpi(
+        ctx: Context<Swap>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: bool,
+    ) -> Result<u64> {
+        process_swap(ctx, amount_in, min_amount_out, is_a_to_b, false)
     }
-    pub fn get_token_b_price(ctx: Context<GetPrice>) -> Result<u64> {
-        let token_a_amount = ctx.accounts.token_a_vault.amount;
-        let token_b_amount = ctx.accounts.token_b_vault.amount;
 
-        require!(token_b_amount > 0, CustomError::InsufficientLiquidity);
+    // Swap through two pools atomically: the first hop pays into an intermediate account that the
+    // second hop spends from. remaining_accounts holds each hop's Swap accounts in order, and only the
+    // end-to-end output is held to min_amount_out. is_a_to_b gives each hop's direction within its own pool
+    pub fn swap_route<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: [bool; 2],
+    ) -> Result<SwapResult> {
+        let mut remaining = ctx.remaining_accounts;
+        let mut amount = amount_in;
+        let mut intermediate: Option<Pubkey> = None;
+
+        for hop_is_a_to_b in is_a_to_b {
+            let mut bumps = SwapBumps::default();
+            let mut hop = Swap::try_accounts(ctx.program_id, &mut remaining, &[], &mut bumps, &mut BTreeSet::new())?;
+            require!(hop.user_authority.key() == ctx.accounts.user_authority.key(), CustomError::Unauthorized);
+
+            let (hop_source, hop_destination) = if hop_is_a_to_b {
+                (hop.user_token_a.key(), hop.user_token_b.key())
+            } else {
+                (hop.user_token_b.key(), hop.user_token_a.key())
+            };
+            // The second hop must spend exactly what the first one paid out
+            if let Some(intermediate) = intermediate {
+                require!(hop_source == intermediate, CustomError::InvalidToken);
+            }
+            intermediate = Some(hop_destination);
 
-        // Price of token B in terms of token A (scaled by 10^6 for precision)
-        let price = (token_a_amount as u128)
-            .checked_mul(1_000_000)
-            .unwrap()
-            .checked_div(token_b_amount as u128)
-            .unwrap() as u64;
+            amount = process_swap(Context::new(ctx.program_id, &mut hop, &[], bumps), amount, 0, hop_is_a_to_b, false)?;
+            hop.exit(ctx.program_id)?;
+        }
+        require!(remaining.is_empty(), CustomError::InvalidSwapPool);
+        require!(amount >= min_amount_out, CustomError::SlippageExceeded);
 
-        Ok(price)
+        Ok(SwapResult { amount_in, amount_out: amount })
     }
 
-    // Get total liquidity of both tokens and current LP supply
-    pub fn get_pool_stats(ctx: Context<GetPoolStats>) -> Result<(u64, u64, u64)> {
-        let token_a_amount = ctx.accounts.token_a_vault.amount;
-        let token_b_amount = ctx.accounts.token_b_vault.amount;
-        let lp_supply = ctx.accounts.lp_mint.supply;
+    // Admin-only internal swap for protocol-owned arbitrage
+    // Output goes to a protocol token account and no swap fee is charged
+    pub fn swap_protocol(
+        ctx: Context<SwapProtocol>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(!ctx.accounts.swap_pool.is_paused(PAUSE_SWAPS), CustomError::PoolPaused);
+        require!(amount_in > 0, CustomError::InvalidAmount);
+        enter_pool(&mut ctx.accounts.swap_pool)?;
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        let (admin_source, protocol_destination, input_vault, output_vault, input_mint, output_mint) = if is_a_to_b {
+            (&ctx.accounts.admin_token_a, &ctx.accounts.protocol_token_b, &ctx.accounts.token_a_vault, &ctx.accounts.token_b_vault, &ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.admin_token_b, &ctx.accounts.protocol_token_a, &ctx.accounts.token_b_vault, &ctx.accounts.token_a_vault, &ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let reserve_in = input_vault.amount;
+        let reserve_out = output_vault.amount;
+
+        // Priced on what the vault was credited, as in swap
+        let transfer_in_cpi = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: admin_source.to_account_info(),
+                to: input_vault.to_account_info(),
+                authority: ctx.accounts.admin.to_account_info(),
+                mint: input_mint.to_account_info(),
+            }
+        );
+        let amount_in = transfer_in(transfer_in_cpi, amount_in, input_mint.decimals)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        swap_pool.accumulate_prices(ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount, now);
+
+        // The pool's own curve with no fee, so the protocol keeps the whole output
+        let (amount_out, _) = pool_swap_output(swap_pool, reserve_in, reserve_out, amount_in, is_a_to_b, 0, now)?;
+        require!(amount_out > 0, CustomError::InvalidAmount);
+
+        let (volume_a, volume_b) = if is_a_to_b { (amount_in, amount_out) } else { (amount_out, amount_in) };
+        record_swap_volume(swap_pool, volume_a, volume_b, now)?;
+
+        let post_reserve_in = reserve_in.checked_add(amount_in).ok_or(CustomError::InvalidAmount)?;
+        let post_reserve_out = reserve_out.checked_sub(amount_out).ok_or(CustomError::InvalidAmount)?;
+        let (reserve_a, reserve_b) = if is_a_to_b {
+            (post_reserve_in, post_reserve_out)
+        } else {
+            (post_reserve_out, post_reserve_in)
+        };
+        check_swap_limits(
+            swap_pool,
+            (reserve_in, reserve_out),
+            (amount_in, amount_out),
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount),
+            (reserve_a, reserve_b),
+        )?;
+
+        require!(amount_out >= min_amount_out, CustomError::SlippageExceeded);
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+            &[swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let transfer_out_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: output_vault.to_account_info(),
+                to: protocol_destination.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: output_mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_out_cpi, amount_out, output_mint.decimals)?;
 
-        Ok((token_a_amount, token_b_amount, lp_supply))
+        swap_pool.in_progress = false;
+        Ok(())
     }
 
-    // Calculate swap result without executing it
-    pub fn calculate_swap_result(ctx: Context<GetPrice>, amount_in: u64, is_a_to_b: bool) -> Result<(u64)> {
+    // Send amount_out to the receiver first, then hand control to the caller's callback program
+    // remaining_accounts[0] is the callback program, the rest are passed to it as its accounts
+    // By the time the callback returns the vaults must satisfy the constant product plus the fee
+    pub fn flash_swap<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashSwap<'info>>,
+        amount_out: u64,
+        is_a_to_b: bool,
+        callback_data: Vec<u8>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.swap_pool.is_paused(PAUSE_SWAPS), CustomError::PoolPaused);
+        require!(amount_out > 0, CustomError::InvalidAmount);
+        // The repayment check below is the unweighted invariant, so auctions can't be flash-swapped
+        require!(
+            lbp_weight_a(&ctx.accounts.swap_pool, Clock::get()?.unix_timestamp).is_none(),
+            CustomError::InvalidSwapPool
+        );
+        // The callback runs while the vault is drained, so it must not reach the pool's other instructions
+        enter_pool(&mut ctx.accounts.swap_pool)?;
+
+        let (callback_program, callback_accounts) = ctx
+            .remaining_accounts
+            .split_first()
+            .ok_or(CustomError::InvalidAmount)?;
+        require!(callback_program.executable, CustomError::InvalidAmount);
+
+        let (reserve_in_before, reserve_out_before) = if is_a_to_b {
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+        } else {
+            (ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+        };
+        require!(amount_out < reserve_out_before, CustomError::InsufficientLiquidity);
+
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        ctx.accounts.swap_pool.accumulate_prices(reserve_a, reserve_b, Clock::get()?.unix_timestamp);
+
+        // Fee is charged on the borrowed output, rounded up in the pool's favour
+        let fee_amount = (amount_out as u128)
+            .checked_mul(ctx.accounts.swap_pool.fee_rate as u128)
+            .and_then(|value| value.checked_add(9999))
+            .ok_or(CustomError::CalculationFailure)?
+            / 10000;
+        let fee_amount = fee_amount as u64;
+
+        // Same accounting as swap, done up front since the whole instruction reverts if unpaid
+        // In auto-compound mode the fee simply stays in the reserves
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        let protocol_fee = (fee_amount as u128)
+            .checked_mul(swap_pool.protocol_fee_share as u128)
+            .ok_or(CustomError::InvalidAmount)?
+            / 10000;
+        let protocol_fee = protocol_fee as u64;
+        if !swap_pool.realtime_fees && !swap_pool.auto_compound_fees {
+            if is_a_to_b {
+                swap_pool.total_fees_b = swap_pool.total_fees_b.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;
+            } else {
+                swap_pool.total_fees_a = swap_pool.total_fees_a.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;
+            }
+        }
+        if is_a_to_b {
+            swap_pool.lifetime_fees_b = swap_pool.lifetime_fees_b.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        } else {
+            swap_pool.lifetime_fees_a = swap_pool.lifetime_fees_a.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        }
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            ctx.accounts.swap_pool.token_a_mint.as_ref(),
+            ctx.accounts.swap_pool.token_b_mint.as_ref(),
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let (output_vault, receiver, output_mint) = if is_a_to_b {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.receiver_token_b, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.receiver_token_a, &ctx.accounts.token_a_mint)
+        };
+        let transfer_out_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: output_vault.to_account_info(),
+                to: receiver.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: output_mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_out_cpi, amount_out, output_mint.decimals)?;
+
+        // Signer and writable flags are forwarded as-is, the pool authority never signs here
+        let callback_ix = anchor_lang::solana_program::instruction::Instruction {
+            program_id: callback_program.key(),
+            accounts: callback_accounts
+                .iter()
+                .map(|account| {
+                    if account.is_writable {
+                        AccountMeta::new(account.key(), account.is_signer)
+                    } else {
+                        AccountMeta::new_readonly(account.key(), account.is_signer)
+                    }
+                })
+                .collect(),
+            data: callback_data,
+        };
+        anchor_lang::solana_program::program::invoke(&callback_ix, ctx.remaining_accounts)?;
+
+        ctx.accounts.token_a_vault.reload()?;
+        ctx.accounts.token_b_vault.reload()?;
+        let (reserve_in_after, reserve_out_after) = if is_a_to_b {
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+        } else {
+            (ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+        };
+
+        // The fee must sit on top of the invariant, so it is excluded from the post-callback product
+        let product_before = (reserve_in_before as u128)
+            .checked_mul(reserve_out_before as u128)
+            .ok_or(CustomError::CalculationFailure)?;
+        let product_after = (reserve_in_after as u128)
+            .checked_mul(reserve_out_after.saturating_sub(fee_amount) as u128)
+            .ok_or(CustomError::CalculationFailure)?;
+        require!(product_after >= product_before, CustomError::FlashSwapNotRepaid);
+
+        // With the fee repaid, its protocol share goes to the fee destination in real-time mode and
+        // otherwise to the fee vault, as in swap
         let swap_pool = &ctx.accounts.swap_pool;
-        
-        let source_amount = if is_a_to_b {
+        if (swap_pool.realtime_fees || !swap_pool.auto_compound_fees) && protocol_fee > 0 {
+            let (vault, fee_vault, fee_destination, mint) = if is_a_to_b {
+                (&ctx.accounts.token_b_vault, &ctx.accounts.fee_vault_b, &ctx.accounts.fee_destination_b, &ctx.accounts.token_b_mint)
+            } else {
+                (&ctx.accounts.token_a_vault, &ctx.accounts.fee_vault_a, &ctx.accounts.fee_destination_a, &ctx.accounts.token_a_mint)
+            };
+            let fee_account = if swap_pool.realtime_fees {
+                fee_destination.as_ref().ok_or(CustomError::MissingFeeDestination)?.to_account_info()
+            } else {
+                fee_vault.to_account_info()
+            };
+            let transfer_fee_cpi = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault.to_account_info(),
+                    to: fee_account,
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    mint: mint.to_account_info(),
+                },
+                signer
+            );
+            transfer_checked(transfer_fee_cpi, protocol_fee, mint.decimals)?;
+        }
+
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(())
+    }
+
+    // Lend amount of token A (or B) to the receiver, then hand control to the callback program as in
+    // flash_swap; by the time it returns the vault must hold the loan plus the fee on top
+    pub fn flash_loan<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashSwap<'info>>,
+        amount: u64,
+        is_token_a: bool,
+        callback_data: Vec<u8>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.swap_pool.is_paused(PAUSE_SWAPS), CustomError::PoolPaused);
+        require!(amount > 0, CustomError::InvalidAmount);
+        enter_pool(&mut ctx.accounts.swap_pool)?;
+
+        let (callback_program, callback_accounts) = ctx
+            .remaining_accounts
+            .split_first()
+            .ok_or(CustomError::InvalidAmount)?;
+        require!(callback_program.executable, CustomError::InvalidAmount);
+
+        let balance_before = if is_token_a {
             ctx.accounts.token_a_vault.amount
         } else {
             ctx.accounts.token_b_vault.amount
         };
+        require!(amount < balance_before, CustomError::InsufficientLiquidity);
 
-        let destination_amount = if is_a_to_b {
-            ctx.accounts.token_b_vault.amount
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        ctx.accounts.swap_pool.accumulate_prices(reserve_a, reserve_b, Clock::get()?.unix_timestamp);
+
+        // Same fee rate as a swap, rounded up in the pool's favour
+        let fee_amount = (amount as u128)
+            .checked_mul(ctx.accounts.swap_pool.fee_rate as u128)
+            .and_then(|value| value.checked_add(9999))
+            .ok_or(CustomError::CalculationFailure)?
+            / 10000;
+        let fee_amount = fee_amount as u64;
+
+        // Accrued up front like flash_swap, since the whole instruction reverts if unpaid
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        let protocol_fee = (fee_amount as u128)
+            .checked_mul(swap_pool.protocol_fee_share as u128)
+            .ok_or(CustomError::InvalidAmount)?
+            / 10000;
+        let protocol_fee = protocol_fee as u64;
+        if !swap_pool.realtime_fees && !swap_pool.auto_compound_fees {
+            if is_token_a {
+                swap_pool.total_fees_a = swap_pool.total_fees_a.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;
+            } else {
+                swap_pool.total_fees_b = swap_pool.total_fees_b.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;
+            }
+        }
+        if is_token_a {
+            swap_pool.lifetime_fees_a = swap_pool.lifetime_fees_a.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
         } else {
-            ctx.accounts.token_a_vault.amount
+            swap_pool.lifetime_fees_b = swap_pool.lifetime_fees_b.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+        }
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            ctx.accounts.swap_pool.token_a_mint.as_ref(),
+            ctx.accounts.swap_pool.token_b_mint.as_ref(),
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let (vault, receiver, mint) = if is_token_a {
+            (&ctx.accounts.token_a_vault, &ctx.accounts.receiver_token_a, &ctx.accounts.token_a_mint)
+        } else {
+            (&ctx.accounts.token_b_vault, &ctx.accounts.receiver_token_b, &ctx.accounts.token_b_mint)
+        };
+        let transfer_out_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: vault.to_account_info(),
+                to: receiver.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_out_cpi, amount, mint.decimals)?;
+
+        // Signer and writable flags are forwarded as-is, the pool authority never signs here
+        let callback_ix = anchor_lang::solana_program::instruction::Instruction {
+            program_id: callback_program.key(),
+            accounts: callback_accounts
+                .iter()
+                .map(|account| {
+                    if account.is_writable {
+                        AccountMeta::new(account.key(), account.is_signer)
+                    } else {
+                        AccountMeta::new_readonly(account.key(), account.is_signer)
+                    }
+                })
+                .collect(),
+            data: callback_data,
         };
+        anchor_lang::solana_program::program::invoke(&callback_ix, ctx.remaining_accounts)?;
+
+        let balance_after = if is_token_a {
+            ctx.accounts.token_a_vault.reload()?;
+            ctx.accounts.token_a_vault.amount
+        } else {
+            ctx.accounts.token_b_vault.reload()?;
+            ctx.accounts.token_b_vault.amount
+        };
+        let required = balance_before.checked_add(fee_amount).ok_or(CustomError::CalculationFailure)?;
+        require!(balance_after >= required, CustomError::FlashLoanNotRepaid);
+
+        // With the fee repaid, its protocol share goes to the fee destination in real-time mode and
+        // otherwise to the fee vault, as in swap
+        let swap_pool = &ctx.accounts.swap_pool;
+        if (swap_pool.realtime_fees || !swap_pool.auto_compound_fees) && protocol_fee > 0 {
+            let (vault, fee_vault, fee_destination, mint) = if is_token_a {
+                (&ctx.accounts.token_a_vault, &ctx.accounts.fee_vault_a, &ctx.accounts.fee_destination_a, &ctx.accounts.token_a_mint)
+            } else {
+                (&ctx.accounts.token_b_vault, &ctx.accounts.fee_vault_b, &ctx.accounts.fee_destination_b, &ctx.accounts.token_b_mint)
+            };
+            let fee_account = if swap_pool.realtime_fees {
+                fee_destination.as_ref().ok_or(CustomError::MissingFeeDestination)?.to_account_info()
+            } else {
+                fee_vault.to_account_info()
+            };
+            let transfer_fee_cpi = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: vault.to_account_info(),
+                    to: fee_account,
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    mint: mint.to_account_info(),
+                },
+                signer
+            );
+            transfer_checked(transfer_fee_cpi, protocol_fee, mint.decimals)?;
+        }
+
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(())
+    }
+
+    // Collect amount_a / amount_b of the accrued fees; None or 0 collects everything accrued in that token
+    pub fn collect_fees(ctx: Context<CollectFees>, amount_a: Option<u64>, amount_b: Option<u64>) -> Result<()> {
+        require!(ctx.accounts.fee_collector.key() == ctx.accounts.swap_pool.fee_authority, CustomError::Unauthorized);
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        let requested = |amount: Option<u64>, accrued: u64| -> Result<u64> {
+            match amount {
+                Some(amount) if amount > 0 => {
+                    require!(amount <= accrued, CustomError::InsufficientFunds);
+                    Ok(amount)
+                }
+                _ => Ok(accrued),
+            }
+        };
+        let fee_amount_a = requested(amount_a, swap_pool.total_fees_a)?;
+        let fee_amount_b = requested(amount_b, swap_pool.total_fees_b)?;
+
+        // Only what is collected leaves the fee counters
+        swap_pool.total_fees_a -= fee_amount_a;
+        swap_pool.total_fees_b -= fee_amount_b;
+
+        if fee_amount_a > 0 {
+            let seeds = &[
+                b"pool_authority".as_ref(),
+                swap_pool.token_a_mint.as_ref(),
+                swap_pool.token_b_mint.as_ref(),
+                swap_pool.fee_tier_seed(),
+                &[swap_pool.authority_bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let transfer_a_cpi = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.fee_vault_a.to_account_info(),
+                    to: ctx.accounts.fee_collector_token_a.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    mint: ctx.accounts.token_a_mint.to_account_info(),
+                },
+                signer
+            );
+            transfer_checked(
+                transfer_a_cpi,
+                fee_amount_a,
+                ctx.accounts.token_a_mint.decimals
+            )?;
+        }
+
+        if fee_amount_b > 0 {
+            let seeds = &[
+                b"pool_authority".as_ref(),
+                swap_pool.token_a_mint.as_ref(),
+                swap_pool.token_b_mint.as_ref(),
+                swap_pool.fee_tier_seed(),
+                &[swap_pool.authority_bump],
+            ];
+            let signer = &[&seeds[..]];
+
+            let transfer_b_cpi = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.fee_vault_b.to_account_info(),
+                    to: ctx.accounts.fee_collector_token_b.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                    mint: ctx.accounts.token_b_mint.to_account_info(),
+                },
+                signer
+            );
+            transfer_checked(
+                transfer_b_cpi,
+                fee_amount_b,
+                ctx.accounts.token_b_mint.decimals
+            )?;
+        }
+
+        emit!(FeesCollected {
+            swap_pool: swap_pool.key(),
+            collector: ctx.accounts.fee_collector.key(),
+            amount_a: fee_amount_a,
+            amount_b: fee_amount_b,
+            reserve_a: ctx.accounts.token_a_vault.amount,
+            reserve_b: ctx.accounts.token_b_vault.amount,
+        });
+
+        Ok(())
+    }
+
+    // Convert accrued fees into LP owned by the fee collector instead of withdrawing them
+    // The fee tokens move from the fee vaults into the reserves and LP is minted for their value
+    pub fn collect_fees_as_lp(ctx: Context<CollectFeesAsLp>) -> Result<()> {
+        require!(ctx.accounts.fee_collector.key() == ctx.accounts.swap_pool.fee_authority, CustomError::Unauthorized);
+
+        let fee_amount_a = ctx.accounts.swap_pool.total_fees_a;
+        let fee_amount_b = ctx.accounts.swap_pool.total_fees_b;
+        require!(fee_amount_a > 0 || fee_amount_b > 0, CustomError::InvalidAmount);
+        enter_pool(&mut ctx.accounts.swap_pool)?;
+
+        // Reserves owned by LPs before the fees are folded in
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
+        require!(reserve_a > 0 && reserve_b > 0 && total_lp_supply > 0, CustomError::InsufficientLiquidity);
+
+        let now = Clock::get()?.unix_timestamp;
+        ctx.accounts.swap_pool.accumulate_prices(reserve_a, reserve_b, now);
+
+        // Balanced part: the largest deposit at the current ratio
+        let mul_div = |a: u64, b: u64, c: u64| -> Result<u128> {
+            Ok((a as u128)
+                .checked_mul(b as u128)
+                .ok_or(CustomError::CalculationFailure)?
+                / c as u128)
+        };
+        let lp_from_a = mul_div(fee_amount_a, total_lp_supply, reserve_a)?;
+        let lp_from_b = mul_div(fee_amount_b, total_lp_supply, reserve_b)?;
+        let (balanced_lp, excess, excess_reserve) = if lp_from_a >= lp_from_b {
+            let used_a = mul_div(fee_amount_b, reserve_a, reserve_b)?;
+            let excess = (fee_amount_a as u128).checked_sub(used_a).ok_or(CustomError::CalculationFailure)?;
+            (lp_from_b, excess, reserve_a as u128 + used_a)
+        } else {
+            let used_b = mul_div(fee_amount_a, reserve_b, reserve_a)?;
+            let excess = (fee_amount_b as u128).checked_sub(used_b).ok_or(CustomError::CalculationFailure)?;
+            (lp_from_a, excess, reserve_b as u128 + used_b)
+        };
+
+        // Imbalanced remainder is zapped in as if half were swapped to the other side:
+        // single-sided LP = supply * (sqrt((reserve + excess) * reserve) - reserve) / reserve,
+        // with the root rounded down in the pool's favour
+        let supply_after_balanced = (total_lp_supply as u128)
+            .checked_add(balanced_lp)
+            .ok_or(CustomError::CalculationFailure)?;
+        let grown_reserve = isqrt(
+            excess_reserve
+                .checked_add(excess)
+                .and_then(|value| value.checked_mul(excess_reserve))
+                .ok_or(CustomError::CalculationFailure)?,
+        );
+        let excess_lp = supply_after_balanced
+            .checked_mul(grown_reserve - excess_reserve)
+            .ok_or(CustomError::CalculationFailure)?
+            / excess_reserve;
+
+        let lp_to_mint = balanced_lp
+            .checked_add(excess_lp)
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(CustomError::CalculationFailure)?;
+        require!(lp_to_mint > 0, CustomError::InvalidAmount);
+
+        // Same LP bookkeeping as add_liquidity, settled before the mint
+        if let Some(lp_history) = ctx.accounts.lp_history.as_mut() {
+            let lp_supply = total_lp_supply.checked_add(lp_to_mint).ok_or(CustomError::CalculationFailure)?;
+            lp_history.record(now, lp_supply);
+        }
+
+        let lp_holder = &mut ctx.accounts.lp_holder;
+        lp_holder.swap_pool = ctx.accounts.swap_pool.key();
+        lp_holder.owner = ctx.accounts.fee_collector.key();
+        lp_holder.bump = ctx.bumps.lp_holder;
+        lp_holder.checkpoint_fees(&ctx.accounts.swap_pool, ctx.accounts.fee_collector_lp_token.amount)?;
+        lp_holder.record_entry(fee_amount_a, fee_amount_b, lp_to_mint)?;
+        ctx.accounts.swap_pool.register_lp_holder(lp_holder)?;
+        ctx.accounts.swap_pool.record_liquidity_event()?;
+
+        // Reset fee counters, the tokens now back the minted LP
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.total_fees_a = 0;
+        swap_pool.total_fees_b = 0;
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+            &[swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let deposits = [
+            (&ctx.accounts.fee_vault_a, &ctx.accounts.token_a_vault, &ctx.accounts.token_a_mint, fee_amount_a),
+            (&ctx.accounts.fee_vault_b, &ctx.accounts.token_b_vault, &ctx.accounts.token_b_mint, fee_amount_b),
+        ];
+        for (fee_vault, vault, mint, amount) in deposits {
+            if amount > 0 {
+                let transfer_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: fee_vault.to_account_info(),
+                        to: vault.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                        mint: mint.to_account_info(),
+                    },
+                    signer
+                );
+                transfer_checked(transfer_ctx, amount, mint.decimals)?;
+            }
+        }
+
+        let mint_lp_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            MintTo {
+                mint: ctx.accounts.lp_mint.to_account_info(),
+                to: ctx.accounts.fee_collector_lp_token.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer
+        );
+        mint_to(mint_lp_ctx, lp_to_mint)?;
+
+        ctx.accounts.swap_pool.in_progress = false;
+        Ok(())
+    }
+
+    // Freeze the operations in the PAUSE_* mask and unfreeze the rest; 0 resumes everything
+    pub fn set_paused(ctx: Context<AdminAction>, paused_ops: u8) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(paused_ops & !PAUSE_ALL == 0, CustomError::InvalidAmount);
+
+        ctx.accounts.swap_pool.paused_ops = paused_ops;
+        Ok(())
+    }
+
+    // Emergency shortcut for set_paused(PAUSE_ALL)
+    pub fn pause_all(ctx: Context<AdminAction>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.paused_ops = PAUSE_ALL;
+        Ok(())
+    }
+
+    pub fn update_fee_rate(ctx: Context<AdminAction>, new_fee_rate: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_fee_rate <= 1000, CustomError::FeeTooHigh); // Max fee of 10%
+        // A tier pool's rate is fixed by the tier in its authority seeds, only legacy pools may change it
+        let fee_tier = ctx.accounts.swap_pool.fee_tier;
+        require!(
+            fee_tier == LEGACY_FEE_TIER || FEE_TIERS.get(fee_tier as usize) == Some(&new_fee_rate),
+            CustomError::InvalidFeeTier
+        );
+
+        ctx.accounts.swap_pool.fee_rate = new_fee_rate;
+        Ok(())
+    }
+
+    pub fn update_protocol_fee_share(ctx: Context<AdminAction>, new_protocol_fee_share: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_protocol_fee_share <= 10000, CustomError::FeeTooHigh);
+
+        ctx.accounts.swap_pool.protocol_fee_share = new_protocol_fee_share;
+        Ok(())
+    }
+
+    pub fn update_max_accrued_fees(ctx: Context<AdminAction>, new_max_accrued_fees: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.max_accrued_fees = new_max_accrued_fees;
+        Ok(())
+    }
+
+    pub fn update_price_band(ctx: Context<AdminAction>, min_price: u64, max_price: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(min_price == 0 || max_price == 0 || min_price <= max_price, CustomError::InvalidAmount);
+
+        ctx.accounts.swap_pool.min_price = min_price;
+        ctx.accounts.swap_pool.max_price = max_price;
+        Ok(())
+    }
+
+    pub fn update_balance_incentive_bps(ctx: Context<AdminAction>, new_balance_incentive_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_balance_incentive_bps <= 1000, CustomError::FeeTooHigh); // Max adjustment of 10%
+
+        ctx.accounts.swap_pool.balance_incentive_bps = new_balance_incentive_bps;
+        Ok(())
+    }
+
+    pub fn update_max_imbalance_bps(ctx: Context<AdminAction>, new_max_imbalance_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_max_imbalance_bps <= 10000, CustomError::InvalidAmount);
+
+        ctx.accounts.swap_pool.max_imbalance_bps = new_max_imbalance_bps;
+        Ok(())
+    }
+
+    pub fn update_max_holders(ctx: Context<AdminAction>, new_max_holders: u32) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.max_holders = new_max_holders;
+        Ok(())
+    }
+
+    pub fn update_max_output_fraction_bps(ctx: Context<AdminAction>, new_max_output_fraction_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(
+            new_max_output_fraction_bps > 0 && new_max_output_fraction_bps <= 10000,
+            CustomError::InvalidAmount
+        );
+
+        ctx.accounts.swap_pool.max_output_fraction_bps = new_max_output_fraction_bps;
+        Ok(())
+    }
+
+    // Turn the pool into a liquidity bootstrapping auction, weights are token A's share in bps
+    // Passing start_ts == end_ts switches back to the standard 50/50 curve
+    pub fn configure_lbp(
+        ctx: Context<AdminAction>,
+        start_weight: u64,
+        end_weight: u64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(end_ts >= start_ts, CustomError::InvalidAmount);
+        if end_ts > start_ts {
+            require!(
+                (1..10000).contains(&start_weight) && (1..10000).contains(&end_weight),
+                CustomError::InvalidAmount
+            );
+        }
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.lbp_start_weight = start_weight;
+        swap_pool.lbp_end_weight = end_weight;
+        swap_pool.lbp_start_ts = start_ts;
+        swap_pool.lbp_end_ts = end_ts;
+        Ok(())
+    }
+
+    // Configure the volume spike breaker; bucket_secs = 0 disables it
+    // Changing the configuration starts a fresh volume history
+    pub fn update_volume_breaker(ctx: Context<AdminAction>, bucket_secs: i64, multiple: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(bucket_secs >= 0, CustomError::InvalidAmount);
+        require!(bucket_secs == 0 || multiple > 1, CustomError::InvalidAmount);
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.spike_bucket_secs = bucket_secs;
+        swap_pool.spike_multiple = multiple;
+        swap_pool.volume_buckets = [0; 8];
+        swap_pool.volume_bucket_start = 0;
+        swap_pool.volume_bucket_head = 0;
+        Ok(())
+    }
+
+    // Resume swaps after a volume spike has been reviewed
+    pub fn clear_volume_halt(ctx: Context<AdminAction>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.volume_halted = false;
+        Ok(())
+    }
+
+    // Fees already sitting in the counters stay collectable, only new swaps compound
+    pub fn set_auto_compound_fees(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(!enabled || !ctx.accounts.swap_pool.realtime_fees, CustomError::ConflictingFeeMode);
+
+        ctx.accounts.swap_pool.auto_compound_fees = enabled;
+        Ok(())
+    }
+
+    pub fn update_min_initial_lp(ctx: Context<AdminAction>, new_min_initial_lp: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.min_initial_lp = new_min_initial_lp;
+        Ok(())
+    }
+
+    pub fn set_fee_exemptions_enabled(ctx: Context<AdminAction>, enabled: bool) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.fee_exemptions_enabled = enabled;
+        Ok(())
+    }
+
+    // Let `user` swap without fees on this pool
+    pub fn add_fee_exemption(ctx: Context<AddFeeExemption>, user: Pubkey) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        let fee_exemption = &mut ctx.accounts.fee_exemption;
+        fee_exemption.swap_pool = ctx.accounts.swap_pool.key();
+        fee_exemption.user = user;
+        fee_exemption.bump = ctx.bumps.fee_exemption;
+        Ok(())
+    }
+
+    // Closing the account is what revokes the exemption
+    pub fn remove_fee_exemption(ctx: Context<RemoveFeeExemption>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        Ok(())
+    }
+
+    // Snapshot the current reserves so monitoring can read changes via get_reserve_delta
+    pub fn set_checkpoint(ctx: Context<SetCheckpoint>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.reserve_checkpoint_a = ctx.accounts.token_a_vault.amount;
+        swap_pool.reserve_checkpoint_b = ctx.accounts.token_b_vault.amount;
+        swap_pool.checkpoint_ts = Clock::get()?.unix_timestamp;
+        Ok(())
+    }
+
+    // Minimum seconds between two swaps by the same user; 0 turns the cooldown off
+    pub fn update_swap_cooldown_secs(ctx: Context<AdminAction>, new_swap_cooldown_secs: u32) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.swap_cooldown_secs = new_swap_cooldown_secs;
+        Ok(())
+    }
+
+    pub fn update_max_price_impact_bps(ctx: Context<AdminAction>, new_max_price_impact_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_max_price_impact_bps <= 10000, CustomError::InvalidAmount);
+
+        ctx.accounts.swap_pool.max_price_impact_bps = new_max_price_impact_bps;
+        Ok(())
+    }
+
+    pub fn update_protocol_max_slippage_bps(ctx: Context<AdminAction>, new_max_slippage_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_max_slippage_bps > 0 && new_max_slippage_bps <= 10000, CustomError::InvalidAmount);
+
+        ctx.accounts.swap_pool.protocol_max_slippage_bps = new_max_slippage_bps;
+        Ok(())
+    }
+
+    pub fn set_realtime_fees(
+        ctx: Context<AdminAction>,
+        enabled: bool,
+        fee_destination_a: Pubkey,
+        fee_destination_b: Pubkey,
+    ) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(!enabled || !ctx.accounts.swap_pool.auto_compound_fees, CustomError::ConflictingFeeMode);
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.realtime_fees = enabled;
+        swap_pool.fee_destination_a = fee_destination_a;
+        swap_pool.fee_destination_b = fee_destination_b;
+        Ok(())
+    }
+
+    pub fn update_burn_fee_bps(ctx: Context<AdminAction>, new_burn_fee_bps: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(new_burn_fee_bps <= 10000, CustomError::FeeTooHigh); // Can't burn more than the whole fee
+
+        ctx.accounts.swap_pool.burn_fee_bps = new_burn_fee_bps;
+        Ok(())
+    }
+
+    pub fn set_tick_spacing(ctx: Context<AdminAction>, tick_spacing: u16) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.tick_spacing = tick_spacing;
+        Ok(())
+    }
+
+    // Confine swaps to [lower_tick, upper_tick), a single global range shared by all LPs; 0/0 restores
+    // the full range. The current price must already sit inside the new range
+    pub fn set_price_range(ctx: Context<SetPriceRange>, lower_tick: i32, upper_tick: i32) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        if lower_tick != 0 || upper_tick != 0 {
+            tick_math::validate_tick_range(lower_tick, upper_tick, ctx.accounts.swap_pool.tick_spacing)?;
+
+            // An unseeded pool has no price yet, so any range is accepted
+            let (reserve_a, reserve_b) = (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount);
+            if reserve_a > 0 && reserve_b > 0 {
+                let tick = tick_math::current_tick(reserve_a, reserve_b)?;
+                require!(tick >= lower_tick && tick < upper_tick, CustomError::InvalidTickRange);
+            }
+        }
+
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        swap_pool.range_lower_tick = lower_tick;
+        swap_pool.range_upper_tick = upper_tick;
+        Ok(())
+    }
+
+    // Delegate fee collection, e.g. to a treasury, without handing over the admin key
+    pub fn set_fee_authority(ctx: Context<AdminAction>, new_fee_authority: Pubkey) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.fee_authority = new_fee_authority;
+        Ok(())
+    }
+
+    pub fn transfer_admin(ctx: Context<TransferAdmin>, new_admin: Pubkey) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        ctx.accounts.swap_pool.admin = new_admin;
+        Ok(())
+    }
+
+    // Get token prices
+    // Price of token A in terms of token B (scaled by 10^6 for precision)
+    pub fn get_token_a_price(ctx: Context<GetPrice>) -> Result<u64> {
+        spot_price(ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+    }
+
+    // Price of token B in terms of token A (scaled by 10^6 for precision)
+    pub fn get_token_b_price(ctx: Context<GetPrice>) -> Result<u64> {
+        spot_price(ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+    }
+
+    // Both prices from one read of the reserves: (A in B, B in A), each scaled by 10^6
+    pub fn get_spot_prices(ctx: Context<GetPrice>) -> Result<(u64, u64)> {
+        let token_a_amount = ctx.accounts.token_a_vault.amount;
+        let token_b_amount = ctx.accounts.token_b_vault.amount;
+
+        Ok((spot_price(token_a_amount, token_b_amount)?, spot_price(token_b_amount, token_a_amount)?))
+    }
+
+    // Token deposits (amount_a, amount_b) needed to receive lp_amount at current reserves
+    // Rounded up so depositing them mints at least lp_amount to the depositor
+    pub fn calculate_tokens_for_lp(ctx: Context<CalculateTokensForLp>, lp_amount: u64) -> Result<(u64, u64)> {
+        require!(lp_amount > 0, CustomError::InvalidAmount);
+
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let total_lp_supply = ctx.accounts.lp_mint.supply;
+
+        if total_lp_supply > 0 {
+            let share = |reserve: u64| -> Result<u64> {
+                let supply = total_lp_supply as u128;
+                let amount = (lp_amount as u128)
+                    .checked_mul(reserve as u128)
+                    .and_then(|value| value.checked_add(supply - 1))
+                    .ok_or(CustomError::CalculationFailure)?
+                    / supply;
+                u64::try_from(amount).map_err(|_| CustomError::CalculationFailure.into())
+            };
+            return Ok((share(reserve_a)?, share(reserve_b)?));
+        }
+
+        // Initial deposit: the first depositor gets isqrt(a * b) over decimal-normalized amounts less the
+        // locked MINIMUM_LIQUIDITY, so invert that for lp_amount + MINIMUM_LIQUIDITY. Amounts follow the
+        // reserve ratio if tokens are already sitting in the vaults, otherwise 1:1 after normalization
+        let target = (lp_amount as u128)
+            .checked_add(MINIMUM_LIQUIDITY as u128)
+            .ok_or(CustomError::CalculationFailure)?;
+        let lp_decimals = ctx.accounts.lp_mint.decimals as i32;
+        let decimals_a = ctx.accounts.token_a_mint.decimals as i32;
+        let decimals_b = ctx.accounts.token_b_mint.decimals as i32;
+        let pow10 = |exponent: i32| 10u128.checked_pow(exponent.unsigned_abs()).ok_or(CustomError::CalculationFailure);
+
+        // b = a * ratio_num / ratio_den
+        let (ratio_num, ratio_den) = if reserve_a > 0 && reserve_b > 0 {
+            (reserve_b as u128, reserve_a as u128)
+        } else if decimals_b >= decimals_a {
+            (pow10(decimals_b - decimals_a)?, 1)
+        } else {
+            (1, pow10(decimals_a - decimals_b)?)
+        };
+
+        // The mint is isqrt(a * b * 10^exponent), which reaches target once a^2 * ratio_num * 10^exponent
+        // >= target^2 * ratio_den; rounding a and then b up keeps the product on the right side of that
+        let exponent = 2 * lp_decimals - decimals_a - decimals_b;
+        let (scale_num, scale_den) = if exponent >= 0 { (1, pow10(exponent)?) } else { (pow10(exponent)?, 1) };
+        let numerator = target
+            .checked_mul(target)
+            .and_then(|value| value.checked_mul(ratio_den))
+            .and_then(|value| value.checked_mul(scale_num))
+            .ok_or(CustomError::CalculationFailure)?;
+        let denominator = ratio_num.checked_mul(scale_den).ok_or(CustomError::CalculationFailure)?;
+        let min_a_squared = (numerator + (denominator - 1)) / denominator;
+        let root = isqrt(min_a_squared);
+        let amount_a = if root * root < min_a_squared { root + 1 } else { root };
+        let amount_b = amount_a
+            .checked_mul(ratio_num)
+            .and_then(|value| value.checked_add(ratio_den - 1))
+            .ok_or(CustomError::CalculationFailure)?
+            / ratio_den;
+
+        Ok((
+            u64::try_from(amount_a).map_err(|_| CustomError::CalculationFailure)?,
+            u64::try_from(amount_b).map_err(|_| CustomError::CalculationFailure)?,
+        ))
+    }
+
+    // What remove_liquidity would pay out for lp_amount right now, for valuing LP held anywhere
+    pub fn lp_to_tokens(ctx: Context<GetPoolStats>, lp_amount: u64) -> Result<(u64, u64)> {
+        withdrawal_amounts(
+            lp_amount,
+            ctx.accounts.lp_mint.supply,
+            ctx.accounts.token_a_vault.amount,
+            ctx.accounts.token_b_vault.amount,
+        )
+    }
+
+    // Get total liquidity of both tokens and current LP supply
+    // Reserves, LP supply and the pool's pricing curve
+    pub fn get_pool_stats(ctx: Context<GetPoolStats>) -> Result<(u64, u64, u64, u8)> {
+        let token_a_amount = ctx.accounts.token_a_vault.amount;
+        let token_b_amount = ctx.accounts.token_b_vault.amount;
+        let lp_supply = ctx.accounts.lp_mint.supply;
+
+        Ok((token_a_amount, token_b_amount, lp_supply, ctx.accounts.swap_pool.curve_type))
+    }
+
+    // Reserves owned by LPs: accrued protocol fees are swept into the fee vaults as they are charged,
+    // so the main vaults hold nothing else
+    pub fn get_effective_reserves(ctx: Context<GetPrice>) -> Result<(u64, u64)> {
+        Ok((ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount))
+    }
+
+    // Fees (token A, token B) an LP has earned, settled at each deposit/withdrawal plus accrual since
+    pub fn get_user_fee_earnings(ctx: Context<GetUserFeeEarnings>) -> Result<(u64, u64)> {
+        ctx.accounts.lp_holder.pending_fees(&ctx.accounts.swap_pool, ctx.accounts.user_lp_token.amount)
+    }
+
+    // Every SwapPool field behind a leading version tag, so SDKs can branch before decoding the rest
+    pub fn describe_pool(ctx: Context<DescribePool>) -> Result<PoolDescription> {
+        let swap_pool = &ctx.accounts.swap_pool;
+
+        Ok(PoolDescription {
+            version: swap_pool.version,
+            pool: (**swap_pool).clone(),
+        })
+    }
+
+    // Configuration without the balances, for clients that simulate rather than decode SwapPool
+    pub fn get_pool_config(ctx: Context<DescribePool>) -> Result<PoolConfig> {
+        let swap_pool = &ctx.accounts.swap_pool;
+
+        Ok(PoolConfig {
+            fee_rate: swap_pool.fee_rate,
+            is_paused: swap_pool.paused_ops != 0,
+            admin: swap_pool.admin,
+            fee_authority: swap_pool.fee_authority,
+            curve_type: swap_pool.curve_type,
+            lp_decimals: swap_pool.lp_decimals,
+            token_a_mint: swap_pool.token_a_mint,
+            token_b_mint: swap_pool.token_b_mint,
+            lp_mint: swap_pool.lp_mint,
+            token_a_vault: swap_pool.token_a_vault,
+            token_b_vault: swap_pool.token_b_vault,
+        })
+    }
+
+    // Everything an off-chain model needs to bootstrap from the current state in one call
+    // Accrued and lifetime fees and cumulative volume are read from the embedded pool
+    pub fn snapshot(ctx: Context<GetPoolStats>) -> Result<PoolSnapshot> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        let clock = Clock::get()?;
+
+        Ok(PoolSnapshot {
+            version: swap_pool.version,
+            pool: (**swap_pool).clone(),
+            reserve_a: ctx.accounts.token_a_vault.amount,
+            reserve_b: ctx.accounts.token_b_vault.amount,
+            lp_supply: ctx.accounts.lp_mint.supply,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        })
+    }
+
+    // Current price accumulators, extrapolated to now; sample twice and divide the difference by the
+    // elapsed time for a time-weighted average price
+    pub fn get_twap(ctx: Context<GetPoolStats>) -> Result<TwapObservation> {
+        let now = Clock::get()?.unix_timestamp;
+        let (price_cumulative_a, price_cumulative_b) = ctx.accounts.swap_pool.cumulative_prices_at(
+            ctx.accounts.token_a_vault.amount,
+            ctx.accounts.token_b_vault.amount,
+            now,
+        );
+
+        Ok(TwapObservation { price_cumulative_a, price_cumulative_b, timestamp: now })
+    }
+
+    // Reserves and LP supply alongside the swap and deposit/withdrawal counters
+    pub fn get_pool_metrics(ctx: Context<GetPoolStats>) -> Result<PoolMetrics> {
+        Ok(PoolMetrics {
+            reserve_a: ctx.accounts.token_a_vault.amount,
+            reserve_b: ctx.accounts.token_b_vault.amount,
+            lp_supply: ctx.accounts.lp_mint.supply,
+            swap_count: ctx.accounts.swap_pool.swap_count,
+            liquidity_event_count: ctx.accounts.swap_pool.liquidity_event_count,
+        })
+    }
+
+    // Signed change in each reserve since the last set_checkpoint
+    pub fn get_reserve_delta(ctx: Context<GetPrice>) -> Result<(i64, i64)> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        require!(swap_pool.checkpoint_ts > 0, CustomError::InvalidAmount);
+
+        let delta = |current: u64, checkpoint: u64| -> Result<i64> {
+            i64::try_from(current as i128 - checkpoint as i128).map_err(|_| CustomError::CalculationFailure.into())
+        };
+
+        Ok((
+            delta(ctx.accounts.token_a_vault.amount, swap_pool.reserve_checkpoint_a)?,
+            delta(ctx.accounts.token_b_vault.amount, swap_pool.reserve_checkpoint_b)?,
+        ))
+    }
+
+    // Cumulative fees per LP token, scaled by FEES_PER_LP_SCALE
+    // An LP's earnings are balance * (current - value at deposit) / FEES_PER_LP_SCALE
+    pub fn get_fees_per_lp(ctx: Context<GetPrice>) -> Result<(u128, u128)> {
+        let swap_pool = &ctx.accounts.swap_pool;
+
+        Ok((swap_pool.fees_per_lp_a, swap_pool.fees_per_lp_b))
+    }
+
+    // Calculate swap result without executing it
+    pub fn calculate_swap_result(ctx: Context<GetPrice>, amount_in: u64, is_a_to_b: bool) -> Result<(u64)> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        
+        let (source_amount, destination_amount) = if is_a_to_b {
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+        } else {
+            (ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+        };
+
+        let (final_output_amount, _) = pool_swap_output(
+            swap_pool,
+            source_amount,
+            destination_amount,
+            amount_in,
+            is_a_to_b,
+            swap_pool.fee_rate,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        // Don't let a quote display zero when the input is simply too small for this pool
+        require!(amount_in == 0 || final_output_amount > 0, CustomError::OutputTooSmall);
+
+        Ok(final_output_amount)
+    }
+
+    // Inverse of calculate_swap_result: the smallest input that yields at least amount_out after fees
+    pub fn calculate_required_input(ctx: Context<GetPrice>, amount_out: u64, is_a_to_b: bool) -> Result<u64> {
+        let swap_pool = &ctx.accounts.swap_pool;
+
+        let (source_amount, destination_amount) = if is_a_to_b {
+            (ctx.accounts.token_a_vault.amount, ctx.accounts.token_b_vault.amount)
+        } else {
+            (ctx.accounts.token_b_vault.amount, ctx.accounts.token_a_vault.amount)
+        };
+
+        pool_swap_input(swap_pool, source_amount, destination_amount, amount_out, swap_pool.fee_rate, Clock::get()?.unix_timestamp)
+    }
+
+    // Quote a multi-hop route without executing it
+    // remaining_accounts holds one [swap_pool, input_vault, output_vault] triple per hop, in order
+    pub fn calculate_route_result(ctx: Context<CalculateRoute>, amount_in: u64) -> Result<u64> {
+        let hops = ctx.remaining_accounts;
+        require!(!hops.is_empty() && hops.len() % 3 == 0, CustomError::InvalidSwapPool);
+
+        let mut amount = amount_in;
+        let mut previous_output_mint: Option<Pubkey> = None;
+
+        for hop in hops.chunks(3) {
+            let (amount_out, input_mint, output_mint) = quote_hop(&hop[0], &hop[1], &hop[2], amount)?;
+
+            // Each hop must consume the token produced by the previous one
+            if let Some(mint) = previous_output_mint {
+                require!(mint == input_mint, CustomError::InvalidToken);
+            }
+
+            amount = amount_out;
+            previous_output_mint = Some(output_mint);
+        }
+
+        Ok(amount)
+    }
+
+    // Price impact in bps of swapping amount_in against arbitrary reserves, no pool account needed
+    // Impact is the relative gap between spot price and the fee-inclusive execution price
+    pub fn calculate_price_impact(
+        _ctx: Context<CalculatePriceImpact>,
+        reserve_in: u64,
+        reserve_out: u64,
+        amount_in: u64,
+        fee_rate: u64,
+    ) -> Result<u64> {
+        require!(reserve_in > 0 && reserve_out > 0, CustomError::InsufficientLiquidity);
+        require!(amount_in > 0, CustomError::InvalidAmount);
+        require!(fee_rate <= 10000, CustomError::FeeTooHigh);
+
+        let (final_output_amount, _) = compute_swap_output(reserve_in, reserve_out, amount_in, fee_rate)?;
+
+        price_impact_bps(reserve_in, reserve_out, amount_in, final_output_amount)
+    }
+
+    // All-time (token A, token B) volume, counting both the input and output side of each swap
+    pub fn get_pool_volume(ctx: Context<GetPoolStats>) -> Result<(u64, u64)> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        Ok((swap_pool.cumulative_volume_a, swap_pool.cumulative_volume_b))
+    }
+
+    // Which operations the pool would accept right now, from its flags, caps and reserves
+    // A true flag doesn't guarantee success: per-call limits (slippage, output caps) still apply
+    pub fn get_available_operations(ctx: Context<GetPoolStats>) -> Result<AvailableOperations> {
+        let swap_pool = &ctx.accounts.swap_pool;
+        let reserve_a = ctx.accounts.token_a_vault.amount;
+        let reserve_b = ctx.accounts.token_b_vault.amount;
+        let lp_supply = ctx.accounts.lp_mint.supply;
+
+        let has_reserves = reserve_a > 0 && reserve_b > 0;
+        let fees_under_cap = swap_pool.max_accrued_fees == 0
+            || (swap_pool.total_fees_a < swap_pool.max_accrued_fees && swap_pool.total_fees_b < swap_pool.max_accrued_fees);
+        let can_swap = !swap_pool.is_paused(PAUSE_SWAPS) && !swap_pool.volume_halted && has_reserves && fees_under_cap;
+
+        // Selling A lowers the price of A in B and buying A raises it, so a price sitting on one
+        // edge of the band only blocks the direction that would push it further out
+        let price = if has_reserves {
+            (reserve_b as u128)
+                .checked_mul(1_000_000)
+                .ok_or(CustomError::CalculationFailure)?
+                / reserve_a as u128
+        } else {
+            0
+        };
+        let above_min = swap_pool.min_price == 0 || price > swap_pool.min_price as u128;
+        let below_max = swap_pool.max_price == 0 || price < swap_pool.max_price as u128;
+
+        let can_deposit = !swap_pool.is_paused(PAUSE_DEPOSITS) && has_reserves && lp_supply > 0;
+
+        Ok(AvailableOperations {
+            swap_a_to_b: can_swap && above_min,
+            swap_b_to_a: can_swap && below_max,
+            deposit: can_deposit,
+            deposit_new_holder: can_deposit
+                && (swap_pool.max_holders == 0 || swap_pool.holder_count < swap_pool.max_holders),
+            withdraw: !swap_pool.is_paused(PAUSE_WITHDRAWALS) && lp_supply > 0,
+        })
+    }
+
+    pub fn get_user_pool_share(ctx: Context<GetUserShare>) -> Result<(u64, u64, u64)> {
+        let token_a_vault_amount = ctx.accounts.token_a_vault.amount;
+        let token_b_vault_amount = ctx.accounts.token_b_vault.amount;
+        let lp_total_supply = ctx.accounts.lp_mint.supply;
+        let user_lp_balance = ctx.accounts.user_lp_token.amount;
+
+        // Calculate user's share in percentage (scaled by 10^6 for precision)
+        let user_share_percentage = if lp_total_supply == 0 {
+            0
+        } else {
+            (user_lp_balance as u128)
+                .checked_mul(1_000_000)
+                .ok_or(CustomError::CalculationFailure)?
+                .checked_div(lp_total_supply as u128)
+                .ok_or(CustomError::InsufficientLiquidity)? as u64
+        };
+
+        // Calculate user's share of tokens
+        let user_token_a_share = if lp_total_supply == 0 {
+            0
+        } else {
+            (user_lp_balance as u128)
+                .checked_mul(token_a_vault_amount as u128)
+                .ok_or(CustomError::CalculationFailure)?
+                .checked_div(lp_total_supply as u128)
+                .ok_or(CustomError::InsufficientLiquidity)? as u64
+        };
+        let user_token_b_share = if lp_total_supply == 0 {
+            0
+        } else {
+            (user_lp_balance as u128)
+                .checked_mul(token_b_vault_amount as u128)
+                .ok_or(CustomError::CalculationFailure)?
+                .checked_div(lp_total_supply as u128)
+                .ok_or(CustomError::InsufficientLiquidity)? as u64
+        };
+
+        Ok((user_share_percentage, user_token_a_share, user_token_b_share))
+    }
+
+    // Maximum amounts the user could withdraw right now, valued against LP-owned reserves
+    pub fn get_max_withdrawable(ctx: Context<GetUserShare>) -> Result<(u64, u64)> {
+        let lp_total_supply = ctx.accounts.lp_mint.supply;
+        let user_lp_balance = ctx.accounts.user_lp_token.amount;
+
+        if lp_total_supply == 0 {
+            return Ok((0, 0));
+        }
+
+        // Accrued protocol fees are kept in the fee vaults, so the main vaults belong to LPs in full
+        let lp_reserve_a = ctx.accounts.token_a_vault.amount;
+        let lp_reserve_b = ctx.accounts.token_b_vault.amount;
+
+        let max_amount_a = (user_lp_balance as u128)
+            .checked_mul(lp_reserve_a as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(lp_total_supply as u128)
+            .ok_or(CustomError::CalculationFailure)? as u64;
+        let max_amount_b = (user_lp_balance as u128)
+            .checked_mul(lp_reserve_b as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(lp_total_supply as u128)
+            .ok_or(CustomError::CalculationFailure)? as u64;
+
+        Ok((max_amount_a, max_amount_b))
+    }
+
+    // Token amounts an NFT position would redeem at current reserves
+    pub fn get_nft_position_value(ctx: Context<GetNftPositionValue>) -> Result<(u64, u64)> {
+        let lp_total_supply = ctx.accounts.lp_mint.supply;
+        require!(lp_total_supply > 0, CustomError::InsufficientLiquidity);
+
+        let liquidity = ctx.accounts.nft_position.liquidity;
+        let amount_a = (liquidity as u128)
+            .checked_mul(ctx.accounts.token_a_vault.amount as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(lp_total_supply as u128)
+            .ok_or(CustomError::CalculationFailure)? as u64;
+        let amount_b = (liquidity as u128)
+            .checked_mul(ctx.accounts.token_b_vault.amount as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(lp_total_supply as u128)
+            .ok_or(CustomError::CalculationFailure)? as u64;
+
+        Ok((amount_a, amount_b))
+    }
+
+    // Create the optional LP supply history buffer for a pool
+    pub fn initialize_lp_history(ctx: Context<InitializeLpHistory>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        let lp_history = &mut ctx.accounts.lp_history;
+        lp_history.swap_pool = ctx.accounts.swap_pool.key();
+        lp_history.head = 0;
+        lp_history.len = 0;
+        lp_history.snapshots = [LpSupplySnapshot::default(); 32];
+        lp_history.bump = ctx.bumps.lp_history;
+
+        Ok(())
+    }
+
+    // Recorded LP supply snapshots, oldest first
+    pub fn get_lp_supply_history(ctx: Context<GetLpSupplyHistory>) -> Result<Vec<LpSupplySnapshot>> {
+        Ok(ctx.accounts.lp_history.snapshots_in_order())
+    }
+
+    // Function to create wrapper for sync native instruction (for SOL pools)
+    pub fn sync_native(ctx: Context<SyncNative>) -> Result<()> {
+        require!(!ctx.accounts.swap_pool.is_paused(PAUSE_DEPOSITS), CustomError::PoolPaused);
+        require!(ctx.accounts.token_account.mint == native_mint::ID, CustomError::NotNativeMint);
+
+        // This is used when one of the tokens is wrapped SOL
+        let cpi_ctx = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            NativeSyncNative {
+                account: ctx.accounts.token_account.to_account_info(),
+            },
+        );
+
+        native_sync_native(cpi_ctx)?;
+
+        Ok(())
+    }
+
+    // Grow a pool created with an older, shorter layout to the current SwapPool size
+    // Anchor can't deserialize the old layout, so the account is resized by hand and the tail zeroed
+    pub fn realloc_pool(ctx: Context<ReallocPool>) -> Result<()> {
+        resize_pool_account(&ctx.accounts)
+    }
+
+    // One-step upgrade of a pool in an older layout: resize it, give the fields added since their
+    // defaults and stamp CURRENT_POOL_VERSION. Safe to call again on an up-to-date pool. Pools whose
+    // vaults or fee vaults aren't the program's PDAs fail every vault constraint, so they are refused
+    pub fn migrate_pool(ctx: Context<ReallocPool>) -> Result<()> {
+        resize_pool_account(&ctx.accounts)?;
+
+        let pool_info = ctx.accounts.swap_pool.to_account_info();
+        let mut swap_pool = SwapPool::try_deserialize(&mut &pool_info.try_borrow_data()?[..])?;
+
+        let vault_pda = |seed: &[u8], mint: &Pubkey| {
+            Pubkey::find_program_address(&[seed, swap_pool.pool_authority.as_ref(), mint.as_ref()], &crate::ID)
+        };
+        let (vault_a, vault_a_bump) = vault_pda(b"token_vault", &swap_pool.token_a_mint);
+        let (vault_b, vault_b_bump) = vault_pda(b"token_vault", &swap_pool.token_b_mint);
+        let (fee_vault_a, _) = vault_pda(b"fee_vault", &swap_pool.token_a_mint);
+        let (fee_vault_b, _) = vault_pda(b"fee_vault", &swap_pool.token_b_mint);
+        require!(
+            swap_pool.token_a_vault == vault_a && swap_pool.token_b_vault == vault_b,
+            CustomError::UnsupportedPoolVaults
+        );
+        require!(
+            swap_pool.fee_vault_a == fee_vault_a && swap_pool.fee_vault_b == fee_vault_b,
+            CustomError::UnsupportedPoolVaults
+        );
+        // Bumps were never stored by pools from before they were recorded
+        swap_pool.vault_a_bump = vault_a_bump;
+        swap_pool.vault_b_bump = vault_b_bump;
+
+        if swap_pool.version < CURRENT_POOL_VERSION {
+            swap_pool.migrate_to(CURRENT_POOL_VERSION);
+        }
+
+        let mut data = pool_info.try_borrow_mut_data()?;
+        let dst: &mut [u8] = &mut data;
+        swap_pool.try_serialize(&mut std::io::Cursor::new(dst))?;
+
+        Ok(())
+    }
+
+    pub fn update_pool_version(ctx: Context<AdminAction>, new_version: u8) -> Result<()> {
+        require!(
+            ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin,
+            CustomError::Unauthorized
+        );
+        // Versions only move forward and never past what this program understands
+        let swap_pool = &mut ctx.accounts.swap_pool;
+        require!(
+            new_version > swap_pool.version && new_version <= CURRENT_POOL_VERSION,
+            CustomError::InvalidAmount
+        );
+        swap_pool.migrate_to(new_version);
+
+        Ok(())
+    }
+
+    // Shut down a drained pool: sweep what is left in the vaults and fee vaults to the admin, burn the
+    // locked liquidity, close the vaults and the pool account and return their rent. SPL Token mints
+    // can't be closed, so the (now empty) LP mint stays behind
+    pub fn close_pool(ctx: Context<ClosePool>) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+
+        // Only the permanently locked liquidity may remain, so nobody else has a claim on the vaults. Pools
+        // seeded before the dedicated lock account kept it in the position escrow, so both count as locked,
+        // but together they can't exceed MINIMUM_LIQUIDITY: anything more is an NFT position still open
+        let locked_lp = ctx.accounts.locked_lp_token.as_ref().map_or(0, |account| account.amount);
+        let escrowed_lp = ctx.accounts.position_lp_vault.as_ref().map_or(0, |account| account.amount);
+        let locked_lp = locked_lp.checked_add(escrowed_lp).ok_or(CustomError::CalculationFailure)?;
+        require!(
+            ctx.accounts.lp_mint.supply == locked_lp && locked_lp <= MINIMUM_LIQUIDITY,
+            CustomError::PoolNotEmpty
+        );
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            ctx.accounts.swap_pool.token_a_mint.as_ref(),
+            ctx.accounts.swap_pool.token_b_mint.as_ref(),
+            ctx.accounts.swap_pool.fee_tier_seed(),
+            &[ctx.accounts.swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let lock_accounts = [ctx.accounts.locked_lp_token.as_ref(), ctx.accounts.position_lp_vault.as_ref()];
+        for locked_lp_token in lock_accounts.into_iter().flatten() {
+            if locked_lp_token.amount > 0 {
+                let burn_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Burn {
+                        mint: ctx.accounts.lp_mint.to_account_info(),
+                        from: locked_lp_token.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    signer
+                );
+                burn(burn_ctx, locked_lp_token.amount)?;
+            }
+
+            let close_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: locked_lp_token.to_account_info(),
+                    destination: ctx.accounts.admin.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                signer
+            );
+            close_account(close_ctx)?;
+        }
+
+        // The locked liquidity's share is all that can be left in the main vaults, uncollected fees in the fee vaults
+        let vaults = [
+            (&ctx.accounts.token_a_vault, &ctx.accounts.admin_token_a, &ctx.accounts.token_a_mint),
+            (&ctx.accounts.token_b_vault, &ctx.accounts.admin_token_b, &ctx.accounts.token_b_mint),
+            (&ctx.accounts.fee_vault_a, &ctx.accounts.admin_token_a, &ctx.accounts.token_a_mint),
+            (&ctx.accounts.fee_vault_b, &ctx.accounts.admin_token_b, &ctx.accounts.token_b_mint),
+        ];
+        for (vault, destination, mint) in vaults {
+            if vault.amount > 0 {
+                let transfer_ctx = CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: vault.to_account_info(),
+                        to: destination.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                        mint: mint.to_account_info(),
+                    },
+                    signer
+                );
+                transfer_checked(transfer_ctx, vault.amount, mint.decimals)?;
+            }
+
+            let close_ctx = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account: vault.to_account_info(),
+                    destination: ctx.accounts.admin.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                signer
+            );
+            close_account(close_ctx)?;
+        }
+
+        // The pool account itself is closed to the admin by the `close` constraint
+        Ok(())
+    }
+
+    // Return tokens sent to a pool_authority-owned account by mistake. The pool's own mints are
+    // off limits, so the reserves, fee vaults and locked liquidity can never be drained this way
+    pub fn rescue_tokens(ctx: Context<RescueTokens>, amount: u64) -> Result<()> {
+        require!(ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin, CustomError::Unauthorized);
+        require!(amount > 0, CustomError::InvalidAmount);
+
+        let swap_pool = &ctx.accounts.swap_pool;
+        let mint = ctx.accounts.mint.key();
+        require!(
+            mint != swap_pool.token_a_mint && mint != swap_pool.token_b_mint && mint != swap_pool.lp_mint,
+            CustomError::InvalidToken
+        );
+
+        let seeds = &[
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+            &[swap_pool.authority_bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        let transfer_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: ctx.accounts.source.to_account_info(),
+                to: ctx.accounts.destination.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_ctx, amount, ctx.accounts.mint.decimals)?;
+
+        Ok(())
+    }
+}
+
+// Distance of the reserves from 50/50 in bps of their total, in decimal-normalized units
+fn reserve_imbalance_bps(reserve_a: u128, reserve_b: u128, decimals_a: u8, decimals_b: u8) -> Result<u128> {
+    let max_decimals = decimals_a.max(decimals_b) as u32;
+    let a = reserve_a
+        .checked_mul(10u128.pow(max_decimals - decimals_a as u32))
+        .ok_or(CustomError::CalculationFailure)?;
+    let b = reserve_b
+        .checked_mul(10u128.pow(max_decimals - decimals_b as u32))
+        .ok_or(CustomError::CalculationFailure)?;
+    let total = a.checked_add(b).ok_or(CustomError::CalculationFailure)?;
+    require!(total > 0, CustomError::InsufficientLiquidity);
+    Ok(a.abs_diff(b).checked_mul(10000).ok_or(CustomError::CalculationFailure)? / total)
+}
+
+// Reject a reserve change that leaves the pool past max_imbalance_bps and further from balance than before
+fn check_imbalance(swap_pool: &SwapPool, before: (u64, u64), after: (u64, u64), decimals_a: u8, decimals_b: u8) -> Result<()> {
+    if swap_pool.max_imbalance_bps == 0 {
+        return Ok(());
+    }
+
+    let imbalance_before = reserve_imbalance_bps(before.0 as u128, before.1 as u128, decimals_a, decimals_b)?;
+    let imbalance_after = reserve_imbalance_bps(after.0 as u128, after.1 as u128, decimals_a, decimals_b)?;
+    require!(
+        imbalance_after <= swap_pool.max_imbalance_bps as u128 || imbalance_after <= imbalance_before,
+        CustomError::PoolImbalanced
+    );
+    Ok(())
+}
+
+// Signed LP adjustment (bps) for a deposit, positive when it moves the reserves toward 50/50
+// Balance is measured in decimal-normalized units, i.e. at a 1:1 peg, which suits the pegged pairs this targets
+fn balance_incentive_adjustment_bps(
+    reserve_a: u64,
+    reserve_b: u64,
+    amount_a: u64,
+    amount_b: u64,
+    decimals_a: u8,
+    decimals_b: u8,
+    incentive_bps: u64,
+) -> Result<i64> {
+    let before = reserve_imbalance_bps(reserve_a as u128, reserve_b as u128, decimals_a, decimals_b)?;
+    let after = reserve_imbalance_bps(
+        reserve_a as u128 + amount_a as u128,
+        reserve_b as u128 + amount_b as u128,
+        decimals_a,
+        decimals_b,
+    )?;
+
+    // The adjustment scales with how much the deposit moved the balance, capped at the incentive
+    let moved = before.abs_diff(after).min(incentive_bps as u128) as i64;
+    Ok(if after < before { moved } else { -moved })
+}
+
+// Shared by realloc_pool and migrate_pool: grow the pool account to the current SwapPool size
+fn resize_pool_account(accounts: &ReallocPool) -> Result<()> {
+    let pool_info = accounts.swap_pool.to_account_info();
+    require!(pool_info.owner == &crate::ID, CustomError::InvalidSwapPool);
+
+    let current_len = pool_info.data_len();
+    let new_len = 8 + SwapPool::INIT_SPACE;
+    {
+        let data = pool_info.try_borrow_data()?;
+        require!(current_len >= SWAP_POOL_ADMIN_OFFSET + 32, CustomError::InvalidSwapPool);
+        require!(data[..8] == SwapPool::DISCRIMINATOR, CustomError::InvalidSwapPool);
+
+        // admin sits at the same offset in every layout, new fields are only ever appended
+        let admin = Pubkey::try_from(&data[SWAP_POOL_ADMIN_OFFSET..SWAP_POOL_ADMIN_OFFSET + 32])
+            .map_err(|_| CustomError::InvalidSwapPool)?;
+        require!(accounts.admin.key() == admin, CustomError::Unauthorized);
+    }
+
+    // Never shrink, that would cut off live fields
+    require!(current_len <= new_len, CustomError::InvalidSwapPool);
+    if current_len == new_len {
+        return Ok(());
+    }
+
+    let required_lamports = Rent::get()?.minimum_balance(new_len);
+    let top_up = required_lamports.saturating_sub(pool_info.lamports());
+    if top_up > 0 {
+        let transfer_cpi = CpiContext::new(
+            accounts.system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: accounts.admin.to_account_info(),
+                to: pool_info.clone(),
+            },
+        );
+        anchor_lang::system_program::transfer(transfer_cpi, top_up)?;
+    }
+
+    // Zeroed tail leaves every new field at 0 / false / default pubkey
+    pool_info.realloc(new_len, true)?;
+
+    msg!("Pool resized from {} to {} bytes", current_len, new_len);
+
+    Ok(())
+}
+
+// Transfer into a pool vault and return what the vault was actually credited, which is less than
+// `amount` when a Token-2022 mint withholds a transfer fee
+fn transfer_in<'info>(
+    cpi_ctx: CpiContext<'_, '_, '_, 'info, TransferChecked<'info>>,
+    amount: u64,
+    decimals: u8,
+) -> Result<u64> {
+    let vault = cpi_ctx.accounts.to.clone();
+    let vault_balance = || -> Result<u64> {
+        let data = vault.try_borrow_data()?;
+        Ok(TokenAccount::try_deserialize(&mut &data[..])?.amount)
+    };
+
+    let balance_before = vault_balance()?;
+    transfer_checked(cpi_ctx, amount, decimals)?;
+    let received = vault_balance()?
+        .checked_sub(balance_before)
+        .ok_or(CustomError::CalculationFailure)?;
+
+    require!(amount == 0 || received > 0, CustomError::UnexpectedTransferFee);
+    Ok(received)
+}
+
+// Accounts a deposit or withdrawal moves tokens through, lent field by field by whichever context runs
+// it so the fungible and NFT paths share one core
+struct LiquidityAccounts<'a, 'info> {
+    token_a_mint: &'a InterfaceAccount<'info, Mint>,
+    token_b_mint: &'a InterfaceAccount<'info, Mint>,
+    token_a_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    token_b_vault: &'a InterfaceAccount<'info, TokenAccount>,
+    user_token_a: &'a InterfaceAccount<'info, TokenAccount>,
+    user_token_b: &'a InterfaceAccount<'info, TokenAccount>,
+    lp_mint: &'a InterfaceAccount<'info, Mint>,
+    pool_authority: &'a UncheckedAccount<'info>,
+    user_authority: &'a Signer<'info>,
+    token_program: &'a Interface<'info, TokenInterface>,
+}
+
+// Deposit core shared by add_liquidity and add_liquidity_nft: prices the deposit against the current
+// reserves, pulls both tokens in and returns the amounts credited to the vaults with the LP they earn.
+// Leaves the pool entered; the caller mints the LP, settles its bookkeeping and clears in_progress
+fn deposit_liquidity(
+    swap_pool: &mut Account<SwapPool>,
+    accounts: &LiquidityAccounts,
+    (amount_a_desired, amount_b_desired): (u64, u64),
+    (amount_a_min, amount_b_min): (u64, u64),
+    expected_ratio: Option<u64>,
+    ratio_tolerance_bps: u64,
+) -> Result<(u64, u64, u64)> {
+    require!(!swap_pool.is_paused(PAUSE_DEPOSITS), CustomError::PoolPaused);
+    require!(amount_a_desired > 0 && amount_b_desired > 0, CustomError::InvalidAmount);
+    enter_pool(swap_pool)?;
+
+    let reserve_a = accounts.token_a_vault.amount;
+    let reserve_b = accounts.token_b_vault.amount;
+    let total_lp_supply = accounts.lp_mint.supply;
+    swap_pool.accumulate_prices(reserve_a, reserve_b, Clock::get()?.unix_timestamp);
+
+    // Reserves without LP (e.g. donations) must be seeded through add_initial_liquidity
+    require!(total_lp_supply > 0, CustomError::PoolNotSeeded);
+
+    require!(reserve_a > 0 && reserve_b > 0, CustomError::InsufficientLiquidity);
+
+    // Guard against a front-run swap skewing the ratio (B per A, scaled by 10^6) before any transfer
+    if let Some(expected_ratio) = expected_ratio {
+        require!(expected_ratio > 0, CustomError::InvalidAmount);
+
+        let current_ratio = (reserve_b as u128)
+            .checked_mul(1_000_000)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(reserve_a as u128)
+            .ok_or(CustomError::CalculationFailure)?;
+        let deviation_bps = current_ratio
+            .abs_diff(expected_ratio as u128)
+            .checked_mul(10000)
+            .ok_or(CustomError::CalculationFailure)?
+            / expected_ratio as u128;
+
+        require!(deviation_bps <= ratio_tolerance_bps as u128, CustomError::RatioSkewed);
+    }
+
+    // Calculate amounts to actually transfer based on current ratio
+    let amount_b_optimal = u64::try_from(
+        (amount_a_desired as u128)
+            .checked_mul(reserve_b as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(reserve_a as u128)
+            .ok_or(CustomError::InsufficientLiquidity)?,
+    )
+    .map_err(|_| CustomError::CalculationFailure)?;
+
+    let balance_incentive_bps = swap_pool.balance_incentive_bps;
+
+    let (amount_a, amount_b) = if balance_incentive_bps > 0 {
+        // With the incentive on, deposits are taken as given so they can move the reserve balance
+        (amount_a_desired, amount_b_desired)
+    } else if amount_b_desired >= amount_b_optimal {
+        let amount_a = amount_a_desired;
+        let amount_b = amount_b_optimal;
+
+        require!(amount_b >= amount_b_min, CustomError::SlippageExceeded);
+        (amount_a, amount_b)
+    } else {
+        let amount_a_optimal = u64::try_from(
+            (amount_b_desired as u128)
+                .checked_mul(reserve_a as u128)
+                .ok_or(CustomError::CalculationFailure)?
+                .checked_div(reserve_b as u128)
+                .ok_or(CustomError::InsufficientLiquidity)?,
+        )
+        .map_err(|_| CustomError::CalculationFailure)?;
+
+        let amount_a = amount_a_optimal;
+        let amount_b = amount_b_desired;
+
+        require!(amount_a >= amount_a_min, CustomError::SlippageExceeded);
+        (amount_a, amount_b)
+    };
+
+    // Report a short balance with the program's own error rather than the token program's
+    require!(
+        accounts.user_token_a.amount >= amount_a && accounts.user_token_b.amount >= amount_b,
+        CustomError::InsufficientFunds
+    );
+
+    // Ratio-matched deposits leave the balance unchanged, so only as-given deposits can worsen it
+    if balance_incentive_bps > 0 {
+        check_imbalance(
+            swap_pool,
+            (reserve_a, reserve_b),
+            (
+                reserve_a.checked_add(amount_a).ok_or(CustomError::CalculationFailure)?,
+                reserve_b.checked_add(amount_b).ok_or(CustomError::CalculationFailure)?,
+            ),
+            accounts.token_a_mint.decimals,
+            accounts.token_b_mint.decimals,
+        )?;
+    }
+
+    // Transfer token A from user to pool; LP is priced on what the vaults were credited, since
+    // transfer-fee mints deliver less than was sent
+    let transfer_a_ctx = CpiContext::new(
+        accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: accounts.user_token_a.to_account_info(),
+            to: accounts.token_a_vault.to_account_info(),
+            authority: accounts.user_authority.to_account_info(),
+            mint: accounts.token_a_mint.to_account_info(),
+        },
+    );
+    let amount_a = transfer_in(
+        transfer_a_ctx,
+        amount_a,
+        accounts.token_a_mint.decimals
+    )?;
+
+    // Transfer token B from user to pool
+    let transfer_b_ctx = CpiContext::new(
+        accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: accounts.user_token_b.to_account_info(),
+            to: accounts.token_b_vault.to_account_info(),
+            authority: accounts.user_authority.to_account_info(),
+            mint: accounts.token_b_mint.to_account_info(),
+        },
+    );
+    let amount_b = transfer_in(
+        transfer_b_ctx,
+        amount_b,
+        accounts.token_b_mint.decimals
+    )?;
+
+    // Calculate LP tokens to mint
+    // The formula uses the minimum ratio to ensure fair distribution
+    let lp_amount_a = u64::try_from(
+        (amount_a as u128)
+            .checked_mul(total_lp_supply as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(reserve_a as u128)
+            .ok_or(CustomError::InsufficientLiquidity)?,
+    )
+    .map_err(|_| CustomError::CalculationFailure)?;
+
+    let lp_amount_b = u64::try_from(
+        (amount_b as u128)
+            .checked_mul(total_lp_supply as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(reserve_b as u128)
+            .ok_or(CustomError::InsufficientLiquidity)?,
+    )
+    .map_err(|_| CustomError::CalculationFailure)?;
+
+    let lp_to_mint = std::cmp::min(lp_amount_a, lp_amount_b);
+
+    // Redeeming the new LP straight away must not return more than was deposited beyond one unit
+    // of rounding, or dust deposits could mint mispriced LP
+    let (redeemable_a, redeemable_b) = withdrawal_amounts(
+        lp_to_mint,
+        total_lp_supply.checked_add(lp_to_mint).ok_or(CustomError::CalculationFailure)?,
+        reserve_a.checked_add(amount_a).ok_or(CustomError::CalculationFailure)?,
+        reserve_b.checked_add(amount_b).ok_or(CustomError::CalculationFailure)?,
+    )?;
+    require!(
+        redeemable_a <= amount_a.saturating_add(1) && redeemable_b <= amount_b.saturating_add(1),
+        CustomError::SlippageExceeded
+    );
+
+    // Reward deposits that move reserves toward balance and penalize those that move away
+    let lp_to_mint = if balance_incentive_bps > 0 {
+        let adjustment_bps = balance_incentive_adjustment_bps(
+            reserve_a,
+            reserve_b,
+            amount_a,
+            amount_b,
+            accounts.token_a_mint.decimals,
+            accounts.token_b_mint.decimals,
+            balance_incentive_bps,
+        )?;
+        ((lp_to_mint as i128)
+            .checked_mul(10000 + adjustment_bps as i128)
+            .ok_or(CustomError::CalculationFailure)?
+            / 10000) as u64
+    } else {
+        lp_to_mint
+    };
+
+    Ok((amount_a, amount_b, lp_to_mint))
+}
+
+// Withdrawal core shared by remove_liquidity and remove_liquidity_nft: the reserves owed for burning
+// lp_amount, checked against the caller's minimums. Leaves the pool entered; the caller burns the LP,
+// settles its bookkeeping, pays out with pay_out_liquidity and clears in_progress
+fn withdraw_liquidity(
+    swap_pool: &mut Account<SwapPool>,
+    accounts: &LiquidityAccounts,
+    lp_amount: u64,
+    (amount_a_min, amount_b_min): (u64, u64),
+) -> Result<(u64, u64)> {
+    require!(!swap_pool.is_paused(PAUSE_WITHDRAWALS), CustomError::PoolPaused);
+    require!(lp_amount > 0, CustomError::InvalidAmount);
+    enter_pool(swap_pool)?;
+    swap_pool.accumulate_prices(
+        accounts.token_a_vault.amount,
+        accounts.token_b_vault.amount,
+        Clock::get()?.unix_timestamp,
+    );
+
+    let (amount_a, amount_b) = withdrawal_amounts(
+        lp_amount,
+        accounts.lp_mint.supply,
+        accounts.token_a_vault.amount,
+        accounts.token_b_vault.amount,
+    )?;
+
+    require!(amount_a >= amount_a_min, CustomError::SlippageExceeded);
+    require!(amount_b >= amount_b_min, CustomError::SlippageExceeded);
+    Ok((amount_a, amount_b))
+}
+
+// Send a withdrawal's tokens from the vaults to the user, signed by the pool authority
+fn pay_out_liquidity(swap_pool: &SwapPool, accounts: &LiquidityAccounts, amount_a: u64, amount_b: u64) -> Result<()> {
+    let seeds = &[
+        b"pool_authority".as_ref(),
+        swap_pool.token_a_mint.as_ref(),
+        swap_pool.token_b_mint.as_ref(),
+        swap_pool.fee_tier_seed(),
+        &[swap_pool.authority_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let payouts = [
+        (accounts.token_a_vault, accounts.user_token_a, accounts.token_a_mint, amount_a),
+        (accounts.token_b_vault, accounts.user_token_b, accounts.token_b_mint, amount_b),
+    ];
+    for (vault, user_token, mint, amount) in payouts {
+        let transfer_ctx = CpiContext::new_with_signer(
+            accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: vault.to_account_info(),
+                to: user_token.to_account_info(),
+                authority: accounts.pool_authority.to_account_info(),
+                mint: mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_ctx, amount, mint.decimals)?;
+    }
+    Ok(())
+}
+
+// Mark the pool busy for the rest of the instruction. Anchor only writes accounts back on exit, so the
+// flag is serialized right away; otherwise a program reached through a CPI (such as a Token-2022
+// transfer hook) would still read it as clear when calling back in
+fn enter_pool(swap_pool: &mut Account<SwapPool>) -> Result<()> {
+    require!(!swap_pool.in_progress, CustomError::ReentrancyDetected);
+    swap_pool.in_progress = true;
+    swap_pool.exit(&crate::ID)
+}
+
+// Throttle repeat swaps by the same user while the pool enforces a cooldown
+fn apply_swap_cooldown(
+    swap_pool: &Account<SwapPool>,
+    swap_cooldown: Option<&mut Account<SwapCooldown>>,
+    bump: Option<u8>,
+    user: Pubkey,
+    now: i64,
+) -> Result<()> {
+    if swap_pool.swap_cooldown_secs == 0 {
+        return Ok(());
+    }
+    let swap_cooldown = swap_cooldown.ok_or(CustomError::MissingCooldownAccount)?;
+    require!(
+        swap_cooldown.last_swap_ts == 0
+            || now >= swap_cooldown.last_swap_ts + swap_pool.swap_cooldown_secs as i64,
+        CustomError::CooldownActive
+    );
+    swap_cooldown.swap_pool = swap_pool.key();
+    swap_cooldown.user = user;
+    swap_cooldown.last_swap_ts = now;
+    swap_cooldown.bump = bump.ok_or(CustomError::MissingCooldownAccount)?;
+    Ok(())
+}
+
+// Feed a trade's volume to the spike breaker and the lifetime counters. A reverted swap can't persist
+// a pause, so the trade that trips the breaker still settles and every swap after it is halted until
+// the admin clears the flag
+fn record_swap_volume(swap_pool: &mut SwapPool, volume_a: u64, volume_b: u64, now: i64) -> Result<()> {
+    if swap_pool.record_volume(volume_a, now)? {
+        swap_pool.volume_halted = true;
+        msg!("Volume spike detected, swaps halted pending admin review");
+    }
+    swap_pool.cumulative_volume_a = swap_pool.cumulative_volume_a.checked_add(volume_a).ok_or(CustomError::InvalidAmount)?;
+    swap_pool.cumulative_volume_b = swap_pool.cumulative_volume_b.checked_add(volume_b).ok_or(CustomError::InvalidAmount)?;
+    Ok(())
+}
+
+// Post-trade limits for anything that trades against the curve. priced is the (input, output) reserves
+// the trade was quoted on and traded its (amount_in, amount_out); before and after are the (a, b)
+// reserves around the trade, after counting everything that leaves the output vault
+fn check_swap_limits(
+    swap_pool: &SwapPool,
+    priced: (u64, u64),
+    traded: (u64, u64),
+    before: (u64, u64),
+    after: (u64, u64),
+) -> Result<()> {
+    let (reserve_in, reserve_out) = priced;
+    let (amount_in, amount_out) = traded;
+    let (post_reserve_a, post_reserve_b) = after;
+
+    // Circuit breaker: the post-swap price of A in B (scaled by 10^6) must stay inside the band
+    if swap_pool.min_price > 0 || swap_pool.max_price > 0 {
+        require!(post_reserve_a > 0, CustomError::InsufficientLiquidity);
+
+        let post_price = (post_reserve_b as u128)
+            .checked_mul(1_000_000)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(post_reserve_a as u128)
+            .ok_or(CustomError::CalculationFailure)?;
+
+        require!(
+            swap_pool.min_price == 0 || post_price >= swap_pool.min_price as u128,
+            CustomError::PriceOutOfBand
+        );
+        require!(
+            swap_pool.max_price == 0 || post_price <= swap_pool.max_price as u128,
+            CustomError::PriceOutOfBand
+        );
+    }
+
+    // Bound any single swap to a fraction of the destination reserve (10000 or 0 = no limit)
+    if swap_pool.max_output_fraction_bps > 0 && swap_pool.max_output_fraction_bps < 10000 {
+        let max_output = (reserve_out as u128)
+            .checked_mul(swap_pool.max_output_fraction_bps as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            / 10000;
+        require!(amount_out as u128 <= max_output, CustomError::OutputExceedsLimit);
+    }
+
+    // Protocol-level ceiling on price impact, independent of the caller's min_amount_out
+    if swap_pool.protocol_max_slippage_bps > 0 && swap_pool.protocol_max_slippage_bps < 10000 {
+        let impact_bps = price_impact_bps(reserve_in, reserve_out, amount_in, amount_out)?;
+        require!(impact_bps <= swap_pool.protocol_max_slippage_bps, CustomError::SlippageExceeded);
+    }
+
+    // Global rail against sandwiches: the reserve ratio may only move so far in one swap
+    if swap_pool.max_price_impact_bps > 0 {
+        let impact_bps = reserve_ratio_change_bps(before, after)?;
+        require!(impact_bps <= swap_pool.max_price_impact_bps as u128, CustomError::PriceImpactTooHigh);
+    }
+
+    // Liquidity only covers the pool's price range, so a swap may not push the price out of it
+    if swap_pool.has_price_range() {
+        let tick = tick_math::current_tick(post_reserve_a, post_reserve_b)?;
+        require!(
+            tick >= swap_pool.range_lower_tick && tick < swap_pool.range_upper_tick,
+            CustomError::PriceRangeExhausted
+        );
+    }
+    Ok(())
+}
+
+// Shared body of swap and swap_cpi, returning the amount sent to the user
+fn process_swap(ctx: Context<Swap>, amount_in: u64, min_amount_out: u64, is_a_to_b: bool, unwrap_sol: bool) -> Result<u64> {
+    require!(!ctx.accounts.swap_pool.is_paused(PAUSE_SWAPS), CustomError::PoolPaused);
+    require!(!ctx.accounts.swap_pool.volume_halted, CustomError::VolumeSpikeHalt);
+    require!(amount_in > 0, CustomError::InvalidAmount);
+    enter_pool(&mut ctx.accounts.swap_pool)?;
+
+    let swap_pool = &mut ctx.accounts.swap_pool;
+    let token_a_vault = &ctx.accounts.token_a_vault;
+    let token_b_vault = &ctx.accounts.token_b_vault;
+    let user_token_a = &ctx.accounts.user_token_a;
+    let user_token_b = &ctx.accounts.user_token_b;
+    let token_a_mint = &ctx.accounts.token_a_mint;
+    let token_b_mint = &ctx.accounts.token_b_mint;
+
+    // The context pins each user account to its mint, so the caller's flag alone picks the direction
+    let (input_amount, input_token_account, redeem_token_account, input_token_vault, redeem_token_vault, input_token_mint, redeem_token_mint) =
+    if is_a_to_b {
+        (amount_in, user_token_a, user_token_b, token_a_vault, token_b_vault, token_a_mint, token_b_mint)
+    } else {
+        (amount_in, user_token_b, user_token_a, token_b_vault, token_a_vault, token_b_mint, token_a_mint)
+    };
+    require!(!unwrap_sol || redeem_token_mint.key() == native_mint::ID, CustomError::NotNativeMint);
+
+    let input_token_vault_amount = input_token_vault.amount;
+    let redeem_token_vault_amount = redeem_token_vault.amount;
+
+    // Fail with the program's own error before any CPI; a wrapped-SOL shortfall is topped up below instead
+    require!(
+        input_token_account.amount >= input_amount || input_token_mint.key() == native_mint::ID,
+        CustomError::InsufficientFunds
+    );
+
+    // A wrapped-SOL input short of amount_in is topped up from the signer's lamports and synced,
+    // so SOL can be swapped without wrapping it by hand first
+    if input_token_mint.key() == native_mint::ID && input_token_account.amount < input_amount {
+        let wrap_cpi = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: ctx.accounts.user_authority.to_account_info(),
+                to: input_token_account.to_account_info(),
+            },
+        );
+        anchor_lang::system_program::transfer(wrap_cpi, input_amount - input_token_account.amount)?;
+
+        let sync_cpi = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            NativeSyncNative {
+                account: input_token_account.to_account_info(),
+            },
+        );
+        native_sync_native(sync_cpi)?;
+    }
+
+    // Pull the input before pricing, since a transfer-fee mint credits the vault less than was sent
+    let transfer_from_user_cpi = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: input_token_account.to_account_info(),
+            to: input_token_vault.to_account_info(),
+            authority: ctx.accounts.user_authority.to_account_info(),
+            mint: input_token_mint.to_account_info(),
+        }
+    );
+    let input_amount = transfer_in(transfer_from_user_cpi, input_amount, input_token_mint.decimals)?;
+
+    let new_input_token_vault_amount = input_token_vault_amount.checked_add(input_amount)
+        .ok_or(CustomError::InvalidAmount)?;
+
+    let now = Clock::get()?.unix_timestamp;
+    swap_pool.accumulate_prices(token_a_vault.amount, token_b_vault.amount, now);
+
+    apply_swap_cooldown(
+        swap_pool,
+        ctx.accounts.swap_cooldown.as_mut(),
+        ctx.bumps.swap_cooldown,
+        ctx.accounts.user_authority.key(),
+        now,
+    )?;
+
+    // Exempt swappers pay no fee while exemptions are switched on for the pool
+    let fee_rate = if swap_pool.fee_exemptions_enabled && ctx.accounts.fee_exemption.is_some() {
+        0
+    } else {
+        swap_pool.fee_rate
+    };
+
+    // Same math as calculate_swap_result so a quote always matches the executed output
+    let (final_amount_to_redeem, fee_amount) = pool_swap_output(
+        swap_pool,
+        input_token_vault_amount,
+        redeem_token_vault_amount,
+        input_amount,
+        is_a_to_b,
+        fee_rate,
+        now,
+    )?;
+    // Dust inputs can round the output down to nothing; revert rather than keep the input for free
+    require!(final_amount_to_redeem > 0, CustomError::InvalidAmount);
+
+    let (volume_a, volume_b) = if is_a_to_b {
+        (input_amount, final_amount_to_redeem)
+    } else {
+        (final_amount_to_redeem, input_amount)
+    };
+    record_swap_volume(swap_pool, volume_a, volume_b, now)?;
+    swap_pool.swap_count = swap_pool.swap_count.checked_add(1).ok_or(CustomError::CalculationFailure)?;
+
+    // Part of the fee is burned from the output token, the rest is accrued as usual
+    let burn_amount = (fee_amount as u128)
+        .checked_mul(swap_pool.burn_fee_bps as u128)
+        .ok_or(CustomError::InvalidAmount)?
+        .checked_div(10000)
+        .ok_or(CustomError::InvalidAmount)? as u64;
+    let retained_fee = fee_amount.checked_sub(burn_amount).ok_or(CustomError::InvalidAmount)?;
+
+    if is_a_to_b {
+        swap_pool.lifetime_fees_b = swap_pool.lifetime_fees_b.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+    } else {
+        swap_pool.lifetime_fees_a = swap_pool.lifetime_fees_a.checked_add(fee_amount).ok_or(CustomError::InvalidAmount)?;
+    }
+
+    // Only the protocol's share of the retained fee leaves the LPs; the remainder never enters the
+    // counters, so it stays in the reserves and raises the value of every LP token
+    let protocol_fee = (retained_fee as u128)
+        .checked_mul(swap_pool.protocol_fee_share as u128)
+        .ok_or(CustomError::InvalidAmount)?
+        .checked_div(10000)
+        .ok_or(CustomError::InvalidAmount)? as u64;
+
+    // In real-time mode the fee is paid out below instead of accruing in the counters,
+    // and in auto-compound mode it stays in the reserves for LPs; otherwise it moves to the fee vault
+    if !swap_pool.realtime_fees && !swap_pool.auto_compound_fees {
+        if is_a_to_b {
+            swap_pool.total_fees_b = swap_pool.total_fees_b.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;   
+        } else {
+            swap_pool.total_fees_a = swap_pool.total_fees_a.checked_add(protocol_fee).ok_or(CustomError::InvalidAmount)?;
+        }
+    }
+
+    // Track fees per LP token so holders can compute earnings from accumulator deltas; only the part
+    // left with LPs counts, not the burned share or the protocol's cut
+    let lp_supply = ctx.accounts.lp_mint.supply;
+    if lp_supply > 0 {
+        let lp_fee = retained_fee.checked_sub(protocol_fee).ok_or(CustomError::CalculationFailure)?;
+        let fee_per_lp = (lp_fee as u128)
+            .checked_mul(FEES_PER_LP_SCALE)
+            .ok_or(CustomError::CalculationFailure)?
+            / lp_supply as u128;
+        if is_a_to_b {
+            swap_pool.fees_per_lp_b = swap_pool.fees_per_lp_b.checked_add(fee_per_lp).ok_or(CustomError::CalculationFailure)?;
+        } else {
+            swap_pool.fees_per_lp_a = swap_pool.fees_per_lp_a.checked_add(fee_per_lp).ok_or(CustomError::CalculationFailure)?;
+        }
+    }
+
+    if swap_pool.auto_compound_fees && lp_supply > 0 && retained_fee > 0 {
+        emit!(SwapFeeCompounded {
+            swap_pool: swap_pool.key(),
+            fee_mint: if is_a_to_b { swap_pool.token_b_mint } else { swap_pool.token_a_mint },
+            fee_amount: retained_fee,
+            value_per_lp_increase: (retained_fee as u128)
+                .checked_mul(FEES_PER_LP_SCALE)
+                .ok_or(CustomError::CalculationFailure)?
+                / lp_supply as u128,
+        });
+    }
+
+    // Force periodic collection once accrued fees reach the configured cap (0 = no cap)
+    if swap_pool.max_accrued_fees > 0 {
+        require!(
+            swap_pool.total_fees_a <= swap_pool.max_accrued_fees && swap_pool.total_fees_b <= swap_pool.max_accrued_fees,
+            CustomError::FeesMustBeCollected
+        );
+    }
+
+    // Everything leaving the output vault: the user's output, the burned fee and the protocol's share,
+    // whether paid out in real time or swept into the fee vault
+    let protocol_claim = if swap_pool.realtime_fees || !swap_pool.auto_compound_fees { protocol_fee } else { 0 };
+    let post_redeem_amount = redeem_token_vault_amount
+        .checked_sub(final_amount_to_redeem)
+        .and_then(|amount| amount.checked_sub(burn_amount))
+        .and_then(|amount| amount.checked_sub(protocol_claim))
+        .ok_or(CustomError::InvalidAmount)?;
+    let (reserve_a, reserve_b) = if is_a_to_b {
+        (new_input_token_vault_amount, post_redeem_amount)
+    } else {
+        (post_redeem_amount, new_input_token_vault_amount)
+    };
+    check_swap_limits(
+        swap_pool,
+        (input_token_vault_amount, redeem_token_vault_amount),
+        (input_amount, final_amount_to_redeem),
+        (token_a_vault.amount, token_b_vault.amount),
+        (reserve_a, reserve_b),
+    )?;
+    check_imbalance(
+        swap_pool,
+        (token_a_vault.amount, token_b_vault.amount),
+        (reserve_a, reserve_b),
+        token_a_mint.decimals,
+        token_b_mint.decimals,
+    )?;
+
+    require!(final_amount_to_redeem >= min_amount_out, CustomError::SlippageExceeded);
+
+    let seeds = &[
+        b"pool_authority".as_ref(),
+        swap_pool.token_a_mint.as_ref(),
+        swap_pool.token_b_mint.as_ref(),
+        swap_pool.fee_tier_seed(),
+        &[swap_pool.authority_bump],
+    ];
+    let signer = &[&seeds[..]];
+
+    let transfer_to_user_cpi = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: redeem_token_vault.to_account_info(),
+            to: redeem_token_account.to_account_info(),
+            authority: ctx.accounts.pool_authority.to_account_info(),
+            mint: redeem_token_mint.to_account_info(),
+        },
+        signer
+    );
+
+    transfer_checked(transfer_to_user_cpi, final_amount_to_redeem, redeem_token_mint.decimals)?;
+
+    // Pay the protocol fee straight to the configured destination for the output token
+    if swap_pool.realtime_fees && protocol_fee > 0 {
+        let fee_destination = if is_a_to_b {
+            ctx.accounts.fee_destination_b.as_ref()
+        } else {
+            ctx.accounts.fee_destination_a.as_ref()
+        }
+        .ok_or(CustomError::MissingFeeDestination)?;
+
+        let transfer_fee_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: redeem_token_vault.to_account_info(),
+                to: fee_destination.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: redeem_token_mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_fee_cpi, protocol_fee, redeem_token_mint.decimals)?;
+    }
+
+    // Accrued fees leave the reserves right away, so the main vaults never hold anything but liquidity
+    if !swap_pool.realtime_fees && !swap_pool.auto_compound_fees && protocol_fee > 0 {
+        let fee_vault = if is_a_to_b { &ctx.accounts.fee_vault_b } else { &ctx.accounts.fee_vault_a };
+        let transfer_fee_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TransferChecked {
+                from: redeem_token_vault.to_account_info(),
+                to: fee_vault.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+                mint: redeem_token_mint.to_account_info(),
+            },
+            signer
+        );
+        transfer_checked(transfer_fee_cpi, protocol_fee, redeem_token_mint.decimals)?;
+    }
+
+    // The vault is owned by the pool authority, so it can burn the fee share directly
+    if burn_amount > 0 {
+        let burn_cpi = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            Burn {
+                mint: redeem_token_mint.to_account_info(),
+                from: redeem_token_vault.to_account_info(),
+                authority: ctx.accounts.pool_authority.to_account_info(),
+            },
+            signer
+        );
+        burn(burn_cpi, burn_amount)?;
+    }
+
+    // Closing the wrapped-SOL output account unwraps it, output and rent alike, into the user's lamports
+    if unwrap_sol {
+        let close_cpi = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: redeem_token_account.to_account_info(),
+                destination: ctx.accounts.user_authority.to_account_info(),
+                authority: ctx.accounts.user_authority.to_account_info(),
+            },
+        );
+        close_account(close_cpi)?;
+    }
+
+    emit!(SwapExecuted {
+        swap_pool: swap_pool.key(),
+        user: ctx.accounts.user_authority.key(),
+        input_mint: input_token_mint.key(),
+        amount_in: input_amount,
+        amount_out: final_amount_to_redeem,
+        fee_amount,
+        reserve_a,
+        reserve_b,
+    });
+
+    swap_pool.in_progress = false;
+    Ok(final_amount_to_redeem)
+}
+
+// Loss in bps of the LP payout versus simply holding the deposited tokens, valued in token B
+// at the pre-withdrawal pool price; negative when fees outweighed the loss
+fn impermanent_loss_bps(
+    held_a: u64,
+    held_b: u64,
+    amount_a: u64,
+    amount_b: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+) -> Option<i64> {
+    if reserve_a == 0 {
+        return None;
+    }
+    let value_in_b = |a: u64, b: u64| a as u128 * reserve_b as u128 / reserve_a as u128 + b as u128;
+
+    let held_value = value_in_b(held_a, held_b) as i128;
+    let lp_value = value_in_b(amount_a, amount_b) as i128;
+    if held_value == 0 {
+        return None;
+    }
+    i64::try_from((held_value - lp_value) * 10000 / held_value).ok()
+}
+
+// Quote a single hop of a route, returning (amount_out, input_mint, output_mint)
+fn quote_hop(
+    pool_info: &AccountInfo,
+    input_vault_info: &AccountInfo,
+    output_vault_info: &AccountInfo,
+    amount_in: u64,
+) -> Result<(u64, Pubkey, Pubkey)> {
+    require!(pool_info.owner == &crate::ID, CustomError::InvalidSwapPool);
+    let swap_pool = {
+        let data = pool_info.try_borrow_data()?;
+        SwapPool::try_deserialize(&mut &data[..])?
+    };
+
+    // Vault keys are stored on the pool, so matching them is enough to trust the balances
+    let (input_key, output_key) = (input_vault_info.key(), output_vault_info.key());
+    let is_a_to_b = input_key == swap_pool.token_a_vault && output_key == swap_pool.token_b_vault;
+    let is_b_to_a = input_key == swap_pool.token_b_vault && output_key == swap_pool.token_a_vault;
+    require!(is_a_to_b || is_b_to_a, CustomError::InvalidSwapPool);
+
+    let input_vault = {
+        let data = input_vault_info.try_borrow_data()?;
+        TokenAccount::try_deserialize(&mut &data[..])?
+    };
+    let output_vault = {
+        let data = output_vault_info.try_borrow_data()?;
+        TokenAccount::try_deserialize(&mut &data[..])?
+    };
+
+    let (final_output_amount, _) = pool_swap_output(
+        &swap_pool,
+        input_vault.amount,
+        output_vault.amount,
+        amount_in,
+        is_a_to_b,
+        swap_pool.fee_rate,
+        Clock::get()?.unix_timestamp,
+    )?;
+
+    Ok((final_output_amount, input_vault.mint, output_vault.mint))
+}
+
+// Token A's weight in bps at `now` for an LBP pool, None for a standard pool
+// Moves linearly from lbp_start_weight to lbp_end_weight across the auction window
+fn lbp_weight_a(swap_pool: &SwapPool, now: i64) -> Option<u64> {
+    if swap_pool.lbp_end_ts <= swap_pool.lbp_start_ts {
+        return None;
+    }
+
+    let now = now.clamp(swap_pool.lbp_start_ts, swap_pool.lbp_end_ts);
+    let elapsed = (now - swap_pool.lbp_start_ts) as i128;
+    let duration = (swap_pool.lbp_end_ts - swap_pool.lbp_start_ts) as i128;
+    let start = swap_pool.lbp_start_weight as i128;
+    let end = swap_pool.lbp_end_weight as i128;
+
+    Some((start + (end - start) * elapsed / duration) as u64)
+}
+
+// Output for a swap against this pool, picking the weighted curve while an LBP is configured
+// fee_rate is passed separately so exempt swappers can be quoted at zero
+fn pool_swap_output(
+    swap_pool: &SwapPool,
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+    is_a_to_b: bool,
+    fee_rate: u64,
+    now: i64,
+) -> Result<(u64, u64)> {
+    if swap_pool.curve_type == CURVE_STABLE {
+        return compute_stable_swap_output(reserve_in, reserve_out, amount_in, swap_pool.amp, fee_rate);
+    }
+    match lbp_weight_a(swap_pool, now) {
+        None => compute_swap_output(reserve_in, reserve_out, amount_in, fee_rate),
+        Some(weight_a) => {
+            let weight_b = 10000 - weight_a;
+            let (weight_in, weight_out) = if is_a_to_b { (weight_a, weight_b) } else { (weight_b, weight_a) };
+            compute_weighted_swap_output(reserve_in, reserve_out, weight_in, weight_out, amount_in, fee_rate)
+        }
+    }
+}
+
+// Exact-output counterpart of pool_swap_output, solved on the plain constant-product curve only
+fn pool_swap_input(
+    swap_pool: &SwapPool,
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_out: u64,
+    fee_rate: u64,
+    now: i64,
+) -> Result<u64> {
+    require!(
+        swap_pool.curve_type == CURVE_CONSTANT_PRODUCT && lbp_weight_a(swap_pool, now).is_none(),
+        CustomError::InvalidSwapPool
+    );
+    compute_swap_input(reserve_in, reserve_out, amount_out, fee_rate)
+}
+
+// Weighted constant product: out = reserve_out * (1 - (reserve_in / (reserve_in + in)) ^ (w_in / w_out))
+// The power has no integer form, so it is evaluated in f64 and floored like every other output
+fn compute_weighted_swap_output(
+    reserve_in: u64,
+    reserve_out: u64,
+    weight_in: u64,
+    weight_out: u64,
+    amount_in: u64,
+    fee_rate: u64,
+) -> Result<(u64, u64)> {
+    require!(weight_in > 0 && weight_out > 0, CustomError::CalculationFailure);
+    require!(reserve_in > 0 && reserve_out > 0, CustomError::InsufficientLiquidity);
+
+    let ratio = reserve_in as f64 / (reserve_in as f64 + amount_in as f64);
+    let output_amount = (reserve_out as f64 * (1.0 - ratio.powf(weight_in as f64 / weight_out as f64))).floor();
+
+    // Never hand out the whole reserve, even if rounding in f64 says so
+    let output_amount = (output_amount as u64).min(reserve_out - 1) as u128;
+
+    let fee_amount = output_amount
+        .checked_mul(fee_rate as u128)
+        .ok_or(CustomError::CalculationFailure)?
+        / 10000;
+    let final_output_amount = output_amount
+        .checked_sub(fee_amount)
+        .ok_or(CustomError::CalculationFailure)?;
+
+    Ok((final_output_amount as u64, fee_amount as u64))
+}
+
+// Reserves paid out for burning lp_amount of total_lp_supply
+// Partial withdrawals round down, leaving the remainder with the LPs who stay; burning the whole
+// supply sweeps the vaults so rounding dust never outlives the last LP
+fn withdrawal_amounts(lp_amount: u64, total_lp_supply: u64, reserve_a: u64, reserve_b: u64) -> Result<(u64, u64)> {
+    require!(lp_amount <= total_lp_supply, CustomError::InsufficientFunds);
+    if lp_amount == total_lp_supply {
+        return Ok((reserve_a, reserve_b));
+    }
+
+    let share = |reserve: u64| -> Result<u64> {
+        Ok((lp_amount as u128)
+            .checked_mul(reserve as u128)
+            .ok_or(CustomError::CalculationFailure)?
+            .checked_div(total_lp_supply as u128)
+            .ok_or(CustomError::InsufficientLiquidity)? as u64)
+    };
+    Ok((share(reserve_a)?, share(reserve_b)?))
+}
+
+// Price of one unit of `base` in the `quote` token, scaled by 10^6
+// The priced token's reserve is the denominator, so that is the one that must be nonzero
+fn spot_price(base_reserve: u64, quote_reserve: u64) -> Result<u64> {
+    require!(base_reserve > 0, CustomError::InsufficientLiquidity);
+
+    let price = (quote_reserve as u128)
+        .checked_mul(1_000_000)
+        .ok_or(CustomError::CalculationFailure)?
+        / base_reserve as u128;
+    Ok(u64::try_from(price).map_err(|_| CustomError::CalculationFailure)?)
+}
+
+// Relative change in bps of the reserve ratio (B per A) between two reserve states
+fn reserve_ratio_change_bps(before: (u64, u64), after: (u64, u64)) -> Result<u128> {
+    require!(before.0 > 0 && after.0 > 0, CustomError::InsufficientLiquidity);
+
+    const RATIO_SCALE: u128 = 1_000_000_000_000;
+    let ratio = |(reserve_a, reserve_b): (u64, u64)| -> Result<u128> {
+        Ok((reserve_b as u128)
+            .checked_mul(RATIO_SCALE)
+            .ok_or(CustomError::CalculationFailure)?
+            / reserve_a as u128)
+    };
+    let ratio_before = ratio(before)?;
+    require!(ratio_before > 0, CustomError::InsufficientLiquidity);
+
+    Ok(ratio(after)?
+        .abs_diff(ratio_before)
+        .checked_mul(10000)
+        .ok_or(CustomError::CalculationFailure)?
+        / ratio_before)
+}
+
+// Gap in bps between the spot price and the execution price of amount_in -> amount_out
+fn price_impact_bps(reserve_in: u64, reserve_out: u64, amount_in: u64, amount_out: u64) -> Result<u64> {
+    require!(reserve_in > 0 && reserve_out > 0 && amount_in > 0, CustomError::InvalidAmount);
+
+    // execution / spot = (out / in) / (reserve_out / reserve_in)
+    let execution_over_spot_bps = (amount_out as u128)
+        .checked_mul(reserve_in as u128)
+        .and_then(|value| value.checked_mul(10000))
+        .ok_or(CustomError::CalculationFailure)?
+        / (amount_in as u128 * reserve_out as u128);
+
+    Ok(10000u128.saturating_sub(execution_over_spot_bps) as u64)
+}
+
+// Integer square root, rounded down (Newton's method from a starting guess at or above the root)
+fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let bits = 128 - n.leading_zeros();
+    let mut x = 1u128 << ((bits + 1) / 2);
+    let mut y = (x + n / x) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+// Constant-product output after the fee, returning (amount_out, fee_amount)
+// Shared by swap and every quote path; all divisions round down so a quote never overstates the output
+fn compute_swap_output(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+    fee_rate: u64,
+) -> Result<(u64, u64)> {
+    let new_reserve_in = (reserve_in as u128)
+        .checked_add(amount_in as u128)
+        .ok_or(CustomError::CalculationFailure)?;
+    let new_reserve_out = (reserve_in as u128)
+        .checked_mul(reserve_out as u128)
+        .ok_or(CustomError::CalculationFailure)?
+        .checked_div(new_reserve_in)
+        .ok_or(CustomError::CalculationFailure)?;
+    let output_amount = (reserve_out as u128)
+        .checked_sub(new_reserve_out)
+        .ok_or(CustomError::CalculationFailure)?;
+
+    let fee_amount = output_amount
+        .checked_mul(fee_rate as u128)
+        .ok_or(CustomError::CalculationFailure)?
+        / 10000;
+    let final_output_amount = output_amount
+        .checked_sub(fee_amount)
+        .ok_or(CustomError::CalculationFailure)?;
+
+    Ok((final_output_amount as u64, fee_amount as u64))
+}
+
+// StableSwap invariant D for two coins: A*n^n*(x+y) + D = A*n^n*D + D^(n+1) / (n^n*x*y), with n = 2,
+// solved by Newton's method as in Curve
+fn stable_swap_d(x: u128, y: u128, amp: u64) -> Option<u128> {
+    let sum = x.checked_add(y)?;
+    if sum == 0 {
+        return Some(0);
+    }
+    let ann = amp as u128 * 4;
+    let mut d = sum;
+    for _ in 0..255 {
+        let d_p = d.checked_mul(d)?.checked_div(x.checked_mul(2)?)?.checked_mul(d)?.checked_div(y.checked_mul(2)?)?;
+        let d_prev = d;
+        let numerator = ann.checked_mul(sum)?.checked_add(d_p.checked_mul(2)?)?.checked_mul(d)?;
+        let denominator = (ann - 1).checked_mul(d)?.checked_add(d_p.checked_mul(3)?)?;
+        d = numerator.checked_div(denominator)?;
+        if d.abs_diff(d_prev) <= 1 {
+            return Some(d);
+        }
+    }
+    None
+}
+
+// Balance of the other coin that keeps the invariant at D once this coin's balance is x
+fn stable_swap_y(x: u128, d: u128, amp: u64) -> Option<u128> {
+    let ann = amp as u128 * 4;
+    let c = d.checked_mul(d)?.checked_div(x.checked_mul(2)?)?.checked_mul(d)?.checked_div(ann.checked_mul(2)?)?;
+    let b = x.checked_add(d.checked_div(ann)?)?;
+    let mut y = d;
+    for _ in 0..255 {
+        let y_prev = y;
+        y = y.checked_mul(y)?.checked_add(c)?.checked_div(y.checked_mul(2)?.checked_add(b)?.checked_sub(d)?)?;
+        if y.abs_diff(y_prev) <= 1 {
+            return Some(y);
+        }
+    }
+    None
+}
+
+// Stable-curve counterpart of compute_swap_output, with the fee taken from the output the same way
+fn compute_stable_swap_output(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+    amp: u64,
+    fee_rate: u64,
+) -> Result<(u64, u64)> {
+    require!(reserve_in > 0 && reserve_out > 0, CustomError::InsufficientLiquidity);
+
+    let d = stable_swap_d(reserve_in as u128, reserve_out as u128, amp).ok_or(CustomError::CalculationFailure)?;
+    let new_reserve_in = (reserve_in as u128)
+        .checked_add(amount_in as u128)
+        .ok_or(CustomError::CalculationFailure)?;
+    let new_reserve_out = stable_swap_y(new_reserve_in, d, amp).ok_or(CustomError::CalculationFailure)?;
+
+    // One unit is held back so Newton's rounding never pays out in the trader's favour
+    let output_amount = (reserve_out as u128)
+        .checked_sub(new_reserve_out)
+        .and_then(|amount| amount.checked_sub(1))
+        .unwrap_or(0);
+
+    let fee_amount = output_amount
+        .checked_mul(fee_rate as u128)
+        .ok_or(CustomError::CalculationFailure)?
+        / 10000;
+    let final_output_amount = output_amount
+        .checked_sub(fee_amount)
+        .ok_or(CustomError::CalculationFailure)?;
+
+    Ok((final_output_amount as u64, fee_amount as u64))
+}
+
+// Smallest input for which compute_swap_output pays out at least amount_out
+fn compute_swap_input(
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_out: u64,
+    fee_rate: u64,
+) -> Result<u64> {
+    require!(amount_out > 0, CustomError::InvalidAmount);
+    require!(reserve_in > 0 && amount_out < reserve_out, CustomError::InsufficientLiquidity);
+    require!(fee_rate < 10000, CustomError::FeeTooHigh);
+
+    // Gross curve output whose fee-adjusted amount reaches amount_out; the fee is floored, so
+    // the ceiling estimate can fall one short and is nudged up
+    let fee_denominator = 10000 - fee_rate as u128;
+    let mut gross_output = (amount_out as u128 * 10000 + fee_denominator - 1) / fee_denominator;
+    while gross_output - gross_output * fee_rate as u128 / 10000 < amount_out as u128 {
+        gross_output += 1;
+    }
+    require!(gross_output < reserve_out as u128, CustomError::InsufficientLiquidity);
+
+    // reserve_in * reserve_out / (reserve_in + input) <= reserve_out - gross_output, rounded in the pool's favour
+    let remaining_out = reserve_out as u128 - gross_output;
+    let input = (reserve_in as u128)
+        .checked_mul(gross_output)
+        .and_then(|value| value.checked_add(remaining_out - 1))
+        .ok_or(CustomError::CalculationFailure)?
+        / remaining_out;
+
+    u64::try_from(input).map_err(|_| CustomError::CalculationFailure.into())
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct FeeExemption {
+    pub swap_pool: Pubkey,          // Pool the exemption applies to
+    pub user: Pubkey,               // Swapper who pays no fee
+    pub bump: u8,                   // Bump for PDA derivation
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SwapCooldown {
+    pub swap_pool: Pubkey,          // Pool the cooldown applies to
+    pub user: Pubkey,               // Swapper being throttled
+    pub last_swap_ts: i64,          // When the user last swapped in this pool
+    pub bump: u8,                   // Bump for PDA derivation
+}
+
+#[event]
+pub struct LiquidityRemoved {
+    pub swap_pool: Pubkey,
+    pub owner: Pubkey,
+    pub lp_amount: u64,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub impermanent_loss_bps: Option<i64>, // None when the LP has no entry snapshot
+    pub reserve_a: u64,             // Reserves after the withdrawal
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct LiquidityAdded {
+    pub swap_pool: Pubkey,
+    pub owner: Pubkey,
+    pub lp_amount: u64,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub reserve_a: u64,             // Reserves after the deposit
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct SwapExecuted {
+    pub swap_pool: Pubkey,
+    pub user: Pubkey,
+    pub input_mint: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,            // Whole fee in the output token, including any burned share
+    pub reserve_a: u64,             // Reserves after the swap
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub swap_pool: Pubkey,
+    pub collector: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub reserve_a: u64,             // Reserves after the collection
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct SwapFeeCompounded {
+    pub swap_pool: Pubkey,
+    pub fee_mint: Pubkey,
+    pub fee_amount: u64,
+    pub value_per_lp_increase: u128, // Scaled by FEES_PER_LP_SCALE
+}
+
+// Result of get_available_operations
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct AvailableOperations {
+    pub swap_a_to_b: bool,
+    pub swap_b_to_a: bool,
+    pub deposit: bool,            // add_liquidity from an existing LP holder
+    pub deposit_new_holder: bool, // add_liquidity from an address not yet counted against max_holders
+    pub withdraw: bool,
+}
+
+// Return data of swap
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct SwapResult {
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+// Return data of get_twap; the average price between two observations is
+// (cumulative_2 - cumulative_1) / (timestamp_2 - timestamp_1), using wrapping subtraction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct TwapObservation {
+    pub price_cumulative_a: u128,
+    pub price_cumulative_b: u128,
+    pub timestamp: i64,
+}
+
+// Return data of get_pool_metrics
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PoolMetrics {
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+    pub lp_supply: u64,
+    pub swap_count: u64,
+    pub liquidity_event_count: u64,
+}
+
+// Result of get_pool_config
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct PoolConfig {
+    pub fee_rate: u64,
+    pub is_paused: bool, // Any operation frozen; paused_ops on the pool says which
+    pub admin: Pubkey,
+    pub fee_authority: Pubkey,
+    pub curve_type: u8,
+    pub lp_decimals: u8,
+    pub token_a_mint: Pubkey,
+    pub token_b_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub token_a_vault: Pubkey,
+    pub token_b_vault: Pubkey,
+}
+
+// Return data of add_initial_liquidity, add_liquidity, remove_liquidity and the other withdrawals
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LiquidityResult {
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub lp_amount: u64, // LP minted or burned
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PoolDescription {
+    pub version: u8,
+    pub pool: SwapPool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PoolSnapshot {
+    pub version: u8,                // Pool layout version, tells consumers how to read `pool`
+    pub pool: SwapPool,
+    pub reserve_a: u64,             // Live vault balances
+    pub reserve_b: u64,
+    pub lp_supply: u64,
+    pub slot: u64,                  // When the snapshot was taken
+    pub timestamp: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SwapPool {
+    pub token_a_mint: Pubkey,       // Mint address of token A
+    pub token_b_mint: Pubkey,       // Mint address of token B
+    pub token_a_vault: Pubkey,      // Vault holding token A liquidity
+    pub token_b_vault: Pubkey,      // Vault holding token B liquidity
+    pub lp_mint: Pubkey,            // Mint for LP tokens
+    pub pool_authority: Pubkey,     // PDA with authority over vaults
+    pub fee_rate: u64,              // Fee taken on swaps (basis points)
+    pub bump: u8,                   // Legacy pool_authority bump, superseded by authority_bump
+    pub paused_ops: u8,             // PAUSE_* bits for the operations frozen by the admin; took over is_paused's byte
+    pub admin: Pubkey,              // Admin address that can pause/unpause
+    pub total_fees_a: u64,          // Accumulated fees in token A
+    pub total_fees_b: u64,          // Accumulated fees in token B
+    pub burn_fee_bps: u64,          // Portion of each swap fee burned (basis points of the fee)
+    pub tick_spacing: u16,          // Tick spacing price range bounds align to (0 = no price range)
+    pub max_accrued_fees: u64,      // Cap on uncollected fees per token before swaps halt (0 = no cap)
+    pub min_price: u64,             // Lower bound on price of A in B, scaled by 10^6 (0 = unbounded)
+    pub max_price: u64,             // Upper bound on price of A in B, scaled by 10^6 (0 = unbounded)
+    pub balance_incentive_bps: u64, // Max LP bonus/penalty for deposits moving reserves toward/away from balance (0 = off)
+    pub fees_per_lp_a: u128,        // Cumulative token A fees per LP token, scaled by FEES_PER_LP_SCALE
+    pub fees_per_lp_b: u128,        // Cumulative token B fees per LP token, scaled by FEES_PER_LP_SCALE
+    pub holder_count: u32,          // Distinct addresses currently holding LP from deposits
+    pub max_holders: u32,           // Cap on holder_count (0 = no cap)
+    pub fee_destination_a: Pubkey,  // Receives token A fees when realtime_fees is on
+    pub fee_destination_b: Pubkey,  // Receives token B fees when realtime_fees is on
+    pub realtime_fees: bool,        // Transfer fees out on every swap instead of accruing them
+    pub max_output_fraction_bps: u64, // Max share of the destination reserve one swap may take (10000 = no limit)
+    pub version: u8,                // Layout version, 0 for pools resized from before versioning
+    pub lbp_start_weight: u64,      // Token A weight (bps) when the auction opens
+    pub lbp_end_weight: u64,        // Token A weight (bps) when the auction closes
+    pub lbp_start_ts: i64,          // Auction window start (0 with lbp_end_ts = standard pool)
+    pub lbp_end_ts: i64,            // Auction window end; weights stay at lbp_end_weight afterwards
+    pub spike_bucket_secs: i64,     // Length of one volume bucket, 0 = spike breaker disabled
+    pub spike_multiple: u64,        // Halt when a bucket exceeds this multiple of the average
+    pub volume_buckets: [u64; 8],   // Rolling token A volume per bucket
+    pub volume_bucket_start: i64,   // Start time of the current bucket
+    pub volume_bucket_head: u8,     // Index of the current bucket
+    pub volume_halted: bool,        // Set by the breaker, cleared by the admin
+    pub auto_compound_fees: bool,   // Leave retained swap fees in the reserves for LPs instead of the fee counters
+    pub min_initial_lp: u64,        // Smallest LP amount add_initial_liquidity may mint
+    pub fee_exemptions_enabled: bool, // Whether FeeExemption accounts waive the swap fee
+    pub reserve_checkpoint_a: u64,  // Token A reserve when the admin last set a checkpoint
+    pub reserve_checkpoint_b: u64,  // Token B reserve when the admin last set a checkpoint
+    pub checkpoint_ts: i64,         // When the checkpoint was taken, 0 = never
+    pub protocol_max_slippage_bps: u64, // Max price impact any swap may have (10000 = disabled)
+    pub cumulative_volume_a: u64,   // All-time token A swapped through the pool, in or out
+    pub cumulative_volume_b: u64,   // All-time token B swapped through the pool, in or out
+    pub max_imbalance_bps: u64,     // Past this reserve imbalance only balancing deposits/swaps are allowed (0 = off)
+    pub protocol_fee_share: u64,    // Share of the retained swap fee (bps) owed to the protocol; the rest stays with LPs
+    pub lifetime_fees_a: u64,       // All swap fees ever charged in token A, whoever they went to
+    pub lifetime_fees_b: u64,       // All swap fees ever charged in token B, whoever they went to
+    pub vault_a_bump: u8,           // Bump of the token A vault PDA
+    pub vault_b_bump: u8,           // Bump of the token B vault PDA
+    pub authority_bump: u8,         // Bump of the pool_authority PDA, used in every authority seeds block
+    pub price_cumulative_a: u128,   // Sum of (price of A in B, scaled by 10^6) * seconds, wrapping on overflow
+    pub price_cumulative_b: u128,   // Sum of (price of B in A, scaled by 10^6) * seconds, wrapping on overflow
+    pub last_price_update: i64,     // When the price accumulators were last advanced, 0 = never
+    pub curve_type: u8,             // Pricing curve, CURVE_CONSTANT_PRODUCT or CURVE_STABLE; fixed at creation
+    pub amp: u64,                   // StableSwap amplification coefficient A, only used by the stable curve
+    pub max_price_impact_bps: u64,  // Max relative move of the reserve ratio in one swap (0 = off)
+    pub swap_cooldown_secs: u32,    // Minimum gap between two swaps by the same user (0 = off)
+    pub swap_count: u64,            // Swaps executed against the pool
+    pub liquidity_event_count: u64, // Deposits and withdrawals, the initial deposit included
+    pub in_progress: bool,          // Set while a swap or deposit/withdrawal is executing, guards against re-entry
+    pub fee_authority: Pubkey,      // Signer allowed to collect protocol fees, the admin unless delegated
+    pub fee_tier: u8,               // Index into FEE_TIERS chosen at creation, part of the pool_authority seeds (LEGACY_FEE_TIER: none)
+    pub fee_vault_a: Pubkey,        // Holds accrued token A fees apart from the reserves until collected
+    pub fee_vault_b: Pubkey,        // Holds accrued token B fees apart from the reserves until collected
+    pub range_lower_tick: i32,      // Inclusive lower tick swaps may move the price to (0/0 = full range)
+    pub range_upper_tick: i32,      // Exclusive upper tick swaps may move the price to
+    pub lp_decimals: u8,            // LP mint decimals chosen at creation (0 on pools from before v26; read the mint)
+}
+
+impl SwapPool {
+    // Whether any of the PAUSE_* bits in `ops` is frozen
+    pub fn is_paused(&self, ops: u8) -> bool {
+        self.paused_ops & ops != 0
+    }
+
+    // Whether set_price_range has confined the pool to a price range
+    pub fn has_price_range(&self) -> bool {
+        self.range_lower_tick != 0 || self.range_upper_tick != 0
+    }
+
+    // Price accumulators as they would stand at `now`, given the reserves held since the last update
+    pub fn cumulative_prices_at(&self, reserve_a: u64, reserve_b: u64, now: i64) -> (u128, u128) {
+        if self.last_price_update == 0 || reserve_a == 0 || reserve_b == 0 {
+            return (self.price_cumulative_a, self.price_cumulative_b);
+        }
+        let elapsed = now.saturating_sub(self.last_price_update).max(0) as u128;
+        let price_a = reserve_b as u128 * 1_000_000 / reserve_a as u128;
+        let price_b = reserve_a as u128 * 1_000_000 / reserve_b as u128;
+        (
+            self.price_cumulative_a.wrapping_add(price_a.wrapping_mul(elapsed)),
+            self.price_cumulative_b.wrapping_add(price_b.wrapping_mul(elapsed)),
+        )
+    }
+
+    // Fill in the fields added between the stored version and new_version, which a resize left zeroed
+    // Fields whose zero already means "off" or "none" are left alone
+    pub fn migrate_to(&mut self, new_version: u8) {
+        if self.version < 5 && new_version >= 5 && self.min_initial_lp == 0 {
+            self.min_initial_lp = DEFAULT_MIN_INITIAL_LP;
+        }
+        // Before the split the protocol kept the whole retained fee
+        if self.version < 11 && new_version >= 11 && self.protocol_fee_share == 0 {
+            self.protocol_fee_share = 10000;
+        }
+        // Pools from before the split kept the authority bump only in `bump`
+        if self.version < 14 && new_version >= 14 {
+            self.authority_bump = self.bump;
+        }
+        // A paused pool stored `true` in this byte, which now reads as swaps only
+        if self.version < 19 && new_version >= 19 && self.paused_ops != 0 {
+            self.paused_ops = PAUSE_ALL;
+        }
+        // Fees were always collected by the admin before fee_authority existed
+        if self.version < 22 && new_version >= 22 {
+            self.fee_authority = self.admin;
+        }
+        // The authority of a pool from before fee tiers was derived without one, so it stays on the legacy seeds
+        if self.version < 23 && new_version >= 23 {
+            self.fee_tier = LEGACY_FEE_TIER;
+        }
+        self.version = new_version;
+    }
+
+    // The tier part of the pool_authority seeds, empty for a legacy pool. An empty seed adds nothing
+    // to the derivation, so legacy pools keep the authority they were created with
+    pub fn fee_tier_seed(&self) -> &[u8] {
+        if self.fee_tier == LEGACY_FEE_TIER {
+            &[]
+        } else {
+            std::slice::from_ref(&self.fee_tier)
+        }
+    }
+
+    // Count one deposit or withdrawal for get_pool_metrics
+    pub fn record_liquidity_event(&mut self) -> Result<()> {
+        self.liquidity_event_count = self.liquidity_event_count.checked_add(1).ok_or(CustomError::CalculationFailure)?;
+        Ok(())
+    }
+
+    // Advance the accumulators to `now` at the pre-trade price, so a trade can't weight its own price
+    pub fn accumulate_prices(&mut self, reserve_a: u64, reserve_b: u64, now: i64) {
+        let (price_cumulative_a, price_cumulative_b) = self.cumulative_prices_at(reserve_a, reserve_b, now);
+        self.price_cumulative_a = price_cumulative_a;
+        self.price_cumulative_b = price_cumulative_b;
+        self.last_price_update = now;
+    }
+
+    // Add token A volume to the rolling buckets, returning true when the current bucket
+    // exceeds spike_multiple times the average of the earlier non-empty buckets
+    pub fn record_volume(&mut self, volume_a: u64, now: i64) -> Result<bool> {
+        if self.spike_bucket_secs <= 0 {
+            return Ok(false);
+        }
+
+        let bucket_count = self.volume_buckets.len();
+        if self.volume_bucket_start == 0 {
+            self.volume_bucket_start = now;
+        }
+        let elapsed_buckets = ((now - self.volume_bucket_start) / self.spike_bucket_secs).max(0);
+        for _ in 0..elapsed_buckets.min(bucket_count as i64) {
+            self.volume_bucket_head = ((self.volume_bucket_head as usize + 1) % bucket_count) as u8;
+            self.volume_buckets[self.volume_bucket_head as usize] = 0;
+        }
+        self.volume_bucket_start += elapsed_buckets * self.spike_bucket_secs;
+
+        let head = self.volume_bucket_head as usize;
+        self.volume_buckets[head] = self.volume_buckets[head].saturating_add(volume_a);
+
+        let (history_total, history_buckets) = self.volume_buckets
+            .iter()
+            .enumerate()
+            .filter(|(index, volume)| *index != head && **volume > 0)
+            .fold((0u128, 0u128), |(total, count), (_, volume)| (total + *volume as u128, count + 1));
+        if history_buckets == 0 {
+            return Ok(false);
+        }
+
+        let average = history_total / history_buckets;
+        Ok(self.volume_buckets[head] as u128 > average.saturating_mul(self.spike_multiple as u128))
+    }
+
+    // Count the owner as a holder the first time they receive LP, enforcing the cap
+    pub fn register_lp_holder(&mut self, lp_holder: &mut LpHolder) -> Result<()> {
+        if lp_holder.is_holder {
+            return Ok(());
+        }
+
+        require!(
+            self.max_holders == 0 || self.holder_count < self.max_holders,
+            CustomError::HolderCapExceeded
+        );
+        self.holder_count = self.holder_count.checked_add(1).ok_or(CustomError::CalculationFailure)?;
+        lp_holder.is_holder = true;
+        Ok(())
+    }
+
+    // Stop counting the owner once they have fully exited
+    pub fn release_lp_holder(&mut self, lp_holder: &mut LpHolder) -> Result<()> {
+        if !lp_holder.is_holder {
+            return Ok(());
+        }
+
+        self.holder_count = self.holder_count.checked_sub(1).ok_or(CustomError::CalculationFailure)?;
+        lp_holder.is_holder = false;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct LpHolder {
+    pub swap_pool: Pubkey,          // Pool the flag belongs to
+    pub owner: Pubkey,              // Address being tracked
+    pub is_holder: bool,            // Whether the owner currently counts towards holder_count
+    pub bump: u8,                   // Bump for PDA derivation
+    pub fees_per_lp_a_checkpoint: u128, // swap_pool.fees_per_lp_a at the last deposit/withdrawal
+    pub fees_per_lp_b_checkpoint: u128, // swap_pool.fees_per_lp_b at the last deposit/withdrawal
+    pub earned_fees_a: u64,         // Token A fees settled at previous checkpoints
+    pub earned_fees_b: u64,         // Token B fees settled at previous checkpoints
+    pub entry_amount_a: u64,        // Token A deposited for the LP counted in entry_lp
+    pub entry_amount_b: u64,        // Token B deposited for the LP counted in entry_lp
+    pub entry_lp: u64,              // LP minted by those deposits, 0 = no entry snapshot
+}
+
+impl LpHolder {
+    // Fees earned by lp_balance since the last checkpoint, on top of what was already settled
+    pub fn pending_fees(&self, swap_pool: &SwapPool, lp_balance: u64) -> Result<(u64, u64)> {
+        let pending = |current: u128, checkpoint: u128, earned: u64| -> Result<u64> {
+            let accrued = current
+                .saturating_sub(checkpoint)
+                .checked_mul(lp_balance as u128)
+                .ok_or(CustomError::CalculationFailure)?
+                / FEES_PER_LP_SCALE;
+            let accrued = u64::try_from(accrued).map_err(|_| CustomError::CalculationFailure)?;
+            earned.checked_add(accrued).ok_or(CustomError::CalculationFailure.into())
+        };
+
+        Ok((
+            pending(swap_pool.fees_per_lp_a, self.fees_per_lp_a_checkpoint, self.earned_fees_a)?,
+            pending(swap_pool.fees_per_lp_b, self.fees_per_lp_b_checkpoint, self.earned_fees_b)?,
+        ))
+    }
+
+    // Add a deposit to the entry snapshot used for impermanent loss reporting
+    pub fn record_entry(&mut self, amount_a: u64, amount_b: u64, lp_minted: u64) -> Result<()> {
+        self.entry_amount_a = self.entry_amount_a.checked_add(amount_a).ok_or(CustomError::CalculationFailure)?;
+        self.entry_amount_b = self.entry_amount_b.checked_add(amount_b).ok_or(CustomError::CalculationFailure)?;
+        self.entry_lp = self.entry_lp.checked_add(lp_minted).ok_or(CustomError::CalculationFailure)?;
+        Ok(())
+    }
+
+    // Remove lp_amount from the snapshot, returning the tokens originally deposited for it
+    // None when there is no snapshot or it doesn't cover the burn (e.g. LP received by transfer)
+    pub fn take_entry(&mut self, lp_amount: u64) -> Option<(u64, u64)> {
+        if self.entry_lp == 0 || lp_amount > self.entry_lp {
+            self.clear_entry();
+            return None;
+        }
+
+        let share = |amount: u64| (amount as u128 * lp_amount as u128 / self.entry_lp as u128) as u64;
+        let (held_a, held_b) = (share(self.entry_amount_a), share(self.entry_amount_b));
+        self.entry_amount_a -= held_a;
+        self.entry_amount_b -= held_b;
+        self.entry_lp -= lp_amount;
+        Some((held_a, held_b))
+    }
+
+    pub fn clear_entry(&mut self) {
+        self.entry_amount_a = 0;
+        self.entry_amount_b = 0;
+        self.entry_lp = 0;
+    }
+
+    // Settle earnings on the balance held so far, call before that balance changes
+    pub fn checkpoint_fees(&mut self, swap_pool: &SwapPool, lp_balance: u64) -> Result<()> {
+        let (earned_a, earned_b) = self.pending_fees(swap_pool, lp_balance)?;
+        self.earned_fees_a = earned_a;
+        self.earned_fees_b = earned_b;
+        self.fees_per_lp_a_checkpoint = swap_pool.fees_per_lp_a;
+        self.fees_per_lp_b_checkpoint = swap_pool.fees_per_lp_b;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct LpSupplySnapshot {
+    pub timestamp: i64,
+    pub lp_supply: u64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct LpSupplyHistory {
+    pub swap_pool: Pubkey,                                  // Pool whose LP supply is tracked
+    pub head: u16,                                          // Index the next snapshot is written to
+    pub len: u16,                                           // Number of valid snapshots
+    pub snapshots: [LpSupplySnapshot; 32],                  // Ring buffer of snapshots
+    pub bump: u8,                                           // Bump for PDA derivation
+}
+
+impl LpSupplyHistory {
+    // Push a snapshot, overwriting the oldest once the buffer is full
+    pub fn record(&mut self, timestamp: i64, lp_supply: u64) {
+        let capacity = self.snapshots.len();
+        self.snapshots[self.head as usize] = LpSupplySnapshot { timestamp, lp_supply };
+        self.head = ((self.head as usize + 1) % capacity) as u16;
+        if (self.len as usize) < capacity {
+            self.len += 1;
+        }
+    }
+
+    pub fn snapshots_in_order(&self) -> Vec<LpSupplySnapshot> {
+        let capacity = self.snapshots.len();
+        let start = (self.head as usize + capacity - self.len as usize) % capacity;
+        (0..self.len as usize)
+            .map(|i| self.snapshots[(start + i) % capacity])
+            .collect()
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct NftPosition {
+    pub swap_pool: Pubkey,          // Pool this position belongs to
+    pub position_mint: Pubkey,      // 1-of-1 mint representing the position
+    pub liquidity: u64,             // LP tokens escrowed for the position
+    pub bump: u8,                   // Bump for PDA derivation
+}
+
+#[derive(Accounts)]
+#[instruction(fee_rate: u64, curve_type: u8, amp: u64, lp_decimals: Option<u8>)]
+pub struct InitializePool<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + SwapPool::INIT_SPACE,
+    )]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    // Vaults are created here as PDAs of the pool authority, so no caller-supplied account can stand in for them
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"token_vault".as_ref(), pool_authority.key().as_ref(), token_a_mint.key().as_ref()],
+        bump,
+        token::mint = token_a_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"token_vault".as_ref(), pool_authority.key().as_ref(), token_b_mint.key().as_ref()],
+        bump,
+        token::mint = token_b_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Accrued protocol fees are moved here at swap time, keeping the vaults above pure reserves
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"fee_vault".as_ref(), pool_authority.key().as_ref(), token_a_mint.key().as_ref()],
+        bump,
+        token::mint = token_a_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"fee_vault".as_ref(), pool_authority.key().as_ref(), token_b_mint.key().as_ref()],
+        bump,
+        token::mint = token_b_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = admin,
+        mint::decimals = lp_decimals.unwrap_or(token_a_mint.decimals.max(token_b_mint.decimals)),
+        mint::authority = pool_authority,
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: PDA that will have authority over the token vaults, checked against the canonical derivation in the handler
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddInitialLiquidity<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key(),
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key(),
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.mint == swap_pool.token_a_mint,
+        constraint = user_token_a.owner == user_authority.key(),
+    )]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_b.mint == swap_pool.token_b_mint,
+        constraint = user_token_b.owner == user_authority.key(),
+    )]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = lp_mint.key() == swap_pool.lp_mint,
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        associated_token::mint = lp_mint,
+        associated_token::authority = user_authority,
+    )]
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+        ],
+        bump = swap_pool.authority_bump
+    )]
+    /// CHECK: This is a PDA used as the authority
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+
+    // Receives MINIMUM_LIQUIDITY on the first deposit. A PDA of its own rather than the pool authority's
+    // LP ATA, which escrows NFT positions and so can't tell locked liquidity from live liquidity
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        seeds = [b"locked_lp".as_ref(), pool_authority.key().as_ref(), lp_mint.key().as_ref()],
+        bump,
+        token::mint = lp_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+    )]
+    pub locked_lp_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = 8 + LpHolder::INIT_SPACE,
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+// Account order is part of the CPI interface for swap and swap_cpi, append new accounts at the end
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    // Mutable so the burned share of the fee can reduce the output mint's supply
+    #[account(
+        mut,
+        constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken,
+    )]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken,
+    )]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key(),
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key(),
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Accrued protocol fees are swept here as they are charged
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.mint == swap_pool.token_a_mint,
+        constraint = user_token_a.owner == user_authority.key(),
+    )]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_b.mint == swap_pool.token_b_mint,
+        constraint = user_token_b.owner == user_authority.key(),
+    )]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = lp_mint.key() == swap_pool.lp_mint
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+
+    // Only required when the pool pays fees out in real time
+    #[account(
+        mut,
+        constraint = fee_destination_a.key() == swap_pool.fee_destination_a,
+        constraint = fee_destination_a.mint == swap_pool.token_a_mint,
+    )]
+    pub fee_destination_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = fee_destination_b.key() == swap_pool.fee_destination_b,
+        constraint = fee_destination_b.mint == swap_pool.token_b_mint,
+    )]
+    pub fee_destination_b: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+
+    // Present only for exempt swappers; waives the fee while exemptions are enabled
+    #[account(
+        seeds = [b"fee_exemption".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump = fee_exemption.bump,
+    )]
+    pub fee_exemption: Option<Account<'info, FeeExemption>>,
+
+    // Only required while the pool enforces a swap cooldown
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = 8 + SwapCooldown::INIT_SPACE,
+        seeds = [b"cooldown".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump,
+    )]
+    pub swap_cooldown: Option<Account<'info, SwapCooldown>>,
+}
+
+#[derive(Accounts)]
+pub struct SwapProtocol<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key(),
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key(),
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = admin_token_a.mint == swap_pool.token_a_mint,
+        constraint = admin_token_a.owner == admin.key(),
+    )]
+    pub admin_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = admin_token_b.mint == swap_pool.token_b_mint,
+        constraint = admin_token_b.owner == admin.key(),
+    )]
+    pub admin_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    // Protocol-owned destinations for the swap output
+    #[account(
+        mut,
+        constraint = protocol_token_a.mint == swap_pool.token_a_mint,
+    )]
+    pub protocol_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = protocol_token_b.mint == swap_pool.token_b_mint,
+    )]
+    pub protocol_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FlashSwap<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key(),
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key(),
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Accrued protocol fees are swept here once the flash fee is repaid
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    // Where the borrowed output is sent; only the one matching the direction is used
+    #[account(
+        mut,
+        constraint = receiver_token_a.mint == swap_pool.token_a_mint,
+    )]
+    pub receiver_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = receiver_token_b.mint == swap_pool.token_b_mint,
+    )]
+    pub receiver_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
+
+    pub user_authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    // Only required when the pool pays fees out in real time
+    #[account(
+        mut,
+        constraint = fee_destination_a.key() == swap_pool.fee_destination_a,
+        constraint = fee_destination_a.mint == swap_pool.token_a_mint,
+    )]
+    pub fee_destination_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = fee_destination_b.key() == swap_pool.fee_destination_b,
+        constraint = fee_destination_b.mint == swap_pool.token_b_mint,
+    )]
+    pub fee_destination_b: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key(),
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key(),
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_a.mint == swap_pool.token_a_mint,
+        constraint = user_token_a.owner == user_authority.key(),
+    )]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = user_token_b.mint == swap_pool.token_b_mint,
+        constraint = user_token_b.owner == user_authority.key(),
+    )]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = lp_mint.key() == swap_pool.lp_mint
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        associated_token::mint = lp_mint,
+        associated_token::authority = user_authority,
+    )]
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = 8 + LpHolder::INIT_SPACE,
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+    
+    // Mutable so remove_liquidity_single can burn its share of the conversion fee
+    #[account(
+        mut,
+        constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken,
+    )]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken,
+    )]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Receive the accrued share of remove_liquidity_single's conversion fee
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_a.mint == swap_pool.token_a_mint,
+        constraint = user_token_a.owner == user_authority.key()
+    )]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = user_token_b.mint == swap_pool.token_b_mint,
+        constraint = user_token_b.owner == user_authority.key()
+    )]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        mut,
+        constraint = lp_mint.key() == swap_pool.lp_mint
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+    
+    #[account(
+        mut,
+        constraint = user_lp_token.mint == lp_mint.key(),
+        constraint = user_lp_token.owner == user_authority.key()
+    )]
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
+
+    // Created on demand so holders who received LP by transfer can still withdraw
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = 8 + LpHolder::INIT_SPACE,
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+
+    // Only required by remove_liquidity_single while the pool enforces a swap cooldown
+    #[account(
+        init_if_needed,
+        payer = user_authority,
+        space = 8 + SwapCooldown::INIT_SPACE,
+        seeds = [b"cooldown".as_ref(), swap_pool.key().as_ref(), user_authority.key().as_ref()],
+        bump,
+    )]
+    pub swap_cooldown: Option<Account<'info, SwapCooldown>>,
+
+    // Only required by remove_liquidity_single when the pool pays fees out in real time
+    #[account(
+        mut,
+        constraint = fee_destination_a.key() == swap_pool.fee_destination_a,
+        constraint = fee_destination_a.mint == swap_pool.token_a_mint,
+    )]
+    pub fee_destination_a: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = fee_destination_b.key() == swap_pool.fee_destination_b,
+        constraint = fee_destination_b.mint == swap_pool.token_b_mint,
+    )]
+    pub fee_destination_b: Option<InterfaceAccount<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Collected fees are paid out of these rather than the reserves
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub fee_collector: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = fee_collector_token_a.mint == swap_pool.token_a_mint,
+        constraint = fee_collector_token_a.owner == fee_collector.key()
+    )]
+    pub fee_collector_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_collector_token_b.mint == swap_pool.token_b_mint,
+        constraint = fee_collector_token_b.owner == fee_collector.key()
+    )]
+    pub fee_collector_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+        ],
+        bump = swap_pool.authority_bump
+    )]
+    /// CHECK: This is a PDA used as the authority
+    pub pool_authority: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePool<'info> {
+    #[account(mut, close = admin)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Uncollected fees are swept to the admin along with the reserves
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = lp_mint.key() == swap_pool.lp_mint
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    // Absent for a pool that was never seeded
+    #[account(
+        mut,
+        seeds = [b"locked_lp".as_ref(), pool_authority.key().as_ref(), lp_mint.key().as_ref()],
+        bump,
+    )]
+    pub locked_lp_token: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    // NFT position escrow, absent if no position was ever opened
+    #[account(
+        mut,
+        associated_token::mint = lp_mint,
+        associated_token::authority = pool_authority,
+    )]
+    pub position_lp_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = admin_token_a.mint == swap_pool.token_a_mint,
+    )]
+    pub admin_token_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = admin_token_b.mint == swap_pool.token_b_mint,
+    )]
+    pub admin_token_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+        ],
+        bump = swap_pool.authority_bump
+    )]
+    /// CHECK: This is a PDA used as the authority
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CollectFeesAsLp<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    // Fees move from here into the reserves above
+    #[account(
+        mut,
+        constraint = fee_vault_a.key() == swap_pool.fee_vault_a @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_vault_b.key() == swap_pool.fee_vault_b @ CustomError::InvalidToken,
+    )]
+    pub fee_vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = lp_mint.key() == swap_pool.lp_mint
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = fee_collector,
+        associated_token::mint = lp_mint,
+        associated_token::authority = fee_collector,
+    )]
+    pub fee_collector_lp_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+        ],
+        bump = swap_pool.authority_bump
+    )]
+    /// CHECK: This is a PDA used as the authority
+    pub pool_authority: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub fee_collector: Signer<'info>,
+
+    // The collector is an LP like any other, so it shares the holder cap and fee checkpoints
+    #[account(
+        init_if_needed,
+        payer = fee_collector,
+        space = 8 + LpHolder::INIT_SPACE,
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), fee_collector.key().as_ref()],
+        bump
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeLpHistory<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + LpSupplyHistory::INIT_SPACE,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump
+    )]
+    pub lp_history: Account<'info, LpSupplyHistory>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetLpSupplyHistory<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Account<'info, LpSupplyHistory>,
+}
+
+#[derive(Accounts)]
+pub struct RescueTokens<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
 
-        let new_source_amount = source_amount.checked_add(amount_in).ok_or(error::CustomError::CalculationFailure)?;
+    pub mint: InterfaceAccount<'info, Mint>,
 
-        let constant_product = source_amount.checked_mul(destination_amount).ok_or(error::CustomError::CalculationFailure)?;
+    // Any account the pool authority owns; rescue_tokens itself rejects the pool's own mints
+    #[account(
+        mut,
+        constraint = source.mint == mint.key() @ CustomError::InvalidToken,
+        constraint = source.owner == pool_authority.key() @ CustomError::InvalidToken,
+    )]
+    pub source: InterfaceAccount<'info, TokenAccount>,
 
-        let new_destination_amount = constant_product.checked_div(new_source_amount).ok_or(error::CustomError::CalculationFailure)?;
+    #[account(
+        mut,
+        constraint = destination.mint == mint.key() @ CustomError::InvalidToken,
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
 
-        let output_amount = destination_amount.checked_sub(new_destination_amount).ok_or(error::CustomError::CalculationFailure)?;
+    #[account(
+        constraint = pool_authority.key() == swap_pool.pool_authority
+    )]
+    /// CHECK: Matched against the authority stored on the pool; seeds are only needed for signing
+    pub pool_authority: UncheckedAccount<'info>,
 
-        let fee_amount = output_amount.checked_mul(swap_pool.fee_rate).ok_or(CustomError::CalculationFailure)?.checked_div(10000).ok_or(CustomError::CalculationFailure)?;
+    pub admin: Signer<'info>,
 
-        let final_output_amount = output_amount.checked_sub(fee_amount).ok_or(CustomError::CalculationFailure)?;
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
-        Ok(final_output_amount)
-    }
+#[derive(Accounts)]
+pub struct SetPriceRange<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
 
-    // Function to get the latest trade volume (could be expanded with more tracking in SwapPool)
-    pub fn get_pool_volume(_ctx: Context<GetPoolStats>) -> Result<(u64, u64)> {
-        // This would need additional state tracking in the SwapPool account
-        // For now, returns zeros as placeholder
-        // To implement properly, add volume tracking to the SwapPool struct
-        // and update it in the swap function
-        Ok((0, 0))
-    }
+    // Read only to check the current price against the new range
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
 
-    pub fn get_user_pool_share(ctx: Context<GetUserShare>) -> Result<(u64, u64, u64)> {
-        let token_a_vault_amount = ctx.accounts.token_a_vault.amount;
-        let token_b_vault_amount = ctx.accounts.token_b_vault.amount;
-        let lp_total_supply = ctx.accounts.lp_mint.supply;
-        let user_lp_balance = ctx.accounts.user_lp_token.amount;
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
 
-        // Calculate user's share in percentage (scaled by 10^6 for precision)
-        let user_share_percentage = if lp_total_supply == 0 {
-            0
-        } else {
-            (user_lp_balance as u128)
-                .checked_mul(1_000_000)
-                .unwrap()
-                .checked_div(lp_total_supply as u128)
-                .unwrap() as u64
-        };
+    pub admin: Signer<'info>,
+}
 
-        // Calculate user's share of tokens
-        let user_token_a_share = if lp_total_supply == 0 {
-            0
-        } else {
-            (user_lp_balance as u128)
-                .checked_mul(token_a_vault_amount as u128)
-                .unwrap()
-                .checked_div(lp_total_supply as u128)
-                .unwrap() as u64
-        };
-        let user_token_b_share = if lp_total_supply == 0 {
-            0
-        } else {
-            (user_lp_balance as u128)
-                .checked_mul(token_b_vault_amount as u128)
-                .unwrap()
-                .checked_div(lp_total_supply as u128)
-                .unwrap() as u64
-        };
+#[derive(Accounts)]
+pub struct AdminAction<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
 
-        Ok((user_share_percentage, user_token_a_share, user_token_b_share))
-    }
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
 
-    // Function to create wrapper for sync native instruction (for SOL pools)
-    pub fn sync_native(ctx: Context<SyncNative>) -> Result<()> {
-        require!(!ctx.accounts.swap_pool.is_paused, CustomError::PoolPaused);
+#[derive(Accounts)]
+pub struct TransferAdmin<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
 
-        // This is used when one of the tokens is wrapped SOL
-        let cpi_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            NativeSyncNative {
-                account: ctx.accounts.token_account.to_account_info(),
-            },
-        );
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
 
-        native_sync_native(cpi_ctx)?;
+#[derive(Accounts)]
+pub struct GetPrice<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+    
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(
+        seeds = [
+            b"pool_authority".as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
+        ],
+        bump = swap_pool.authority_bump
+    )]
+    /// CHECK: This is a PDA used as the authority
+    pub pool_authority: UncheckedAccount<'info>,
+}
 
-        Ok(())
-    }
+// Route quotes read every pool from remaining_accounts
+#[derive(Accounts)]
+pub struct CalculateRoute {}
 
-    // Function to update pool implementation (for future upgrades)
-    pub fn update_pool_version(ctx: Context<AdminAction>, _new_version: u8) -> Result<()> {
-        require!(
-            ctx.accounts.admin.key() == ctx.accounts.swap_pool.admin,
-            CustomError::Unauthorized
-        );
-        
-        // This is a placeholder for future upgradability
-        // Add version field to SwapPool struct to track upgrades
-        // For now, this just verifies admin authority
-        
-        Ok(())
-    }
+// Each hop's Swap accounts come from remaining_accounts and are validated as a Swap context
+#[derive(Accounts)]
+pub struct SwapRoute<'info> {
+    #[account(mut)]
+    pub user_authority: Signer<'info>,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct SwapPool {
-    pub token_a_mint: Pubkey,       // Mint address of token A
-    pub token_b_mint: Pubkey,       // Mint address of token B
-    pub token_a_vault: Pubkey,      // Vault holding token A liquidity
-    pub token_b_vault: Pubkey,      // Vault holding token B liquidity
-    pub lp_mint: Pubkey,            // Mint for LP tokens
-    pub pool_authority: Pubkey,     // PDA with authority over vaults
-    pub fee_rate: u64,              // Fee taken on swaps (basis points)
-    pub bump: u8,                   // Bump for PDA derivation
-    pub is_paused: bool,            // Emergency pause flag
-    pub admin: Pubkey,              // Admin address that can pause/unpause
-    pub total_fees_a: u64,          // Accumulated fees in token A
-    pub total_fees_b: u64,          // Accumulated fees in token B
-}
+// Pure math view, every input is an instruction argument
+#[derive(Accounts)]
+pub struct CalculatePriceImpact {}
 
 #[derive(Accounts)]
-#[instruction(fee_rate: u64, bump: u8)]
-pub struct InitializePool<'info> {
-    #[account(
-        init,
-        payer = admin,
-        space = 8 + 32 + 32 + 32 + 32 + 32 + 32 + 8 +  1 +  1 +  32 + 8 + 8,
-    )]
+pub struct CalculateTokensForLp<'info> {
     pub swap_pool: Account<'info, SwapPool>,
 
+    #[account(
+        constraint = token_a_mint.key() == swap_pool.token_a_mint,
+    )]
     pub token_a_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = token_b_mint.key() == swap_pool.token_b_mint,
+    )]
     pub token_b_mint: InterfaceAccount<'info, Mint>,
 
-    // #[account(
-    //     mut,
-    //     constraint = token_a_vault.mint == token_a_mint.key(),
-    //     constraint = token_a_vault.owner == pool_authority.key(),
-    // )]
-    // pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-
-    // #[account(
-    //     mut,
-    //     constraint = token_b_vault.mint == token_b_mint.key(),
-    //     constraint = token_b_vault.owner == pool_authority.key(),
-    // )]
-    // pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-
-    // #[account(
-    //     init,
-    //     payer = admin,
-    //     seeds = [
-    //         b"token_vault".as_ref(),
-    //         pool_authority.key().as_ref(),
-    //         token_a_mint.key().as_ref(),
-    //     ],
-    //     bump,
-    //     token::mint = token_a_mint,
-    //     token::authority = pool_authority,
-    // )]
-    // pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-
-    // #[account(
-    //     init,
-    //     payer = admin,
-    //     seeds = [
-    //         b"token_vault".as_ref(),
-    //         pool_authority.key().as_ref(),
-    //         token_b_mint.key().as_ref(),
-    //     ],
-    //     bump,
-    //     token::mint = token_b_mint,
-    //     token::authority = pool_authority,
-    // )]
-    // pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-
-    // #[account(
-    //     init, 
-    //     payer = admin,
-    //     associated_token::mint = token_a_mint,
-    //     associated_token::authority = pool_authority,
-    //     associated_token::token_program = token_program,
-    //   )]
-    //   pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-      
-    //   #[account(
-    //     init, 
-    //     payer = admin,
-    //     associated_token::mint = token_b_mint,
-    //     associated_token::authority = pool_authority,
-    //     associated_token::token_program = token_program,
-    //   )]
-    //   pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        constraint = token_a_vault.mint == token_a_mint.key(),
-        constraint = token_a_vault.owner == pool_authority.key(),
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
     )]
     pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        constraint = token_b_vault.mint == token_b_mint.key(),
-        constraint = token_b_vault.owner == pool_authority.key(),
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
     )]
     pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        init,
-        payer = admin,
-        mint::decimals = 6,
-        mint::authority = pool_authority,
+        constraint = lp_mint.key() == swap_pool.lp_mint,
+    )]
+    pub lp_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct GetPoolStats<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
+        constraint = token_a_vault.mint == swap_pool.token_a_mint,
+        constraint = token_a_vault.owner == pool_authority.key()
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
+        constraint = token_b_vault.mint == swap_pool.token_b_mint,
+        constraint = token_b_vault.owner == pool_authority.key()
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        constraint = lp_mint.key() == swap_pool.lp_mint,
     )]
     pub lp_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
         seeds = [
             b"pool_authority".as_ref(),
-            token_a_mint.key().as_ref(),
-            token_b_mint.key().as_ref(),
+            swap_pool.token_a_mint.as_ref(),
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
         ],
-        bump = bump,
+        bump = swap_pool.authority_bump
     )]
-    /// CHECK: PDA that will have authority over the token vaults
+    /// CHECK: This is a PDA used as the authority
     pub pool_authority: UncheckedAccount<'info>,
-
-    #[account(mut)]
-    pub admin: Signer<'info>,
-
-    pub system_program: Program<'info, System>,
-    pub token_program: Interface<'info, TokenInterface>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct AddInitialLiquidity<'info> {
-    #[account(mut)]
+pub struct GetUserShare<'info> {
     pub swap_pool: Account<'info, SwapPool>,
 
-    pub token_a_mint: InterfaceAccount<'info, Mint>,
-    pub token_b_mint: InterfaceAccount<'info, Mint>,
-
     #[account(
-        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
         constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key(),
+        constraint = token_a_vault.owner == pool_authority.key()
     )]
     pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
         constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key(),
+        constraint = token_b_vault.owner == pool_authority.key()
     )]
     pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut,
-        constraint = user_token_a.mint == swap_pool.token_a_mint,
-        constraint = user_token_a.owner == user_authority.key(),
-    )]
-    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        mut,
-        constraint = user_token_b.mint == swap_pool.token_b_mint,
-        constraint = user_token_b.owner == user_authority.key(),
-    )]
-    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        mut,
-        constraint = lp_mint.key() == swap_pool.lp_mint,
+        constraint = lp_mint.key() == swap_pool.lp_mint
     )]
     pub lp_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
-        init_if_needed,
-        payer = user_authority,
-        associated_token::mint = lp_mint,
-        associated_token::authority = user_authority,
+        constraint = user_lp_token.mint == lp_mint.key(),
+        constraint = user_lp_token.owner == user_authority.key()
     )]
     pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
 
@@ -839,84 +5413,133 @@ pub struct AddInitialLiquidity<'info> {
             b"pool_authority".as_ref(),
             swap_pool.token_a_mint.as_ref(),
             swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
         ],
-        bump = swap_pool.bump
+        bump = swap_pool.authority_bump
     )]
     /// CHECK: This is a PDA used as the authority
     pub pool_authority: UncheckedAccount<'info>,
+    
+    pub user_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetUserFeeEarnings<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), lp_holder.owner.as_ref()],
+        bump = lp_holder.bump,
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        constraint = user_lp_token.mint == swap_pool.lp_mint,
+        constraint = user_lp_token.owner == lp_holder.owner,
+    )]
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct AddFeeExemption<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + FeeExemption::INIT_SPACE,
+        seeds = [b"fee_exemption".as_ref(), swap_pool.key().as_ref(), user.as_ref()],
+        bump,
+    )]
+    pub fee_exemption: Account<'info, FeeExemption>,
 
     #[account(mut)]
-    pub user_authority: Signer<'info>,
+    pub admin: Signer<'info>,
 
-    pub token_program: Interface<'info, TokenInterface>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct Swap<'info> {
+pub struct RemoveFeeExemption<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+
+    #[account(
+        mut,
+        close = admin,
+        constraint = fee_exemption.swap_pool == swap_pool.key(),
+    )]
+    pub fee_exemption: Account<'info, FeeExemption>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetCheckpoint<'info> {
+    #[account(mut)]
     pub swap_pool: Account<'info, SwapPool>,
 
-    pub token_a_mint: InterfaceAccount<'info, Mint>,
-    pub token_b_mint: InterfaceAccount<'info, Mint>,
-
     #[account(
-        mut,
-        constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key(),
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
     )]
     pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut,
-        constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key(),
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
     )]
     pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
 
-    #[account(
-        mut,
-        constraint = user_token_a.mint == swap_pool.token_a_mint,
-        constraint = user_token_a.owner == user_authority.key(),
-    )]
-    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    pub admin: Signer<'info>,
+}
 
-    #[account(
-        mut,
-        constraint = user_token_b.mint == swap_pool.token_b_mint,
-        constraint = user_token_b.owner == user_authority.key(),
-    )]
-    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+#[derive(Accounts)]
+pub struct DescribePool<'info> {
+    pub swap_pool: Account<'info, SwapPool>,
+}
 
-    #[account(
-        seeds = [
-            b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref(),
-        ],
-        bump = swap_pool.bump
-    )]
-    /// CHECK: This is a PDA used as the authority
-    pub pool_authority: UncheckedAccount<'info>,
+#[derive(Accounts)]
+pub struct ReallocPool<'info> {
+    #[account(mut)]
+    /// CHECK: May still be in an old layout; owner, discriminator and admin are checked in the handler
+    pub swap_pool: UncheckedAccount<'info>,
 
     #[account(mut)]
-    pub user_authority: Signer<'info>,
+    pub admin: Signer<'info>,
 
-    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct AddLiquidity<'info> {
+pub struct SyncNative<'info> {
+    #[account(mut)]
+    pub swap_pool: Account<'info, SwapPool>,
+    
+    #[account(mut)]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+    
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidityNft<'info> {
     #[account(mut)]
     pub swap_pool: Account<'info, SwapPool>,
 
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
     pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
     pub token_b_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
         mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
         constraint = token_a_vault.mint == swap_pool.token_a_mint,
         constraint = token_a_vault.owner == pool_authority.key(),
     )]
@@ -924,6 +5547,8 @@ pub struct AddLiquidity<'info> {
 
     #[account(
         mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
         constraint = token_b_vault.mint == swap_pool.token_b_mint,
         constraint = token_b_vault.owner == pool_authority.key(),
     )]
@@ -953,17 +5578,63 @@ pub struct AddLiquidity<'info> {
         init_if_needed,
         payer = user_authority,
         associated_token::mint = lp_mint,
+        associated_token::authority = pool_authority,
+    )]
+    pub position_lp_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = user_authority,
+        mint::decimals = 0,
+        mint::authority = pool_authority,
+    )]
+    pub position_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = user_authority,
+        associated_token::mint = position_mint,
         associated_token::authority = user_authority,
     )]
-    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+    pub user_position_token: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = user_authority,
+        space = 8 + NftPosition::INIT_SPACE,
+        seeds = [
+            b"nft_position".as_ref(),
+            position_mint.key().as_ref(),
+        ],
+        bump
+    )]
+    pub nft_position: Account<'info, NftPosition>,
+
+    // Fee and holder bookkeeping for the position, keyed by the position so it follows the NFT
+    #[account(
+        init,
+        payer = user_authority,
+        space = 8 + LpHolder::INIT_SPACE,
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), nft_position.key().as_ref()],
+        bump
+    )]
+    pub lp_holder: Account<'info, LpHolder>,
+
+    #[account(
+        mut,
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
+    )]
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
 
     #[account(
         seeds = [
             b"pool_authority".as_ref(),
             swap_pool.token_a_mint.as_ref(),
             swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
         ],
-        bump = swap_pool.bump
+        bump = swap_pool.authority_bump
     )]
     /// CHECK: This is a PDA used as the authority
     pub pool_authority: UncheckedAccount<'info>,
@@ -974,221 +5645,131 @@ pub struct AddLiquidity<'info> {
     pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveLiquidity<'info> {
+pub struct RemoveLiquidityNft<'info> {
     #[account(mut)]
     pub swap_pool: Account<'info, SwapPool>,
-    
+
+    #[account(constraint = token_a_mint.key() == swap_pool.token_a_mint @ CustomError::InvalidToken)]
     pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(constraint = token_b_mint.key() == swap_pool.token_b_mint @ CustomError::InvalidToken)]
     pub token_b_mint: InterfaceAccount<'info, Mint>,
-    
+
     #[account(
         mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
         constraint = token_a_vault.mint == swap_pool.token_a_mint,
         constraint = token_a_vault.owner == pool_authority.key()
     )]
     pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-    
+
     #[account(
         mut,
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
         constraint = token_b_vault.mint == swap_pool.token_b_mint,
         constraint = token_b_vault.owner == pool_authority.key()
     )]
     pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-    
+
     #[account(
         mut,
         constraint = user_token_a.mint == swap_pool.token_a_mint,
         constraint = user_token_a.owner == user_authority.key()
     )]
     pub user_token_a: InterfaceAccount<'info, TokenAccount>,
-    
+
     #[account(
         mut,
         constraint = user_token_b.mint == swap_pool.token_b_mint,
         constraint = user_token_b.owner == user_authority.key()
     )]
     pub user_token_b: InterfaceAccount<'info, TokenAccount>,
-    
+
     #[account(
         mut,
         constraint = lp_mint.key() == swap_pool.lp_mint
     )]
     pub lp_mint: InterfaceAccount<'info, Mint>,
-    
+
     #[account(
         mut,
-        constraint = user_lp_token.mint == lp_mint.key(),
-        constraint = user_lp_token.owner == user_authority.key()
-    )]
-    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(
-        seeds = [
-            b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref()
-        ],
-        bump = swap_pool.bump
+        associated_token::mint = lp_mint,
+        associated_token::authority = pool_authority,
     )]
-    /// CHECK: This is a PDA used as the authority
-    pub pool_authority: UncheckedAccount<'info>,
-    
-    #[account(mut)]
-    pub user_authority: Signer<'info>,
+    pub position_lp_vault: InterfaceAccount<'info, TokenAccount>,
 
-    pub token_program: Interface<'info, TokenInterface>,
-    pub system_program: Program<'info, System>,
-}
-
-#[derive(Accounts)]
-pub struct CollectFees<'info> {
     #[account(mut)]
-    pub swap_pool: Account<'info, SwapPool>,
-
-    pub token_a_mint: InterfaceAccount<'info, Mint>,
-    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    pub position_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
         mut,
-        constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key()
+        constraint = user_position_token.mint == position_mint.key(),
+        constraint = user_position_token.owner == user_authority.key(),
+        constraint = user_position_token.amount == 1,
     )]
-    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    pub user_position_token: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
         mut,
-        constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key()
+        close = user_authority,
+        seeds = [
+            b"nft_position".as_ref(),
+            position_mint.key().as_ref(),
+        ],
+        bump = nft_position.bump,
+        constraint = nft_position.swap_pool == swap_pool.key(),
     )]
-    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub fee_collector: Signer<'info>,
+    pub nft_position: Account<'info, NftPosition>,
 
     #[account(
         mut,
-        constraint = fee_collector_token_a.mint == swap_pool.token_a_mint,
-        constraint = fee_collector_token_a.owner == fee_collector.key()
+        seeds = [b"lp_holder".as_ref(), swap_pool.key().as_ref(), nft_position.key().as_ref()],
+        bump = lp_holder.bump,
     )]
-    pub fee_collector_token_a: InterfaceAccount<'info, TokenAccount>,
+    pub lp_holder: Account<'info, LpHolder>,
 
     #[account(
         mut,
-        constraint = fee_collector_token_b.mint == swap_pool.token_b_mint,
-        constraint = fee_collector_token_b.owner == fee_collector.key()
+        seeds = [b"lp_history".as_ref(), swap_pool.key().as_ref()],
+        bump = lp_history.bump,
     )]
-    pub fee_collector_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub lp_history: Option<Account<'info, LpSupplyHistory>>,
 
     #[account(
         seeds = [
             b"pool_authority".as_ref(),
             swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref()
+            swap_pool.token_b_mint.as_ref(),
+            swap_pool.fee_tier_seed(),
         ],
-        bump = swap_pool.bump
+        bump = swap_pool.authority_bump
     )]
     /// CHECK: This is a PDA used as the authority
     pub pool_authority: UncheckedAccount<'info>,
 
-    pub token_program: Interface<'info, TokenInterface>,
-}
-
-#[derive(Accounts)]
-pub struct AdminAction<'info> {
-    #[account(mut)]
-    pub swap_pool: Account<'info, SwapPool>,
-
-    #[account(mut)]
-    pub admin: Signer<'info>,
-}
-
-#[derive(Accounts)]
-pub struct TransferAdmin<'info> {
-    #[account(mut)]
-    pub swap_pool: Account<'info, SwapPool>,
-
     #[account(mut)]
-    pub admin: Signer<'info>,
-}
-
-#[derive(Accounts)]
-pub struct GetPrice<'info> {
-    pub swap_pool: Account<'info, SwapPool>,
-    
-    #[account(
-        constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key()
-    )]
-    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(
-        constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key()
-    )]
-    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(
-        seeds = [
-            b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref()
-        ],
-        bump = swap_pool.bump
-    )]
-    /// CHECK: This is a PDA used as the authority
-    pub pool_authority: UncheckedAccount<'info>,
-}
-
-#[derive(Accounts)]
-pub struct GetPoolStats<'info> {
-    pub swap_pool: Account<'info, SwapPool>,
-
-    #[account(
-        constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key()
-    )]
-    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key()
-    )]
-    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        constraint = lp_mint.key() == swap_pool.lp_mint,
-    )]
-    pub lp_mint: InterfaceAccount<'info, Mint>,
+    pub user_authority: Signer<'info>,
 
-    #[account(
-        seeds = [
-            b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref()
-        ],
-        bump = swap_pool.bump
-    )]
-    /// CHECK: This is a PDA used as the authority
-    pub pool_authority: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct GetUserShare<'info> {
+pub struct GetNftPositionValue<'info> {
     pub swap_pool: Account<'info, SwapPool>,
 
     #[account(
-        constraint = token_a_vault.mint == swap_pool.token_a_mint,
-        constraint = token_a_vault.owner == pool_authority.key()
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_a_mint.as_ref()],
+        bump = swap_pool.vault_a_bump,
     )]
     pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        constraint = token_b_vault.mint == swap_pool.token_b_mint,
-        constraint = token_b_vault.owner == pool_authority.key()
+        seeds = [b"token_vault".as_ref(), swap_pool.pool_authority.as_ref(), swap_pool.token_b_mint.as_ref()],
+        bump = swap_pool.vault_b_bump,
     )]
     pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
 
@@ -1198,35 +5779,7 @@ pub struct GetUserShare<'info> {
     pub lp_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
-        constraint = user_lp_token.mint == lp_mint.key(),
-        constraint = user_lp_token.owner == user_authority.key()
-    )]
-    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
-
-    #[account(
-        seeds = [
-            b"pool_authority".as_ref(),
-            swap_pool.token_a_mint.as_ref(),
-            swap_pool.token_b_mint.as_ref()
-        ],
-        bump = swap_pool.bump
+        constraint = nft_position.swap_pool == swap_pool.key()
     )]
-    /// CHECK: This is a PDA used as the authority
-    pub pool_authority: UncheckedAccount<'info>,
-    
-    pub user_authority: Signer<'info>,
+    pub nft_position: Account<'info, NftPosition>,
 }
-
-#[derive(Accounts)]
-pub struct SyncNative<'info> {
-    #[account(mut)]
-    pub swap_pool: Account<'info, SwapPool>,
-    
-    #[account(mut)]
-    pub token_account: InterfaceAccount<'info, TokenAccount>,
-    
-    #[account(mut)]
-    pub admin: Signer<'info>,
-    
-    pub token_program: Interface<'info, TokenInterface>,
-}
\ No newline at end of file
@@ -0,0 +1,44 @@
+use anchor_lang::prelude::*;
+
+use crate::error::CustomError;
+
+// Same bounds as Uniswap v3, price = 1.0001^tick stays representable at both ends
+pub const MIN_TICK: i32 = -443_636;
+pub const MAX_TICK: i32 = 443_636;
+
+const TICK_BASE: f64 = 1.0001;
+
+// Price of token A in token B at the given tick
+pub fn tick_to_price(tick: i32) -> f64 {
+    TICK_BASE.powi(tick)
+}
+
+// Largest tick whose price does not exceed the given price
+pub fn price_to_tick(price: f64) -> Result<i32> {
+    require!(price > 0.0 && price.is_finite(), CustomError::CalculationFailure);
+
+    let tick = (price.ln() / TICK_BASE.ln()).floor() as i32;
+    Ok(tick.clamp(MIN_TICK, MAX_TICK))
+}
+
+// Current pool tick derived from the reserve ratio (token B per token A)
+pub fn current_tick(reserve_a: u64, reserve_b: u64) -> Result<i32> {
+    require!(reserve_a > 0 && reserve_b > 0, CustomError::InsufficientLiquidity);
+
+    price_to_tick(reserve_b as f64 / reserve_a as f64)
+}
+
+// Ticks must be ordered, in bounds and aligned to the pool's spacing
+pub fn validate_tick_range(lower_tick: i32, upper_tick: i32, tick_spacing: u16) -> Result<()> {
+    require!(tick_spacing > 0, CustomError::ConcentratedLiquidityDisabled);
+    require!(lower_tick < upper_tick, CustomError::InvalidTickRange);
+    require!(lower_tick >= MIN_TICK && upper_tick <= MAX_TICK, CustomError::InvalidTickRange);
+
+    let spacing = tick_spacing as i32;
+    require!(
+        lower_tick % spacing == 0 && upper_tick % spacing == 0,
+        CustomError::InvalidTickRange
+    );
+
+    Ok(())
+}
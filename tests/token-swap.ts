@@ -6,14 +6,28 @@ import {
   PublicKey,
   SystemProgram,
   SYSVAR_RENT_PUBKEY,
+  Transaction,
+  sendAndConfirmTransaction,
 } from "@solana/web3.js";
 import {
   ASSOCIATED_TOKEN_PROGRAM_ID,
   createMint,
   getOrCreateAssociatedTokenAccount,
+  NATIVE_MINT,
   TOKEN_PROGRAM_ID,
   mintTo as splMintTo,
   createAccount as createTokenAccount,
+  createTransferInstruction,
+  createWrappedNativeAccount,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getMint,
+  transferChecked,
+  TOKEN_2022_PROGRAM_ID,
+  ExtensionType,
+  getMintLen,
+  createInitializeMintInstruction,
+  createInitializeTransferFeeConfigInstruction,
 } from "@solana/spl-token";
 import { expect } from "chai";
 
@@ -38,6 +52,8 @@ describe("token-swap tests", () => {
 
   let tokenAVault: PublicKey;
   let tokenBVault: PublicKey;
+  let feeVaultA: PublicKey;
+  let feeVaultB: PublicKey;
   let vaultABump: number;
   let vaultBBump: number;
 
@@ -58,9 +74,37 @@ describe("token-swap tests", () => {
 
   // Constants\
   const FEE_RATE = 30; // 0.3% fee
+  const FEE_TIERS = [1, 5, 30, 100]; // Mirrors FEE_TIERS in the program
+  const feeTierSeed = (feeRate: number) => Buffer.from([FEE_TIERS.indexOf(feeRate)]);
   const INITIAL_LIQUIDITY_A = 1_000_000_000; // 1,000 tokens (assuming 6 decimals)
   const INITIAL_LIQUIDITY_B = 2_000_000_000; // 2,000 tokens (assuming 6 decimals)
   const TOKEN_DECIMALS = 6;
+  const MINIMUM_LIQUIDITY = 1_000; // LP locked by the first deposit
+  const PAUSE_SWAPS = 1 << 0;
+  const PAUSE_DEPOSITS = 1 << 1;
+  const PAUSE_WITHDRAWALS = 1 << 2;
+  const CURVE_CONSTANT_PRODUCT = 0;
+  const CURVE_STABLE = 1;
+
+  // Pools take their mints smallest pubkey first
+  const orderedMints = (x: PublicKey, y: PublicKey): [PublicKey, PublicKey] =>
+    Buffer.compare(x.toBuffer(), y.toBuffer()) < 0 ? [x, y] : [y, x];
+  const createOrderedMints = async () =>
+    orderedMints(
+      await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS),
+      await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS)
+    );
+  // Fee vaults sit next to the token vaults, derived from the same authority and mint
+  const feeVaultOf = (authority: PublicKey, mint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("fee_vault"), authority.toBuffer(), mint.toBuffer()],
+      program.programId
+    )[0];
+  const lockedLpOf = (authority: PublicKey, lpMint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("locked_lp"), authority.toBuffer(), lpMint.toBuffer()],
+      program.programId
+    )[0];
 
   before(async () => {
     // Airdrop SOL to test accounts
@@ -75,8 +119,8 @@ describe("token-swap tests", () => {
     // Wait for confirmation
     await new Promise((resolve) => setTimeout(resolve, 1000));
 
-    // Create token mints
-    tokenAMint = await createMint(
+    // Create token mints, in the canonical order the program requires
+    const firstMint = await createMint(
       provider.connection,
       admin,
       admin.publicKey,
@@ -87,7 +131,7 @@ describe("token-swap tests", () => {
       TOKEN_PROGRAM_ID
     );
 
-    tokenBMint = await createMint(
+    const secondMint = await createMint(
       provider.connection,
       admin,
       admin.publicKey,
@@ -97,6 +141,7 @@ describe("token-swap tests", () => {
       undefined,
       TOKEN_PROGRAM_ID
     );
+    [tokenAMint, tokenBMint] = orderedMints(firstMint, secondMint);
 
     // Create token accounts for all users
     adminTokenA = (
@@ -266,6 +311,7 @@ describe("token-swap tests", () => {
         Buffer.from("pool_authority"),
         tokenAMint.toBuffer(),
         tokenBMint.toBuffer(),
+        feeTierSeed(FEE_RATE),
       ],
       program.programId
     );
@@ -335,6 +381,8 @@ describe("token-swap tests", () => {
 
     tokenAVault = tokenAVaultAddress;
     tokenBVault = tokenBVaultAddress;
+    feeVaultA = feeVaultOf(poolAuthority, tokenAMint);
+    feeVaultB = feeVaultOf(poolAuthority, tokenBMint);
   });
 
   it("Initialize pool", async () => {
@@ -342,48 +390,19 @@ describe("token-swap tests", () => {
 
     try {
       // Calculate PDA for pool authority
-      const [poolAuthority, poolAuthorityBump] =
+      const [poolAuthority] =
         PublicKey.findProgramAddressSync(
           [
             Buffer.from("pool_authority"),
             tokenAMint.toBuffer(),
             tokenBMint.toBuffer(),
+            feeTierSeed(FEE_RATE),
           ],
           program.programId
         );
 
       console.log("Pool Authority:", poolAuthority.toString());
 
-      // Create token A vault with a NEW keypair
-      const tokenAVaultKeypair = Keypair.generate();
-      console.log(
-        "Token A Vault keypair:",
-        tokenAVaultKeypair.publicKey.toString()
-      );
-
-      await createTokenAccount(
-        provider.connection,
-        admin,
-        tokenAMint,
-        poolAuthority,
-        tokenAVaultKeypair // Use the keypair for creating the account
-      );
-
-      // Create token B vault with a NEW keypair
-      const tokenBVaultKeypair = Keypair.generate();
-      console.log(
-        "Token B Vault keypair:",
-        tokenBVaultKeypair.publicKey.toString()
-      );
-
-      await createTokenAccount(
-        provider.connection,
-        admin,
-        tokenBMint,
-        poolAuthority,
-        tokenBVaultKeypair // Use the keypair for creating the account
-      );
-
       // Explicitly check if the swap pool account exists
       const swapPoolInfo = await provider.connection.getAccountInfo(
         swapPool.publicKey
@@ -396,15 +415,17 @@ describe("token-swap tests", () => {
         console.log("New swapPool:", swapPool.publicKey.toString());
       }
 
-      // Initialize the pool with manually created accounts
-      await program.methods
-        .initializePool(new anchor.BN(FEE_RATE), poolAuthorityBump)
+      // Initialize the pool; the program creates both vaults at their PDAs
+      const initSignature = await program.methods
+        .initializePool(new anchor.BN(FEE_RATE), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
         .accounts({
           swapPool: swapPool.publicKey,
           tokenAMint,
           tokenBMint,
-          tokenAVault: tokenAVaultKeypair.publicKey,
-          tokenBVault: tokenBVaultKeypair.publicKey,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
           lpMint: lpMint.publicKey,
           poolAuthority,
           admin: admin.publicKey,
@@ -412,11 +433,23 @@ describe("token-swap tests", () => {
           tokenProgram: TOKEN_PROGRAM_ID,
           rent: SYSVAR_RENT_PUBKEY,
         })
-        .signers([admin, swapPool, lpMint]) // Include vault keypairs if needed
+        .signers([admin, swapPool, lpMint]) // Vaults are PDAs created by the program
         .rpc();
 
       console.log("Pool initialized successfully");
 
+      // Informational logs are compiled out unless the verbose_logs feature is enabled
+      await provider.connection.confirmTransaction(initSignature, "confirmed");
+      const initTx = await provider.connection.getTransaction(initSignature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const initLogs = initTx?.meta?.logMessages ?? [];
+      const verboseLogs = process.env.VERBOSE_LOGS === "1";
+      expect(
+        initLogs.some((log) => log.includes("Token swap pool initialized"))
+      ).to.equal(verboseLogs);
+
       // Rest of your test
     } catch (err) {
       console.error("Error details:", err);
@@ -429,5 +462,4820 @@ describe("token-swap tests", () => {
       swapPool.publicKey
     );
     expect(poolAccount.tokenAMint.toString()).to.equal(tokenAMint.toString());
+    expect(poolAccount.tokenAVault.toString()).to.equal(tokenAVault.toString());
+    expect(poolAccount.tokenBVault.toString()).to.equal(tokenBVault.toString());
+
+    const vaultA = await getAccount(provider.connection, tokenAVault);
+    expect(vaultA.owner.toString()).to.equal(poolAuthority.toString());
+  });
+
+  it("Rejects a pool initialized with attacker-owned vaults", async () => {
+    const [mintA, mintB] = await createOrderedMints();
+    const [authority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)],
+      program.programId
+    );
+    // Right mints, but accounts the attacker created and controls instead of the vault PDAs
+    const fakeVaultA = await createTokenAccount(provider.connection, user2, mintA, user2.publicKey, Keypair.generate());
+    const fakeVaultB = await createTokenAccount(provider.connection, user2, mintB, user2.publicKey, Keypair.generate());
+    const pool = Keypair.generate();
+    const poolLpMint = Keypair.generate();
+
+    try {
+      await program.methods
+        .initializePool(new anchor.BN(FEE_RATE), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
+        .accounts({
+          swapPool: pool.publicKey,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAVault: fakeVaultA,
+          tokenBVault: fakeVaultB,
+          feeVaultA: feeVaultOf(authority, mintA),
+          feeVaultB: feeVaultOf(authority, mintB),
+          lpMint: poolLpMint.publicKey,
+          poolAuthority: authority,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([admin, pool, poolLpMint])
+        .rpc();
+      expect.fail("attacker-owned vaults should be rejected");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("ConstraintSeeds");
+    }
+  });
+
+  it("Rejects a pool authority derived with a forged bump", async () => {
+    const [mintA, mintB] = await createOrderedMints();
+    const seeds = [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)];
+    const [, canonicalBump] = PublicKey.findProgramAddressSync(seeds, program.programId);
+
+    // Any lower bump that still lands off-curve yields a valid-looking but non-canonical PDA
+    let forgedAuthority: PublicKey;
+    for (let bump = canonicalBump - 1; bump >= 0 && !forgedAuthority; bump--) {
+      try {
+        forgedAuthority = PublicKey.createProgramAddressSync([...seeds, Buffer.from([bump])], program.programId);
+      } catch {
+        // On-curve for this bump, keep looking
+      }
+    }
+    const vaultOf = (mint: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("token_vault"), forgedAuthority.toBuffer(), mint.toBuffer()],
+        program.programId
+      )[0];
+    const pool = Keypair.generate();
+    const poolLpMint = Keypair.generate();
+
+    try {
+      await program.methods
+        .initializePool(new anchor.BN(FEE_RATE), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
+        .accounts({
+          swapPool: pool.publicKey,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAVault: vaultOf(mintA),
+          tokenBVault: vaultOf(mintB),
+          feeVaultA: feeVaultOf(forgedAuthority, mintA),
+          feeVaultB: feeVaultOf(forgedAuthority, mintB),
+          lpMint: poolLpMint.publicKey,
+          poolAuthority: forgedAuthority,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([admin, pool, poolLpMint])
+        .rpc();
+      expect.fail("a non-canonical pool authority should be rejected");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidSwapPool");
+    }
+  });
+
+  it("Rejects an initial deposit that mints too little LP", async () => {
+    try {
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1), new anchor.BN(1), new anchor.BN(0))
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          userLpToken: getAssociatedTokenAddressSync(lpMint.publicKey, user1.publicKey),
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          lockedLpToken: lockedLpOf(poolAuthority, lpMint.publicKey),
+          lpHistory: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([user1])
+        .rpc();
+      expect.fail("a 1:1 deposit of one unit should be rejected");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InitialLiquidityTooSmall");
+    }
+  });
+
+  it("Add initial liquidity", async () => {
+    user1LpToken = getAssociatedTokenAddressSync(
+      lpMint.publicKey,
+      user1.publicKey
+    );
+
+    await program.methods
+      .addInitialLiquidity(
+        new anchor.BN(INITIAL_LIQUIDITY_A),
+        new anchor.BN(INITIAL_LIQUIDITY_B),
+        new anchor.BN(0)
+      )
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        userLpToken: user1LpToken,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        lockedLpToken: lockedLpOf(poolAuthority, lpMint.publicKey),
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .signers([user1])
+      .rpc();
+
+    const vaultA = await getAccount(provider.connection, tokenAVault);
+    const vaultB = await getAccount(provider.connection, tokenBVault);
+    expect(Number(vaultA.amount)).to.equal(INITIAL_LIQUIDITY_A);
+    expect(Number(vaultB.amount)).to.equal(INITIAL_LIQUIDITY_B);
+
+    // The user gets everything except the permanently locked minimum
+    const lpAccount = await getAccount(provider.connection, user1LpToken);
+    const lockedLp = await getAccount(provider.connection, lockedLpOf(poolAuthority, lpMint.publicKey));
+    expect(Number(lpAccount.amount)).to.be.greaterThan(0);
+    expect(Number(lockedLp.amount)).to.equal(MINIMUM_LIQUIDITY);
+
+    // LP decimals follow the larger token's decimals, so the geometric mean is in LP units
+    const lpMintAccount = await getMint(provider.connection, lpMint.publicKey);
+    expect(lpMintAccount.decimals).to.equal(TOKEN_DECIMALS);
+    expect(Number(lpMintAccount.supply)).to.be.closeTo(
+      Math.sqrt(INITIAL_LIQUIDITY_A * INITIAL_LIQUIDITY_B),
+      1
+    );
+  });
+
+  it("Remove all liquidity", async () => {
+    await program.methods
+      .removeAllLiquidity(new anchor.BN(0), new anchor.BN(0), false)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        userLpToken: user1LpToken,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user1])
+      .rpc();
+
+    // The only user LP fully exited; just the locked minimum and its share of the reserves remain
+    const vaultA = await getAccount(provider.connection, tokenAVault);
+    const vaultB = await getAccount(provider.connection, tokenBVault);
+    expect(Number(vaultA.amount)).to.be.greaterThan(0);
+    expect(Number(vaultB.amount)).to.be.greaterThan(0);
+
+    const lpAccount = await getAccount(provider.connection, user1LpToken);
+    expect(Number(lpAccount.amount)).to.equal(0);
+
+    const lpMintAccount = await getMint(provider.connection, lpMint.publicKey);
+    expect(Number(lpMintAccount.supply)).to.equal(MINIMUM_LIQUIDITY);
+  });
+
+  it("Credits a vault donation to the locked liquidity", async () => {
+    const supplyBefore = (await getMint(provider.connection, lpMint.publicKey)).supply;
+    const vaultABefore = (await getAccount(provider.connection, tokenAVault)).amount;
+
+    await transferChecked(
+      provider.connection,
+      user2,
+      user2TokenA,
+      tokenAMint,
+      tokenAVault,
+      user2,
+      1_000_000,
+      TOKEN_DECIMALS
+    );
+    await transferChecked(
+      provider.connection,
+      user2,
+      user2TokenB,
+      tokenBMint,
+      tokenBVault,
+      user2,
+      2_000_000,
+      TOKEN_DECIMALS
+    );
+
+    // No LP is minted for a donation, so it all accrues to the existing supply
+    const supplyAfter = (await getMint(provider.connection, lpMint.publicKey)).supply;
+    const vaultAAfter = (await getAccount(provider.connection, tokenAVault)).amount;
+    expect(supplyAfter).to.equal(supplyBefore);
+    expect(Number(vaultAAfter - vaultABefore)).to.equal(1_000_000);
+  });
+
+  it("Rejects a decoy vault that is not the pool's stored vault", async () => {
+    // Same mint and same owner as the real vault, but a different account
+    const decoyVault = await createTokenAccount(
+      provider.connection,
+      admin,
+      tokenAMint,
+      poolAuthority,
+      Keypair.generate()
+    );
+
+    try {
+      await program.methods
+        .getPoolStats()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault: decoyVault,
+          tokenBVault,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+        })
+        .view();
+      expect.fail("decoy vault should have been rejected");
+    } catch (err) {
+      expect(err.toString()).to.include("ConstraintSeeds");
+    }
+  });
+
+  it("Syncs a wrapped SOL account", async () => {
+    const wsolAccount = await createWrappedNativeAccount(
+      provider.connection,
+      admin,
+      admin.publicKey,
+      1_000_000
+    );
+
+    await program.methods
+      .syncNative()
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAccount: wsolAccount,
+        admin: admin.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Rejects sync_native on a non-native token account", async () => {
+    try {
+      await program.methods
+        .syncNative()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAccount: adminTokenA,
+          admin: admin.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc();
+      expect.fail("sync_native should reject a non-native account");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("NotNativeMint");
+    }
+  });
+
+  it("Quotes exactly the output a swap pays", async () => {
+    for (let i = 0; i < 20; i++) {
+      const amountIn = new anchor.BN(1_000 + Math.floor(Math.random() * 50_000));
+
+      const quoted = await program.methods
+        .calculateSwapResult(amountIn, true)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault,
+          tokenBVault,
+          poolAuthority,
+        })
+        .view();
+
+      const before = (await getAccount(provider.connection, user1TokenB)).amount;
+      await program.methods
+        .swap(amountIn, new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      const after = (await getAccount(provider.connection, user1TokenB)).amount;
+
+      expect((after - before).toString()).to.equal(quoted.toString());
+    }
+  });
+
+  it("Reallocates a pool in place without touching its fields", async () => {
+    const before = await program.account.swapPool.fetch(swapPool.publicKey);
+
+    await program.methods
+      .reallocPool()
+      .accounts({
+        swapPool: swapPool.publicKey,
+        admin: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([admin])
+      .rpc();
+
+    const after = await program.account.swapPool.fetch(swapPool.publicKey);
+    expect(after.admin.toBase58()).to.equal(before.admin.toBase58());
+    expect(after.feeRate.toString()).to.equal(before.feeRate.toString());
+    expect(after.tokenAVault.toBase58()).to.equal(before.tokenAVault.toBase58());
+
+    try {
+      await program.methods
+        .reallocPool()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          admin: user1.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user1])
+        .rpc();
+      expect.fail("non-admin realloc should fail");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+  });
+
+  it("Migrates a pool in one call and leaves an up-to-date pool as it was", async () => {
+    const migrateAs = (signer: Keypair) =>
+      program.methods
+        .migratePool()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          admin: signer.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([signer])
+        .rpc();
+    const before = await program.account.swapPool.fetch(swapPool.publicKey);
+
+    await migrateAs(admin);
+
+    const after = await program.account.swapPool.fetch(swapPool.publicKey);
+    expect(after.version).to.equal(before.version);
+    expect(after.feeAuthority.toBase58()).to.equal(before.feeAuthority.toBase58());
+    expect(after.protocolFeeShare.toString()).to.equal(before.protocolFeeShare.toString());
+    expect(after.minInitialLp.toString()).to.equal(before.minInitialLp.toString());
+    expect(after.authorityBump).to.equal(before.authorityBump);
+    const info = await provider.connection.getAccountInfo(swapPool.publicKey);
+    expect(info.data.length).to.equal(program.account.swapPool.size);
+    expect(after.vaultABump).to.equal(before.vaultABump);
+    expect(after.vaultBBump).to.equal(before.vaultBBump);
+
+    // The migrated pool still passes every vault and fee vault constraint
+    const userBBefore = (await getAccount(provider.connection, user1TokenB)).amount;
+    await program.methods
+      .swap(new anchor.BN(10_000), new anchor.BN(0), true, false)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        feeDestinationA: null,
+        feeDestinationB: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        feeExemption: null,
+      })
+      .signers([user1])
+      .rpc();
+    expect((await getAccount(provider.connection, user1TokenB)).amount > userBBefore).to.be.true;
+
+    const userABefore = (await getAccount(provider.connection, user1TokenA)).amount;
+    await program.methods
+      .removeLiquidity(new anchor.BN(1_000), new anchor.BN(0), new anchor.BN(0))
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        userLpToken: user1LpToken,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user1])
+      .rpc();
+    expect((await getAccount(provider.connection, user1TokenA)).amount > userABefore).to.be.true;
+
+    try {
+      await migrateAs(user1);
+      expect.fail("non-admin migration should fail");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+  });
+
+  describe("flash_swap", () => {
+    // The SPL token program doubles as the callback: its Transfer repays the vault
+    const flashSwap = async (amountOut: number, repayA: number) => {
+      const repayIx = createTransferInstruction(user1TokenA, tokenAVault, user1.publicKey, repayA);
+      return program.methods
+        .flashSwap(new anchor.BN(amountOut), true, repayIx.data)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          receiverTokenA: user1TokenA,
+          receiverTokenB: user1TokenB,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([
+          { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+          ...repayIx.keys,
+        ])
+        .signers([user1])
+        .rpc();
+    };
+
+    it("Pays out and accepts a repayment that covers the invariant and fee", async () => {
+      const reserveA = BigInt((await getAccount(provider.connection, tokenAVault)).amount);
+      const reserveB = BigInt((await getAccount(provider.connection, tokenBVault)).amount);
+      const amountOut = 10_000n;
+      const fee = (amountOut * BigInt(FEE_RATE) + 9_999n) / 10_000n;
+      const repay = (reserveA * reserveB) / (reserveB - amountOut - fee) - reserveA + 1n;
+
+      const before = (await getAccount(provider.connection, user1TokenB)).amount;
+      await flashSwap(Number(amountOut), Number(repay));
+      const after = (await getAccount(provider.connection, user1TokenB)).amount;
+
+      expect((after - before).toString()).to.equal(amountOut.toString());
+    });
+
+    it("Reverts when the callback does not repay enough", async () => {
+      try {
+        await flashSwap(10_000, 1);
+        expect.fail("under-repaid flash swap should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("FlashSwapNotRepaid");
+      }
+    });
+  });
+
+  describe("flash_loan", () => {
+    // As in the flash_swap tests, the SPL token program is the callee and its Transfer repays the vault
+    const flashLoan = async (amount: number, repay: number) => {
+      const repayIx = createTransferInstruction(user1TokenA, tokenAVault, user1.publicKey, repay);
+      return program.methods
+        .flashLoan(new anchor.BN(amount), true, repayIx.data)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          receiverTokenA: user1TokenA,
+          receiverTokenB: user1TokenB,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts([
+          { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+          ...repayIx.keys,
+        ])
+        .signers([user1])
+        .rpc();
+    };
+
+    it("Lends from the vault and accrues the fee once repaid", async () => {
+      const amount = 50_000n;
+      const fee = (amount * BigInt(FEE_RATE) + 9_999n) / 10_000n;
+      const vaultBefore = (await getAccount(provider.connection, tokenAVault)).amount;
+      const feeVaultBefore = (await getAccount(provider.connection, feeVaultA)).amount;
+      const poolBefore = await program.account.swapPool.fetch(swapPool.publicKey);
+
+      await flashLoan(Number(amount), Number(amount + fee));
+
+      const vaultAfter = (await getAccount(provider.connection, tokenAVault)).amount;
+      const feeVaultAfter = (await getAccount(provider.connection, feeVaultA)).amount;
+      const poolAfter = await program.account.swapPool.fetch(swapPool.publicKey);
+      // The LP share stays in the vault; the protocol share is swept into the fee vault
+      const protocolFee = (fee * BigInt(poolAfter.protocolFeeShare.toString())) / 10_000n;
+      expect((vaultAfter - vaultBefore).toString()).to.equal((fee - protocolFee).toString());
+      expect((feeVaultAfter - feeVaultBefore).toString()).to.equal(protocolFee.toString());
+      expect(poolAfter.totalFeesA.sub(poolBefore.totalFeesA).toString()).to.equal(protocolFee.toString());
+    });
+
+    it("Reverts when only the principal is repaid", async () => {
+      try {
+        await flashLoan(50_000, 50_000);
+        expect.fail("a loan repaid without the fee should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("FlashLoanNotRepaid");
+      }
+    });
+  });
+
+  describe("max_output_fraction_bps", () => {
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+
+    const setLimit = (bps: number) =>
+      program.methods
+        .updateMaxOutputFractionBps(new anchor.BN(bps))
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    before(() => setLimit(100)); // 1% of the destination reserve
+    after(() => setLimit(10000));
+
+    it("Allows a swap within the limit", async () => {
+      await swapAToB(1_000);
+    });
+
+    it("Rejects a swap that would take more than the limit", async () => {
+      try {
+        await swapAToB(200_000);
+        expect.fail("swap above the output limit should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("OutputExceedsLimit");
+      }
+    });
+  });
+
+  it("Describes the pool with its layout version", async () => {
+    const description = await program.methods
+      .describePool()
+      .accounts({ swapPool: swapPool.publicKey })
+      .view();
+    const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+
+    // Fresh pools start at the program's current layout version
+    expect(description.version).to.be.greaterThan(0);
+    expect(description.version).to.equal(pool.version);
+    expect(description.pool.version).to.equal(pool.version);
+    expect(description.pool.feeRate.toString()).to.equal(pool.feeRate.toString());
+    expect(description.pool.lpMint.toBase58()).to.equal(pool.lpMint.toBase58());
+
+    // Already at the current version, so there is nothing to upgrade to
+    try {
+      await program.methods
+        .updatePoolVersion(pool.version)
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      expect.fail("re-applying the current version should fail");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidAmount");
+    }
+  });
+
+  it("Returns the pool configuration without decoding the account", async () => {
+    const config = await program.methods
+      .getPoolConfig()
+      .accounts({ swapPool: swapPool.publicKey })
+      .view();
+    const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+
+    expect(config.feeRate.toString()).to.equal(pool.feeRate.toString());
+    expect(config.isPaused).to.equal(pool.pausedOps !== 0);
+    expect(config.admin.toBase58()).to.equal(pool.admin.toBase58());
+    expect(config.feeAuthority.toBase58()).to.equal(pool.feeAuthority.toBase58());
+    expect(config.curveType).to.equal(pool.curveType);
+    expect(config.tokenAMint.toBase58()).to.equal(tokenAMint.toBase58());
+    expect(config.tokenBMint.toBase58()).to.equal(tokenBMint.toBase58());
+    expect(config.lpMint.toBase58()).to.equal(lpMint.publicKey.toBase58());
+    expect(config.tokenAVault.toBase58()).to.equal(tokenAVault.toBase58());
+    expect(config.tokenBVault.toBase58()).to.equal(tokenBVault.toBase58());
+  });
+
+  it("Shifts the quoted price as an LBP auction progresses", async () => {
+    const quoteAToB = () =>
+      program.methods
+        .calculateSwapResult(new anchor.BN(10_000), true)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault,
+          tokenBVault,
+          poolAuthority,
+        })
+        .view();
+    const configureLbp = (startWeight: number, endWeight: number, startTs: number, endTs: number) =>
+      program.methods
+        .configureLbp(
+          new anchor.BN(startWeight),
+          new anchor.BN(endWeight),
+          new anchor.BN(startTs),
+          new anchor.BN(endTs)
+        )
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    // Token A starts heavy (expensive) and loses weight over a short window
+    const now = Math.floor(Date.now() / 1000);
+    await configureLbp(9000, 1000, now, now + 6);
+
+    const early = await quoteAToB();
+    await new Promise((resolve) => setTimeout(resolve, 4000));
+    const late = await quoteAToB();
+
+    expect(late.lt(early)).to.be.true;
+
+    await configureLbp(0, 0, 0, 0);
+  });
+
+  it("Reports each LP's fee earnings in proportion to their share", async () => {
+    user2LpToken = getAssociatedTokenAddressSync(lpMint.publicKey, user2.publicKey);
+    const lpHolderOf = (user: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("lp_holder"), swapPool.publicKey.toBuffer(), user.toBuffer()],
+        program.programId
+      )[0];
+    const depositAccounts = (user: Keypair, userTokenA: PublicKey, userTokenB: PublicKey, userLpToken: PublicKey) => ({
+      swapPool: swapPool.publicKey,
+      tokenAMint,
+      tokenBMint,
+      tokenAVault,
+      tokenBVault,
+      userTokenA,
+      userTokenB,
+      lpMint: lpMint.publicKey,
+      userLpToken,
+      poolAuthority,
+      userAuthority: user.publicKey,
+      lockedLpToken: lockedLpOf(poolAuthority, lpMint.publicKey),
+      lpHistory: null,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    });
+
+    // Re-seed the emptied pool with user1, then a ten times smaller deposit from user2
+    await program.methods
+      .addInitialLiquidity(new anchor.BN(100_000_000), new anchor.BN(200_000_000), new anchor.BN(0))
+      .accounts(depositAccounts(user1, user1TokenA, user1TokenB, user1LpToken))
+      .signers([user1])
+      .rpc();
+    await program.methods
+      .addLiquidity(
+        new anchor.BN(10_000_000),
+        new anchor.BN(20_000_000),
+        new anchor.BN(0),
+        new anchor.BN(0),
+        null,
+        new anchor.BN(0)
+      )
+      .accounts(depositAccounts(user2, user2TokenA, user2TokenB, user2LpToken))
+      .signers([user2])
+      .rpc();
+
+    for (let i = 0; i < 3; i++) {
+      await program.methods
+        .swap(new anchor.BN(1_000_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    }
+
+    const earningsOf = (user: PublicKey, userLpToken: PublicKey) =>
+      program.methods
+        .getUserFeeEarnings()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          lpHolder: lpHolderOf(user),
+          userLpToken,
+        })
+        .view();
+    const [user1Earnings, user2Earnings] = [
+      await earningsOf(user1.publicKey, user1LpToken),
+      await earningsOf(user2.publicKey, user2LpToken),
+    ];
+
+    const user1Lp = Number((await getAccount(provider.connection, user1LpToken)).amount);
+    const user2Lp = Number((await getAccount(provider.connection, user2LpToken)).amount);
+
+    // All swaps were A to B, so only token B fees accrued
+    expect(user1Earnings[1].toNumber()).to.be.greaterThan(0);
+    expect(user2Earnings[1].toNumber()).to.be.greaterThan(0);
+    expect(user1Earnings[1].toNumber() / user2Earnings[1].toNumber()).to.be.closeTo(user1Lp / user2Lp, 0.01);
+  });
+
+  it("Returns the swap output as return data from swap_cpi", async () => {
+    const amountIn = new anchor.BN(50_000);
+    const quoted = await program.methods
+      .calculateSwapResult(amountIn, true)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAVault,
+        tokenBVault,
+        poolAuthority,
+      })
+      .view();
+
+    const simulation = await program.methods
+      .swapCpi(amountIn, new anchor.BN(0), true)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        feeDestinationA: null,
+        feeDestinationB: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        feeExemption: null,
+      })
+      .signers([user1])
+      .simulate();
+
+    // Same bytes a calling program would get back from get_return_data
+    const prefix = `Program return: ${program.programId.toBase58()} `;
+    const returnLog = simulation.raw.find((log) => log.startsWith(prefix));
+    const returned = Buffer.from(returnLog.slice(prefix.length), "base64").readBigUInt64LE();
+
+    expect(returned.toString()).to.equal(quoted.toString());
+  });
+
+  it("Reports realized impermanent loss when an LP exits after a price move", async () => {
+    // Push the price hard so the loss dwarfs the fees earned in between
+    await program.methods
+      .swap(new anchor.BN(50_000_000), new anchor.BN(0), true, false)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        feeDestinationA: null,
+        feeDestinationB: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        feeExemption: null,
+      })
+      .signers([user1])
+      .rpc();
+
+    const user2Lp = (await getAccount(provider.connection, user2LpToken)).amount;
+    const signature = await program.methods
+      .removeLiquidity(new anchor.BN((user2Lp / 2n).toString()), new anchor.BN(0), new anchor.BN(0))
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user2TokenA,
+        userTokenB: user2TokenB,
+        lpMint: lpMint.publicKey,
+        userLpToken: user2LpToken,
+        poolAuthority,
+        userAuthority: user2.publicKey,
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user2])
+      .rpc({ commitment: "confirmed" });
+
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(tx.meta.logMessages)];
+    const removed = events.find((event) => event.name === "LiquidityRemoved");
+
+    expect(removed).to.not.be.undefined;
+    expect(removed.data.impermanentLossBps).to.not.be.null;
+    expect(removed.data.impermanentLossBps.toNumber()).to.be.greaterThan(0);
+  });
+
+  describe("volume spike breaker", () => {
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+
+    before(() =>
+      program.methods
+        .updateVolumeBreaker(new anchor.BN(2), new anchor.BN(3))
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc()
+    );
+    after(async () => {
+      await program.methods
+        .updateVolumeBreaker(new anchor.BN(0), new anchor.BN(0))
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      // The swap after the first clear lands in the spiked bucket and may trip it again
+      await program.methods
+        .clearVolumeHalt()
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Lets steady volume through", async () => {
+      for (let i = 0; i < 3; i++) {
+        await swapAToB(100_000);
+        await sleep(2_500);
+      }
+      const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+      expect(pool.volumeHalted).to.be.false;
+    });
+
+    it("Halts swaps after a sudden spike until the admin clears it", async () => {
+      await swapAToB(1_000_000);
+
+      try {
+        await swapAToB(100_000);
+        expect.fail("swaps should be halted after a spike");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("VolumeSpikeHalt");
+      }
+
+      await program.methods
+        .clearVolumeHalt()
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await swapAToB(100_000);
+    });
+  });
+
+  it("Quotes the token deposits needed for an exact LP amount", async () => {
+    const targetLp = 1_000_000;
+    const [amountA, amountB] = await program.methods
+      .calculateTokensForLp(new anchor.BN(targetLp))
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        lpMint: lpMint.publicKey,
+      })
+      .view();
+
+    const before = Number((await getAccount(provider.connection, user2LpToken)).amount);
+    await program.methods
+      .addLiquidity(amountA, amountB, new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        userTokenA: user2TokenA,
+        userTokenB: user2TokenB,
+        lpMint: lpMint.publicKey,
+        userLpToken: user2LpToken,
+        poolAuthority,
+        userAuthority: user2.publicKey,
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .signers([user2])
+      .rpc();
+    const minted = Number((await getAccount(provider.connection, user2LpToken)).amount) - before;
+
+    expect(minted).to.be.closeTo(targetLp, targetLp / 1000);
+  });
+
+  it("Quotes a seeding deposit that mints at least the LP amount on an unseeded pool", async () => {
+    const pool = await createFreshPool([user1], 10_000_000_000n);
+    const targetLp = 1_000_000;
+    const [amountA, amountB] = await program.methods
+      .calculateTokensForLp(new anchor.BN(targetLp))
+      .accounts({
+        swapPool: pool.swapPool,
+        tokenAMint: pool.mintA,
+        tokenBMint: pool.mintB,
+        tokenAVault: pool.vaultA,
+        tokenBVault: pool.vaultB,
+        lpMint: pool.lpMint,
+      })
+      .view();
+
+    // The locked minimum comes out of the first mint, so the quote has to cover it
+    expect(amountA.toNumber()).to.equal(targetLp + MINIMUM_LIQUIDITY);
+    expect(amountB.toNumber()).to.equal(targetLp + MINIMUM_LIQUIDITY);
+
+    await program.methods
+      .addInitialLiquidity(amountA, amountB, new anchor.BN(targetLp))
+      .accounts(pool.accountsFor(user1))
+      .signers([user1])
+      .rpc();
+    const userLp = getAssociatedTokenAddressSync(pool.lpMint, user1.publicKey);
+    expect(Number((await getAccount(provider.connection, userLp)).amount)).to.equal(targetLp);
+  });
+
+  it("Grows LP redemption value on every swap when fees auto-compound", async () => {
+    const setAutoCompound = (enabled: boolean) =>
+      program.methods
+        .setAutoCompoundFees(enabled)
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    // Invariant per LP token, unaffected by the price moving with each swap
+    const valuePerLp = async () => {
+      const vaultA = await getAccount(provider.connection, tokenAVault);
+      const vaultB = await getAccount(provider.connection, tokenBVault);
+      const supply = (await getMint(provider.connection, lpMint.publicKey)).supply;
+      return Math.sqrt(Number(vaultA.amount) * Number(vaultB.amount)) / Number(supply);
+    };
+
+    await setAutoCompound(true);
+    let previous = await valuePerLp();
+    for (let i = 0; i < 4; i++) {
+      await program.methods
+        .swap(new anchor.BN(500_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+
+      const current = await valuePerLp();
+      expect(current).to.be.greaterThan(previous);
+      previous = current;
+    }
+    await setAutoCompound(false);
+  });
+
+  describe("fee exemptions", () => {
+    const feeExemptionOf = (user: PublicKey) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from("fee_exemption"), swapPool.publicKey.toBuffer(), user.toBuffer()],
+        program.programId
+      )[0];
+    const setExemptionsEnabled = (enabled: boolean) =>
+      program.methods
+        .setFeeExemptionsEnabled(enabled)
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const swapAToB = (
+      user: Keypair,
+      userTokenA: PublicKey,
+      userTokenB: PublicKey,
+      feeExemption: PublicKey | null
+    ) =>
+      program.methods
+        .swap(new anchor.BN(100_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA,
+          userTokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption,
+        })
+        .signers([user])
+        .rpc();
+    // Reference x*y=k output for 100_000 A at the current reserves, before any fee
+    const feeFreeOutput = async () => {
+      const reserveA = BigInt((await getAccount(provider.connection, tokenAVault)).amount);
+      const reserveB = BigInt((await getAccount(provider.connection, tokenBVault)).amount);
+      return reserveB - (reserveA * reserveB) / (reserveA + 100_000n);
+    };
+
+    before(async () => {
+      await setExemptionsEnabled(true);
+      await program.methods
+        .addFeeExemption(user2.publicKey)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          feeExemption: feeExemptionOf(user2.publicKey),
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([admin])
+        .rpc();
+    });
+    after(async () => {
+      await program.methods
+        .removeFeeExemption()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          feeExemption: feeExemptionOf(user2.publicKey),
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+      await setExemptionsEnabled(false);
+    });
+
+    it("Charges no fee to an exempt swapper", async () => {
+      const expected = await feeFreeOutput();
+      const before = (await getAccount(provider.connection, user2TokenB)).amount;
+      await swapAToB(user2, user2TokenA, user2TokenB, feeExemptionOf(user2.publicKey));
+      const after = (await getAccount(provider.connection, user2TokenB)).amount;
+
+      expect((after - before).toString()).to.equal(expected.toString());
+    });
+
+    it("Charges the full fee to everyone else", async () => {
+      const gross = await feeFreeOutput();
+      const expected = gross - (gross * BigInt(FEE_RATE)) / 10_000n;
+      const before = (await getAccount(provider.connection, user1TokenB)).amount;
+      await swapAToB(user1, user1TokenA, user1TokenB, null);
+      const after = (await getAccount(provider.connection, user1TokenB)).amount;
+
+      expect((after - before).toString()).to.equal(expected.toString());
+    });
+  });
+
+  it("Matches a reference x*y=k implementation across reserves and amounts", async () => {
+    const referenceOutput = (reserveIn: bigint, reserveOut: bigint, amountIn: bigint) => {
+      const k = reserveIn * reserveOut;
+      const gross = reserveOut - k / (reserveIn + amountIn);
+      return gross - (gross * BigInt(FEE_RATE)) / 10_000n;
+    };
+
+    // Each executed swap moves the reserves, so every round checks a new combination
+    for (const amountIn of [1_000n, 75_000n, 2_500_000n, 20_000_000n]) {
+      const reserveA = BigInt((await getAccount(provider.connection, tokenAVault)).amount);
+      const reserveB = BigInt((await getAccount(provider.connection, tokenBVault)).amount);
+
+      const quoted = await program.methods
+        .calculateSwapResult(new anchor.BN(amountIn.toString()), true)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault,
+          tokenBVault,
+          poolAuthority,
+        })
+        .view();
+      expect(quoted.toString()).to.equal(referenceOutput(reserveA, reserveB, amountIn).toString());
+
+      const before = (await getAccount(provider.connection, user1TokenB)).amount;
+      await program.methods
+        .swap(new anchor.BN(amountIn.toString()), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      const after = (await getAccount(provider.connection, user1TokenB)).amount;
+      expect((after - before).toString()).to.equal(referenceOutput(reserveA, reserveB, amountIn).toString());
+    }
+  });
+
+  it("Reports reserve deltas since the last checkpoint", async () => {
+    await program.methods
+      .setCheckpoint()
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAVault,
+        tokenBVault,
+        admin: admin.publicKey,
+      })
+      .signers([admin])
+      .rpc();
+    const startA = BigInt((await getAccount(provider.connection, tokenAVault)).amount);
+    const startB = BigInt((await getAccount(provider.connection, tokenBVault)).amount);
+
+    for (let i = 0; i < 2; i++) {
+      await program.methods
+        .swap(new anchor.BN(250_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    }
+
+    const [deltaA, deltaB] = await program.methods
+      .getReserveDelta()
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAVault,
+        tokenBVault,
+        poolAuthority,
+      })
+      .view();
+    const endA = BigInt((await getAccount(provider.connection, tokenAVault)).amount);
+    const endB = BigInt((await getAccount(provider.connection, tokenBVault)).amount);
+
+    expect(deltaA.toString()).to.equal((endA - startA).toString());
+    expect(deltaB.toString()).to.equal((endB - startB).toString());
+    expect(deltaA.toNumber()).to.equal(500_000);
+    expect(deltaB.toNumber()).to.be.lessThan(0);
+  });
+
+  describe("protocol_max_slippage_bps", () => {
+    const setLimit = (bps: number) =>
+      program.methods
+        .updateProtocolMaxSlippageBps(new anchor.BN(bps))
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+
+    before(() => setLimit(500)); // 5% price impact
+    after(() => setLimit(10000));
+
+    it("Allows a low-impact swap", async () => {
+      await swapAToB(10_000);
+    });
+
+    it("Blocks a high-impact swap even with min_amount_out = 0", async () => {
+      const reserveA = Number((await getAccount(provider.connection, tokenAVault)).amount);
+      try {
+        await swapAToB(Math.floor(reserveA / 4));
+        expect.fail("a 25% of reserve swap should exceed the protocol limit");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+      }
+    });
+  });
+
+  // Stands up a separate pool on new mints, funding each user with `amount` of both tokens
+  const createFreshPool = async (
+    users: Keypair[],
+    amount: bigint,
+    curveType = CURVE_CONSTANT_PRODUCT,
+    amp = 0,
+    lpDecimals: number | null = null,
+    mints?: [PublicKey, PublicKey]
+  ) => {
+    const freshPool = Keypair.generate();
+    const freshLpMint = Keypair.generate();
+    const [mintA, mintB] = mints ?? (await createOrderedMints());
+    const [authority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)],
+      program.programId
+    );
+    const [vaultA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("token_vault"), authority.toBuffer(), mintA.toBuffer()],
+      program.programId
+    );
+    const [vaultB] = PublicKey.findProgramAddressSync(
+      [Buffer.from("token_vault"), authority.toBuffer(), mintB.toBuffer()],
+      program.programId
+    );
+    const feeVaultA = feeVaultOf(authority, mintA);
+    const feeVaultB = feeVaultOf(authority, mintB);
+
+    const tokenAccounts = new Map<string, { a: PublicKey; b: PublicKey }>();
+    for (const user of users) {
+      const a = (await getOrCreateAssociatedTokenAccount(provider.connection, user, mintA, user.publicKey)).address;
+      const b = (await getOrCreateAssociatedTokenAccount(provider.connection, user, mintB, user.publicKey)).address;
+      await splMintTo(provider.connection, admin, mintA, a, admin, amount);
+      await splMintTo(provider.connection, admin, mintB, b, admin, amount);
+      tokenAccounts.set(user.publicKey.toBase58(), { a, b });
+    }
+
+    await program.methods
+      .initializePool(new anchor.BN(FEE_RATE), curveType, new anchor.BN(amp), lpDecimals)
+      .accounts({
+        swapPool: freshPool.publicKey,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAVault: vaultA,
+        tokenBVault: vaultB,
+        feeVaultA,
+        feeVaultB,
+        lpMint: freshLpMint.publicKey,
+        poolAuthority: authority,
+        admin: admin.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        rent: SYSVAR_RENT_PUBKEY,
+      })
+      .signers([admin, freshPool, freshLpMint])
+      .rpc();
+
+    return {
+      swapPool: freshPool.publicKey,
+      lpMint: freshLpMint.publicKey,
+      mintA,
+      mintB,
+      vaultA,
+      vaultB,
+      feeVaultA,
+      feeVaultB,
+      tokenAccountsOf: (user: Keypair) => tokenAccounts.get(user.publicKey.toBase58()),
+      accountsFor: (user: Keypair) => ({
+        swapPool: freshPool.publicKey,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAVault: vaultA,
+        tokenBVault: vaultB,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: tokenAccounts.get(user.publicKey.toBase58()).a,
+        userTokenB: tokenAccounts.get(user.publicKey.toBase58()).b,
+        lpMint: freshLpMint.publicKey,
+        userLpToken: getAssociatedTokenAddressSync(freshLpMint.publicKey, user.publicKey),
+        poolAuthority: authority,
+        userAuthority: user.publicKey,
+        lockedLpToken: lockedLpOf(authority, freshLpMint.publicKey),
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: SYSVAR_RENT_PUBKEY,
+      }),
+    };
+  };
+
+  describe("first depositor inflation attack", () => {
+    const attacker = user1;
+    const victim = user2;
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+
+    const donate = async (user: Keypair, amount: number, target = pool) => {
+      const accounts = target.tokenAccountsOf(user);
+      await transferChecked(provider.connection, user, accounts.a, target.mintA, target.vaultA, user, amount, TOKEN_DECIMALS);
+      await transferChecked(provider.connection, user, accounts.b, target.mintB, target.vaultB, user, amount, TOKEN_DECIMALS);
+    };
+    const seed = (amount: number) =>
+      program.methods
+        .addInitialLiquidity(new anchor.BN(amount), new anchor.BN(amount), new anchor.BN(0))
+        .accounts(pool.accountsFor(attacker))
+        .signers([attacker])
+        .rpc();
+    const deposit = (user: Keypair, amount: number, target = pool) =>
+      program.methods
+        .addLiquidity(
+          new anchor.BN(amount),
+          new anchor.BN(amount),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(target.accountsFor(user))
+        .signers([user])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([attacker, victim], 10_000_000_000n);
+
+      // Only the locked minimum should stand in the attacker's way
+      await program.methods
+        .updateMinInitialLp(new anchor.BN(0))
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Rejects add_liquidity on a pool with reserves but no LP supply", async () => {
+      // A pool holding a donation can't be seeded anymore, so keep this one apart from the seeded pool
+      const donated = await createFreshPool([attacker, victim], 10_000_000_000n);
+      await donate(attacker, 1_000, donated);
+      try {
+        await deposit(victim, 1_000_000, donated);
+        expect.fail("add_liquidity should require an existing LP supply");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolNotSeeded");
+      }
+    });
+
+    it("Rejects an initial deposit that doesn't exceed the locked minimum", async () => {
+      try {
+        await seed(MINIMUM_LIQUIDITY);
+        expect.fail("a deposit worth exactly the locked minimum should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InsufficientInitialLiquidity");
+      }
+    });
+
+    it("Makes a donation after a dust-sized seed unprofitable", async () => {
+      // Seed with the smallest deposit allowed, then inflate the share price with a large donation
+      await seed(1_024);
+      const attackerLp = getAssociatedTokenAddressSync(pool.lpMint, attacker.publicKey);
+      const attackerShares = (await getAccount(provider.connection, attackerLp)).amount;
+      expect(Number(attackerShares)).to.equal(1_024 - MINIMUM_LIQUIDITY);
+
+      const donation = 1_000_000_000;
+      await donate(attacker, donation);
+
+      // The victim still receives LP instead of rounding down to zero
+      await deposit(victim, 1_000_000);
+      const victimLp = getAssociatedTokenAddressSync(pool.lpMint, victim.publicKey);
+      expect(Number((await getAccount(provider.connection, victimLp)).amount)).to.be.greaterThan(0);
+
+      // Most of the donation now belongs to the locked liquidity, so the attacker can't recover it
+      const attackerA = pool.tokenAccountsOf(attacker).a;
+      const before = (await getAccount(provider.connection, attackerA)).amount;
+      await program.methods
+        .removeLiquidity(new anchor.BN(attackerShares.toString()), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(attacker))
+        .signers([attacker])
+        .rpc();
+      const recovered = (await getAccount(provider.connection, attackerA)).amount - before;
+      expect(Number(recovered)).to.be.lessThan(donation / 10);
+    });
+  });
+
+  describe("return data", () => {
+    const returnedU64s = async (signature: string, count: number) => {
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const data = Buffer.from(tx.meta.returnData.data[0], "base64");
+      return Array.from({ length: count }, (_, i) => data.readBigUInt64LE(i * 8));
+    };
+    const balance = async (account: PublicKey) =>
+      (await getAccount(provider.connection, account)).amount;
+    const liquidityAccounts = () => ({
+      swapPool: swapPool.publicKey,
+      tokenAMint,
+      tokenBMint,
+      tokenAVault,
+      tokenBVault,
+      feeVaultA,
+      feeVaultB,
+      userTokenA: user2TokenA,
+      userTokenB: user2TokenB,
+      lpMint: lpMint.publicKey,
+      userLpToken: user2LpToken,
+      poolAuthority,
+      userAuthority: user2.publicKey,
+      lpHistory: null,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    });
+
+    it("Returns the amounts a swap moved", async () => {
+      const aBefore = await balance(user2TokenA);
+      const bBefore = await balance(user2TokenB);
+
+      const signature = await program.methods
+        .swap(new anchor.BN(50_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user2TokenA,
+          userTokenB: user2TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user2.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user2])
+        .rpc({ commitment: "confirmed" });
+
+      const [amountIn, amountOut] = await returnedU64s(signature, 2);
+      expect(amountIn).to.equal(aBefore - (await balance(user2TokenA)));
+      expect(amountOut).to.equal((await balance(user2TokenB)) - bBefore);
+    });
+
+    it("Returns the amounts add_liquidity and remove_liquidity moved", async () => {
+      const aBefore = await balance(user2TokenA);
+      const bBefore = await balance(user2TokenB);
+      const lpBefore = await balance(user2LpToken);
+
+      const addSignature = await program.methods
+        .addLiquidity(
+          new anchor.BN(1_000_000),
+          new anchor.BN(3_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(liquidityAccounts())
+        .signers([user2])
+        .rpc({ commitment: "confirmed" });
+
+      const [addedA, addedB, minted] = await returnedU64s(addSignature, 3);
+      const aAfterAdd = await balance(user2TokenA);
+      const bAfterAdd = await balance(user2TokenB);
+      expect(addedA).to.equal(aBefore - aAfterAdd);
+      expect(addedB).to.equal(bBefore - bAfterAdd);
+      expect(minted).to.equal((await balance(user2LpToken)) - lpBefore);
+
+      const removeSignature = await program.methods
+        .removeLiquidity(new anchor.BN(minted.toString()), new anchor.BN(0), new anchor.BN(0))
+        .accounts(liquidityAccounts())
+        .signers([user2])
+        .rpc({ commitment: "confirmed" });
+
+      const [removedA, removedB, burned] = await returnedU64s(removeSignature, 3);
+      expect(removedA).to.equal((await balance(user2TokenA)) - aAfterAdd);
+      expect(removedB).to.equal((await balance(user2TokenB)) - bAfterAdd);
+      expect(burned).to.equal(minted);
+      expect(await balance(user2LpToken)).to.equal(lpBefore);
+    });
+  });
+
+  describe("initial LP integer square root", () => {
+    const U64_MAX = 18_446_744_073_709_551_615n;
+
+    // Seeds a new pool and returns the LP supply minted by the geometric mean
+    const seededSupply = async (amountA: bigint, amountB: bigint) => {
+      const pool = await createFreshPool([user1], amountA > amountB ? amountA : amountB);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(amountA.toString()), new anchor.BN(amountB.toString()), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      return (await getMint(provider.connection, pool.lpMint)).supply;
+    };
+
+    it("Mints the exact root of a perfect square", async () => {
+      expect(await seededSupply(1_000_000n, 4_000_000n)).to.equal(2_000_000n);
+    });
+
+    it("Rounds a non-square product down", async () => {
+      // 999_999 * 1_000_001 = 10^12 - 1, whose root is just under 10^6
+      expect(await seededSupply(999_999n, 1_000_001n)).to.equal(999_999n);
+    });
+
+    it("Handles deposits near u64::MAX without overflow", async () => {
+      expect(await seededSupply(U64_MAX, U64_MAX)).to.equal(U64_MAX);
+      // sqrt(M * (M - 1)) lies strictly between M - 1 and M
+      expect(await seededSupply(U64_MAX, U64_MAX - 1n)).to.equal(U64_MAX - 1n);
+    });
+  });
+
+  describe("withdrawal rounding dust", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const vaultAmounts = async () => [
+      (await getAccount(provider.connection, pool.vaultA)).amount,
+      (await getAccount(provider.connection, pool.vaultB)).amount,
+    ];
+    const lpBalance = async (user: Keypair) =>
+      (await getAccount(provider.connection, getAssociatedTokenAddressSync(pool.lpMint, user.publicKey))).amount;
+    const withdraw = (user: Keypair, lpAmount: bigint) =>
+      program.methods
+        .removeLiquidity(new anchor.BN(lpAmount.toString()), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user))
+        .signers([user])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1, user2], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(3_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Leaves nothing behind but the locked liquidity's share after many cycles", async () => {
+      // Odd, unbalanced amounts so every deposit and withdrawal rounds
+      for (let i = 0; i < 10; i++) {
+        await program.methods
+          .addLiquidity(
+            new anchor.BN(1_000_003 + i),
+            new anchor.BN(3_000_017 + i),
+            new anchor.BN(0),
+            new anchor.BN(0),
+            null,
+            new anchor.BN(0)
+          )
+          .accounts(pool.accountsFor(user2))
+          .signers([user2])
+          .rpc();
+        await withdraw(user2, await lpBalance(user2));
+      }
+
+      // Dust from the cycles stayed in reserves for the remaining LPs; now the seeder exits too
+      const [reserveA, reserveB] = await vaultAmounts();
+      const supply = (await getMint(provider.connection, pool.lpMint)).supply;
+      await withdraw(user1, await lpBalance(user1));
+
+      // Exactly the locked LP's pro-rata share (rounded in its favor) is left in the vaults
+      const ceilShare = (reserve: bigint) =>
+        (BigInt(MINIMUM_LIQUIDITY) * reserve + supply - 1n) / supply;
+      const [leftA, leftB] = await vaultAmounts();
+      expect(leftA).to.equal(ceilShare(reserveA));
+      expect(leftB).to.equal(ceilShare(reserveB));
+      expect((await getMint(provider.connection, pool.lpMint)).supply).to.equal(BigInt(MINIMUM_LIQUIDITY));
+    });
+  });
+
+  it("Tracks cumulative swap volume on both sides", async () => {
+    const volume = () =>
+      program.methods
+        .getPoolVolume()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault,
+          tokenBVault,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+        })
+        .view();
+
+    const [volumeABefore, volumeBBefore] = await volume();
+    const bBefore = (await getAccount(provider.connection, user1TokenB)).amount;
+
+    await program.methods
+      .swap(new anchor.BN(200_000), new anchor.BN(0), true, false)
+      .accounts({
+        swapPool: swapPool.publicKey,
+        tokenAMint,
+        tokenBMint,
+        tokenAVault,
+        tokenBVault,
+        feeVaultA,
+        feeVaultB,
+        userTokenA: user1TokenA,
+        userTokenB: user1TokenB,
+        lpMint: lpMint.publicKey,
+        poolAuthority,
+        userAuthority: user1.publicKey,
+        feeDestinationA: null,
+        feeDestinationB: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        feeExemption: null,
+      })
+      .signers([user1])
+      .rpc();
+
+    const [volumeAAfter, volumeBAfter] = await volume();
+    const received = (await getAccount(provider.connection, user1TokenB)).amount - bBefore;
+
+    // Token A was the input and token B the output
+    expect(volumeAAfter.sub(volumeABefore).toNumber()).to.equal(200_000);
+    expect(volumeBAfter.sub(volumeBBefore).toString()).to.equal(received.toString());
+  });
+
+  describe("get_available_operations", () => {
+    const available = () =>
+      program.methods
+        .getAvailableOperations()
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAVault,
+          tokenBVault,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+        })
+        .view();
+    const adminCall = (builder: any) =>
+      builder
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+    after(async () => {
+      await adminCall(program.methods.setPaused(0));
+      await adminCall(program.methods.updatePriceBand(new anchor.BN(0), new anchor.BN(0)));
+      await adminCall(program.methods.updateMaxHolders(0));
+    });
+
+    it("Reports every operation available on a healthy pool", async () => {
+      const ops = await available();
+      expect(ops.swapAToB).to.be.true;
+      expect(ops.swapBToA).to.be.true;
+      expect(ops.deposit).to.be.true;
+      expect(ops.depositNewHolder).to.be.true;
+      expect(ops.withdraw).to.be.true;
+    });
+
+    it("Reports nothing available while paused", async () => {
+      await adminCall(program.methods.pauseAll());
+      const ops = await available();
+      expect(ops.swapAToB).to.be.false;
+      expect(ops.swapBToA).to.be.false;
+      expect(ops.deposit).to.be.false;
+      expect(ops.depositNewHolder).to.be.false;
+      expect(ops.withdraw).to.be.false;
+      await adminCall(program.methods.setPaused(0));
+    });
+
+    it("Blocks only the swap direction that leaves the price band", async () => {
+      const reserveA = (await getAccount(provider.connection, tokenAVault)).amount;
+      const reserveB = (await getAccount(provider.connection, tokenBVault)).amount;
+      const price = (reserveB * 1_000_000n) / reserveA;
+
+      // The price sits exactly on the lower edge, so selling A can't go any lower
+      await adminCall(program.methods.updatePriceBand(new anchor.BN(price.toString()), new anchor.BN(0)));
+      const ops = await available();
+      expect(ops.swapAToB).to.be.false;
+      expect(ops.swapBToA).to.be.true;
+      await adminCall(program.methods.updatePriceBand(new anchor.BN(0), new anchor.BN(0)));
+    });
+
+    it("Blocks deposits from new holders once the holder cap is full", async () => {
+      const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+      await adminCall(program.methods.updateMaxHolders(pool.holderCount));
+      const ops = await available();
+      expect(ops.deposit).to.be.true;
+      expect(ops.depositNewHolder).to.be.false;
+      await adminCall(program.methods.updateMaxHolders(0));
+    });
+  });
+
+  describe("events", () => {
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const eventOf = async (signature: string, name: string) => {
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const event = [...parser.parseLogs(tx.meta.logMessages)].find((e) => e.name === name);
+      expect(event, `${name} event`).to.not.be.undefined;
+      return event.data;
+    };
+    const expectReserves = async (data: any) => {
+      expect(data.reserveA.toString()).to.equal((await getAccount(provider.connection, tokenAVault)).amount.toString());
+      expect(data.reserveB.toString()).to.equal((await getAccount(provider.connection, tokenBVault)).amount.toString());
+    };
+    const liquidityAccounts = () => ({
+      swapPool: swapPool.publicKey,
+      tokenAMint,
+      tokenBMint,
+      tokenAVault,
+      tokenBVault,
+      feeVaultA,
+      feeVaultB,
+      userTokenA: user2TokenA,
+      userTokenB: user2TokenB,
+      lpMint: lpMint.publicKey,
+      userLpToken: user2LpToken,
+      poolAuthority,
+      userAuthority: user2.publicKey,
+      lpHistory: null,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    });
+
+    it("Emits SwapExecuted with the fee and resulting reserves", async () => {
+      const bBefore = (await getAccount(provider.connection, user1TokenB)).amount;
+      const signature = await program.methods
+        .swap(new anchor.BN(300_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc({ commitment: "confirmed" });
+
+      const data = await eventOf(signature, "SwapExecuted");
+      const received = (await getAccount(provider.connection, user1TokenB)).amount - bBefore;
+      expect(data.swapPool.toString()).to.equal(swapPool.publicKey.toString());
+      expect(data.user.toString()).to.equal(user1.publicKey.toString());
+      expect(data.inputMint.toString()).to.equal(tokenAMint.toString());
+      expect(data.amountIn.toNumber()).to.equal(300_000);
+      expect(data.amountOut.toString()).to.equal(received.toString());
+      expect(data.feeAmount.toNumber()).to.be.greaterThan(0);
+      await expectReserves(data);
+    });
+
+    it("Emits LiquidityAdded and LiquidityRemoved with the resulting reserves", async () => {
+      const addSignature = await program.methods
+        .addLiquidity(
+          new anchor.BN(1_000_000),
+          new anchor.BN(3_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(liquidityAccounts())
+        .signers([user2])
+        .rpc({ commitment: "confirmed" });
+
+      const added = await eventOf(addSignature, "LiquidityAdded");
+      expect(added.owner.toString()).to.equal(user2.publicKey.toString());
+      expect(added.lpAmount.toNumber()).to.be.greaterThan(0);
+      await expectReserves(added);
+
+      const removeSignature = await program.methods
+        .removeLiquidity(added.lpAmount, new anchor.BN(0), new anchor.BN(0))
+        .accounts(liquidityAccounts())
+        .signers([user2])
+        .rpc({ commitment: "confirmed" });
+
+      const removed = await eventOf(removeSignature, "LiquidityRemoved");
+      expect(removed.lpAmount.toString()).to.equal(added.lpAmount.toString());
+      await expectReserves(removed);
+    });
+
+    it("Emits FeesCollected with the collected amounts", async () => {
+      const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+      const signature = await program.methods
+        .collectFees(null, null)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          feeCollector: admin.publicKey,
+          feeCollectorTokenA: adminTokenA,
+          feeCollectorTokenB: adminTokenB,
+          poolAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc({ commitment: "confirmed" });
+
+      const data = await eventOf(signature, "FeesCollected");
+      expect(data.collector.toString()).to.equal(admin.publicKey.toString());
+      expect(data.amountA.toString()).to.equal(pool.totalFeesA.toString());
+      expect(data.amountB.toString()).to.equal(pool.totalFeesB.toString());
+      await expectReserves(data);
+    });
+  });
+
+  describe("max_imbalance_bps", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const adminCall = (builder: any) =>
+      builder
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const deposit = (amountA: number, amountB: number) =>
+      program.methods
+        .addLiquidity(
+          new anchor.BN(amountA),
+          new anchor.BN(amountB),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(pool.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+
+    before(async () => {
+      // 3:1 reserves at equal decimals are 5000 bps away from balance
+      pool = await createFreshPool([user1, user2], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(3_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      await adminCall(program.methods.updateMaxImbalanceBps(new anchor.BN(2_000)));
+      // Take deposits as given so they can move the balance
+      await adminCall(program.methods.updateBalanceIncentiveBps(new anchor.BN(100)));
+    });
+
+    it("Allows a deposit that moves the pool toward balance", async () => {
+      await deposit(1_000_000, 5_000_000);
+    });
+
+    it("Rejects a deposit that worsens the imbalance", async () => {
+      try {
+        await deposit(5_000_000, 1_000_000);
+        expect.fail("an imbalancing deposit should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolImbalanced");
+      }
+    });
+
+    it("Rejects a swap that worsens the imbalance", async () => {
+      const accounts = pool.tokenAccountsOf(user1);
+      try {
+        await program.methods
+          .swap(new anchor.BN(1_000_000), new anchor.BN(0), true, false)
+          .accounts({
+            swapPool: pool.swapPool,
+            tokenAMint: pool.mintA,
+            tokenBMint: pool.mintB,
+            tokenAVault: pool.vaultA,
+            tokenBVault: pool.vaultB,
+            feeVaultA: pool.feeVaultA,
+            feeVaultB: pool.feeVaultB,
+            userTokenA: accounts.a,
+            userTokenB: accounts.b,
+            lpMint: pool.lpMint,
+            poolAuthority: pool.accountsFor(user1).poolAuthority,
+            userAuthority: user1.publicKey,
+            feeDestinationA: null,
+            feeDestinationB: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+            feeExemption: null,
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("selling more of the heavy token should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolImbalanced");
+      }
+    });
+  });
+
+  describe("protocol_fee_share", () => {
+    const setShare = (bps: number) =>
+      program.methods
+        .updateProtocolFeeShare(new anchor.BN(bps))
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc({ commitment: "confirmed" });
+    // Invariant per LP token squared over the vault reserves; uncollected protocol fees sit in the fee vaults
+    const lpValue = async () => {
+      const reserveA = (await getAccount(provider.connection, tokenAVault)).amount;
+      const reserveB = (await getAccount(provider.connection, tokenBVault)).amount;
+      const supply = (await getMint(provider.connection, lpMint.publicKey)).supply;
+      return (reserveA * reserveB * 1_000_000_000_000n) / (supply * supply);
+    };
+
+    after(() => setShare(10000));
+
+    it("Rejects a share above 100%", async () => {
+      try {
+        await setShare(10001);
+        expect.fail("a share above 10000 bps should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("FeeTooHigh");
+      }
+    });
+
+    it("Accrues only the protocol share and leaves the rest to LPs", async () => {
+      await setShare(5000);
+      const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+      const valueBefore = await lpValue();
+
+      for (let i = 0; i < 3; i++) {
+        const feesBefore = (await program.account.swapPool.fetch(swapPool.publicKey)).totalFeesB;
+        const signature = await swapAToB(2_000_000);
+        const tx = await provider.connection.getTransaction(signature, {
+          commitment: "confirmed",
+          maxSupportedTransactionVersion: 0,
+        });
+        const executed = [...parser.parseLogs(tx.meta.logMessages)].find((e) => e.name === "SwapExecuted");
+        const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+
+        const burned = executed.data.feeAmount.muln(pool.burnFeeBps.toNumber()).divn(10000);
+        const retained = executed.data.feeAmount.sub(burned);
+        expect(pool.totalFeesB.sub(feesBefore).toString()).to.equal(retained.muln(5000).divn(10000).toString());
+      }
+
+      expect((await lpValue()) > valueBefore).to.be.true;
+    });
+  });
+
+  it("Snapshots the full pool state consistently with the individual getters", async () => {
+    const statsAccounts = {
+      swapPool: swapPool.publicKey,
+      tokenAVault,
+      tokenBVault,
+      lpMint: lpMint.publicKey,
+      poolAuthority,
+    };
+    const snapshot = await program.methods.snapshot().accounts(statsAccounts).view();
+    const [reserveA, reserveB, lpSupply] = await program.methods.getPoolStats().accounts(statsAccounts).view();
+    const [volumeA, volumeB] = await program.methods.getPoolVolume().accounts(statsAccounts).view();
+    const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+
+    expect(snapshot.version).to.equal(pool.version);
+    expect(snapshot.reserveA.toString()).to.equal(reserveA.toString());
+    expect(snapshot.reserveB.toString()).to.equal(reserveB.toString());
+    expect(snapshot.lpSupply.toString()).to.equal(lpSupply.toString());
+    expect(snapshot.pool.cumulativeVolumeA.toString()).to.equal(volumeA.toString());
+    expect(snapshot.pool.cumulativeVolumeB.toString()).to.equal(volumeB.toString());
+
+    // The embedded pool is the account itself, field for field
+    for (const [field, value] of Object.entries(pool)) {
+      expect(JSON.stringify(snapshot.pool[field]), field).to.equal(JSON.stringify(value));
+    }
+
+    // Swaps have run on this pool, and the lifetime total covers whatever is still uncollected
+    expect(snapshot.pool.lifetimeFeesB.gt(new anchor.BN(0))).to.be.true;
+    expect(snapshot.pool.lifetimeFeesB.gte(snapshot.pool.totalFeesB)).to.be.true;
+    expect(snapshot.timestamp.toNumber()).to.be.closeTo(Math.floor(Date.now() / 1000), 60);
+  });
+
+  describe("Token-2022 transfer-fee mints", () => {
+    const TRANSFER_FEE_BPS = 100;
+    const user = user1;
+    const freshPool = Keypair.generate();
+    const freshLpMint = Keypair.generate();
+    const mintA = Keypair.generate();
+    let mintB: PublicKey;
+    let authority: PublicKey;
+    let vaultA: PublicKey;
+    let vaultB: PublicKey;
+    let userA: PublicKey;
+    let userB: PublicKey;
+
+    // Token-2022 rounds the withheld fee up
+    const afterFee = (amount: bigint) => amount - (amount * BigInt(TRANSFER_FEE_BPS) + 9_999n) / 10_000n;
+    const balance = async (account: PublicKey) =>
+      (await getAccount(provider.connection, account, undefined, TOKEN_2022_PROGRAM_ID)).amount;
+    const poolAccounts = () => ({
+      swapPool: freshPool.publicKey,
+      tokenAMint: mintA.publicKey,
+      tokenBMint: mintB,
+      tokenAVault: vaultA,
+      tokenBVault: vaultB,
+      feeVaultA: feeVaultOf(authority, mintA.publicKey),
+      feeVaultB: feeVaultOf(authority, mintB),
+      userTokenA: userA,
+      userTokenB: userB,
+      lpMint: freshLpMint.publicKey,
+      userLpToken: getAssociatedTokenAddressSync(freshLpMint.publicKey, user.publicKey, false, TOKEN_2022_PROGRAM_ID),
+      poolAuthority: authority,
+      userAuthority: user.publicKey,
+      lockedLpToken: lockedLpOf(authority, freshLpMint.publicKey),
+      lpHistory: null,
+      tokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    });
+
+    before(async () => {
+      const mintLen = getMintLen([ExtensionType.TransferFeeConfig]);
+      const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+      await sendAndConfirmTransaction(
+        provider.connection,
+        new Transaction().add(
+          SystemProgram.createAccount({
+            fromPubkey: admin.publicKey,
+            newAccountPubkey: mintA.publicKey,
+            space: mintLen,
+            lamports,
+            programId: TOKEN_2022_PROGRAM_ID,
+          }),
+          createInitializeTransferFeeConfigInstruction(
+            mintA.publicKey,
+            admin.publicKey,
+            admin.publicKey,
+            TRANSFER_FEE_BPS,
+            BigInt(Number.MAX_SAFE_INTEGER),
+            TOKEN_2022_PROGRAM_ID
+          ),
+          createInitializeMintInstruction(mintA.publicKey, TOKEN_DECIMALS, admin.publicKey, null, TOKEN_2022_PROGRAM_ID)
+        ),
+        [admin, mintA]
+      );
+      // The transfer-fee mint has to be token A, so draw B's address until it sorts after A
+      let mintBKeypair = Keypair.generate();
+      while (Buffer.compare(mintBKeypair.publicKey.toBuffer(), mintA.publicKey.toBuffer()) < 0) {
+        mintBKeypair = Keypair.generate();
+      }
+      mintB = await createMint(
+        provider.connection,
+        admin,
+        admin.publicKey,
+        null,
+        TOKEN_DECIMALS,
+        mintBKeypair,
+        undefined,
+        TOKEN_2022_PROGRAM_ID
+      );
+
+      [authority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool_authority"), mintA.publicKey.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)],
+        program.programId
+      );
+      [vaultA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("token_vault"), authority.toBuffer(), mintA.publicKey.toBuffer()],
+        program.programId
+      );
+      [vaultB] = PublicKey.findProgramAddressSync(
+        [Buffer.from("token_vault"), authority.toBuffer(), mintB.toBuffer()],
+        program.programId
+      );
+
+      userA = (
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection, user, mintA.publicKey, user.publicKey, false, undefined, undefined, TOKEN_2022_PROGRAM_ID
+        )
+      ).address;
+      userB = (
+        await getOrCreateAssociatedTokenAccount(
+          provider.connection, user, mintB, user.publicKey, false, undefined, undefined, TOKEN_2022_PROGRAM_ID
+        )
+      ).address;
+      await splMintTo(provider.connection, admin, mintA.publicKey, userA, admin, 10_000_000_000n, [], undefined, TOKEN_2022_PROGRAM_ID);
+      await splMintTo(provider.connection, admin, mintB, userB, admin, 10_000_000_000n, [], undefined, TOKEN_2022_PROGRAM_ID);
+
+      await program.methods
+        .initializePool(new anchor.BN(FEE_RATE), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
+        .accounts({
+          swapPool: freshPool.publicKey,
+          tokenAMint: mintA.publicKey,
+          tokenBMint: mintB,
+          tokenAVault: vaultA,
+          tokenBVault: vaultB,
+          feeVaultA: feeVaultOf(authority, mintA.publicKey),
+          feeVaultB: feeVaultOf(authority, mintB),
+          lpMint: freshLpMint.publicKey,
+          poolAuthority: authority,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([admin, freshPool, freshLpMint])
+        .rpc();
+
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(poolAccounts())
+        .signers([user])
+        .rpc();
+    });
+
+    it("Mints LP against the amounts the vaults actually received", async () => {
+      const [reserveA, reserveB, userABefore] = await Promise.all([balance(vaultA), balance(vaultB), balance(userA)]);
+      const supply = (await getMint(provider.connection, freshLpMint.publicKey, undefined, TOKEN_2022_PROGRAM_ID)).supply;
+      const lpBefore = await balance(poolAccounts().userLpToken);
+
+      await program.methods
+        .addLiquidity(
+          new anchor.BN(100_000_000),
+          new anchor.BN(200_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(poolAccounts())
+        .signers([user])
+        .rpc();
+
+      const sentA = userABefore - (await balance(userA));
+      const receivedA = (await balance(vaultA)) - reserveA;
+      const receivedB = (await balance(vaultB)) - reserveB;
+      expect(receivedA.toString()).to.equal(afterFee(sentA).toString());
+
+      const lpForA = (receivedA * supply) / reserveA;
+      const lpForB = (receivedB * supply) / reserveB;
+      const minted = (await balance(poolAccounts().userLpToken)) - lpBefore;
+      expect(minted.toString()).to.equal((lpForA < lpForB ? lpForA : lpForB).toString());
+    });
+
+    it("Prices a swap on the input the vault actually received", async () => {
+      const amountIn = 10_000_000n;
+      const reserveA = await balance(vaultA);
+      const quoted = await program.methods
+        .calculateSwapResult(new anchor.BN(afterFee(amountIn).toString()), true)
+        .accounts({ swapPool: freshPool.publicKey, tokenAVault: vaultA, tokenBVault: vaultB, poolAuthority: authority })
+        .view();
+
+      const userBBefore = await balance(userB);
+      await program.methods
+        .swap(new anchor.BN(amountIn.toString()), new anchor.BN(0), true, false)
+        .accounts({
+          ...poolAccounts(),
+          feeDestinationA: null,
+          feeDestinationB: null,
+          feeExemption: null,
+        })
+        .signers([user])
+        .rpc();
+
+      expect(((await balance(vaultA)) - reserveA).toString()).to.equal(afterFee(amountIn).toString());
+      expect(((await balance(userB)) - userBBefore).toString()).to.equal(quoted.toString());
+    });
+  });
+
+  describe("remove_liquidity_single", () => {
+    // Two identically seeded pools: one exits in a single call, the other removes and then swaps
+    let single: Awaited<ReturnType<typeof createFreshPool>>;
+    let manual: Awaited<ReturnType<typeof createFreshPool>>;
+    const balance = async (account: PublicKey) => (await getAccount(provider.connection, account)).amount;
+    const lpBalance = async (pool: typeof single) =>
+      balance(getAssociatedTokenAddressSync(pool.lpMint, user2.publicKey));
+
+    before(async () => {
+      single = await createFreshPool([user1, user2], 10_000_000_000n);
+      manual = await createFreshPool([user1, user2], 10_000_000_000n);
+      for (const pool of [single, manual]) {
+        await program.methods
+          .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(3_000_000_000), new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc();
+        await program.methods
+          .addLiquidity(
+            new anchor.BN(100_000_000),
+            new anchor.BN(300_000_000),
+            new anchor.BN(0),
+            new anchor.BN(0),
+            null,
+            new anchor.BN(0)
+          )
+          .accounts(pool.accountsFor(user2))
+          .signers([user2])
+          .rpc();
+      }
+    });
+
+    it("Rejects a single-sided exit below min_amount_out", async () => {
+      try {
+        await program.methods
+          .removeLiquiditySingle(new anchor.BN((await lpBalance(single)).toString()), false, new anchor.BN(600_000_000))
+          .accounts(single.accountsFor(user2))
+          .signers([user2])
+          .rpc();
+        expect.fail("a conversion short of min_amount_out should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+      }
+    });
+
+    it("Pays the same as removing liquidity and swapping the other side", async () => {
+      const lpAmount = new anchor.BN((await lpBalance(single)).toString());
+      const singleB = single.tokenAccountsOf(user2).b;
+      const manualA = manual.tokenAccountsOf(user2).a;
+      const manualB = manual.tokenAccountsOf(user2).b;
+
+      const singleBefore = await balance(singleB);
+      await program.methods
+        .removeLiquiditySingle(lpAmount, false, new anchor.BN(0))
+        .accounts(single.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+      const singleOut = (await balance(singleB)) - singleBefore;
+
+      const [aBefore, bBefore] = [await balance(manualA), await balance(manualB)];
+      await program.methods
+        .removeLiquidity(lpAmount, new anchor.BN(0), new anchor.BN(0))
+        .accounts(manual.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+      const removedA = (await balance(manualA)) - aBefore;
+      await program.methods
+        .swap(new anchor.BN(removedA.toString()), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: manual.swapPool,
+          tokenAMint: manual.mintA,
+          tokenBMint: manual.mintB,
+          tokenAVault: manual.vaultA,
+          tokenBVault: manual.vaultB,
+          feeVaultA: manual.feeVaultA,
+          feeVaultB: manual.feeVaultB,
+          userTokenA: manualA,
+          userTokenB: manualB,
+          lpMint: manual.lpMint,
+          poolAuthority: manual.accountsFor(user2).poolAuthority,
+          userAuthority: user2.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user2])
+        .rpc();
+      const manualOut = (await balance(manualB)) - bBefore;
+
+      expect(singleOut).to.equal(manualOut);
+      expect(await balance(single.vaultA)).to.equal(await balance(manual.vaultA));
+      expect(await balance(single.vaultB)).to.equal(await balance(manual.vaultB));
+
+      // The internal conversion accrues its fee just like the swap did
+      const [singlePool, manualPool] = await Promise.all([
+        program.account.swapPool.fetch(single.swapPool),
+        program.account.swapPool.fetch(manual.swapPool),
+      ]);
+      expect(singlePool.totalFeesB.gt(new anchor.BN(0))).to.be.true;
+      expect(singlePool.totalFeesB.toString()).to.equal(manualPool.totalFeesB.toString());
+    });
+  });
+
+  describe("real-time fees", () => {
+    // user2 only holds the fee destinations, so anything it receives is a fee payout
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const balance = async (account: PublicKey) => (await getAccount(provider.connection, account)).amount;
+    const destinations = () => ({
+      feeDestinationA: pool.tokenAccountsOf(user2).a,
+      feeDestinationB: pool.tokenAccountsOf(user2).b,
+    });
+
+    before(async () => {
+      pool = await createFreshPool([user1, user2], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(3_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      await program.methods
+        .setRealtimeFees(true, destinations().feeDestinationA, destinations().feeDestinationB)
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Pays the conversion fee of a single-sided exit to the fee destination", async () => {
+      const removeSingle = (feeDestinations: { feeDestinationA: PublicKey | null; feeDestinationB: PublicKey | null }) =>
+        program.methods
+          .removeLiquiditySingle(new anchor.BN(100_000_000), false, new anchor.BN(0))
+          .accounts({ ...pool.accountsFor(user1), ...feeDestinations })
+          .signers([user1])
+          .rpc();
+
+      try {
+        await removeSingle({ feeDestinationA: null, feeDestinationB: null });
+        expect.fail("a real-time pool needs the fee destination");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("MissingFeeDestination");
+      }
+
+      const destinationBefore = await balance(destinations().feeDestinationB);
+      const feeVaultBefore = await balance(pool.feeVaultB);
+      await removeSingle(destinations());
+
+      expect((await balance(destinations().feeDestinationB)) > destinationBefore).to.be.true;
+      expect((await balance(pool.feeVaultB)).toString()).to.equal(feeVaultBefore.toString());
+      expect((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toNumber()).to.equal(0);
+    });
+
+    it("Pays the fee of a flash loan and a flash swap to the fee destination", async () => {
+      const { a: userA, b: userB } = pool.tokenAccountsOf(user1);
+      const accounts = {
+        swapPool: pool.swapPool,
+        tokenAMint: pool.mintA,
+        tokenBMint: pool.mintB,
+        tokenAVault: pool.vaultA,
+        tokenBVault: pool.vaultB,
+        feeVaultA: pool.feeVaultA,
+        feeVaultB: pool.feeVaultB,
+        receiverTokenA: userA,
+        receiverTokenB: userB,
+        poolAuthority: pool.accountsFor(user1).poolAuthority,
+        userAuthority: user1.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        ...destinations(),
+      };
+      // The SPL token program is the callback again, repaying the borrowed token with the fee on top
+      const amount = 50_000n;
+      const fee = (amount * BigInt(FEE_RATE) + 9_999n) / 10_000n;
+      const repay = (from: PublicKey, vault: PublicKey) =>
+        createTransferInstruction(from, vault, user1.publicKey, amount + fee);
+      const callback = (repayIx: ReturnType<typeof repay>) => [
+        { pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false },
+        ...repayIx.keys,
+      ];
+
+      const loanRepay = repay(userA, pool.vaultA);
+      const destinationABefore = await balance(accounts.feeDestinationA);
+      await program.methods
+        .flashLoan(new anchor.BN(amount.toString()), true, loanRepay.data)
+        .accounts(accounts)
+        .remainingAccounts(callback(loanRepay))
+        .signers([user1])
+        .rpc();
+      expect(((await balance(accounts.feeDestinationA)) - destinationABefore).toString()).to.equal(fee.toString());
+
+      const swapRepay = repay(userB, pool.vaultB);
+      const destinationBBefore = await balance(accounts.feeDestinationB);
+      await program.methods
+        .flashSwap(new anchor.BN(amount.toString()), true, swapRepay.data)
+        .accounts(accounts)
+        .remainingAccounts(callback(swapRepay))
+        .signers([user1])
+        .rpc();
+      expect(((await balance(accounts.feeDestinationB)) - destinationBBefore).toString()).to.equal(fee.toString());
+    });
+  });
+
+  describe("TWAP price accumulators", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const reserves = async () => [
+      (await getAccount(provider.connection, pool.vaultA)).amount,
+      (await getAccount(provider.connection, pool.vaultB)).amount,
+    ];
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    const observe = () =>
+      program.methods
+        .getTwap()
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+        })
+        .view();
+    const sleep = (ms: number) => new Promise((resolve) => setTimeout(resolve, ms));
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(2_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Accumulates the pre-swap price over the time it was held", async () => {
+      // Seeding only starts the clock, since no price was held before it
+      let state = await program.account.swapPool.fetch(pool.swapPool);
+      expect(state.lastPriceUpdate.toNumber()).to.be.greaterThan(0);
+      expect(state.priceCumulativeA.toString()).to.equal("0");
+
+      const start = await observe();
+      let weightedA = 0n;
+      let weightedB = 0n;
+      let lastUpdate = BigInt(state.lastPriceUpdate.toString());
+      for (const amountIn of [50_000_000, 20_000_000, 80_000_000]) {
+        await sleep(2_000);
+        const [reserveA, reserveB] = await reserves();
+        const priceA = (reserveB * 1_000_000n) / reserveA;
+        const priceB = (reserveA * 1_000_000n) / reserveB;
+        const before = state;
+
+        await swapAToB(amountIn);
+        state = await program.account.swapPool.fetch(pool.swapPool);
+        const elapsed = BigInt(state.lastPriceUpdate.sub(before.lastPriceUpdate).toString());
+        expect(elapsed > 0n).to.be.true;
+        expect(state.priceCumulativeA.sub(before.priceCumulativeA).toString()).to.equal((priceA * elapsed).toString());
+        expect(state.priceCumulativeB.sub(before.priceCumulativeB).toString()).to.equal((priceB * elapsed).toString());
+
+        // Only the part of each interval after the first observation counts towards its average
+        const from = lastUpdate > BigInt(start.timestamp.toString()) ? lastUpdate : BigInt(start.timestamp.toString());
+        weightedA += priceA * (BigInt(state.lastPriceUpdate.toString()) - from);
+        weightedB += priceB * (BigInt(state.lastPriceUpdate.toString()) - from);
+        lastUpdate = BigInt(state.lastPriceUpdate.toString());
+      }
+
+      // Sampled right at the last update, the window's average is the time-weighted mean of the held prices
+      const [reserveA, reserveB] = await reserves();
+      await sleep(2_000);
+      const end = await observe();
+      const tail = BigInt(end.timestamp.toString()) - lastUpdate;
+      weightedA += ((reserveB * 1_000_000n) / reserveA) * tail;
+      weightedB += ((reserveA * 1_000_000n) / reserveB) * tail;
+
+      const window = BigInt(end.timestamp.sub(start.timestamp).toString());
+      const twapA = BigInt(end.priceCumulativeA.sub(start.priceCumulativeA).toString()) / window;
+      const twapB = BigInt(end.priceCumulativeB.sub(start.priceCumulativeB).toString()) / window;
+      expect(twapA.toString()).to.equal((weightedA / window).toString());
+      expect(twapB.toString()).to.equal((weightedB / window).toString());
+
+      // Selling A the whole time, so the average sits between the opening and closing spot prices
+      const closingPriceA = (reserveB * 1_000_000n) / reserveA;
+      expect(twapA >= closingPriceA).to.be.true;
+      expect(twapA <= 2_000_000n).to.be.true;
+    });
+
+    it("Closes the held price interval on deposits and withdrawals too", async () => {
+      const addLiquidity = () =>
+        program.methods
+          .addLiquidity(
+            new anchor.BN(100_000_000),
+            new anchor.BN(400_000_000),
+            new anchor.BN(0),
+            new anchor.BN(0),
+            null,
+            new anchor.BN(0)
+          )
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc();
+      const removeLiquidity = async () => {
+        const lp = (await getAccount(provider.connection, pool.accountsFor(user1).userLpToken)).amount;
+        return program.methods
+          .removeLiquidity(new anchor.BN((lp / 4n).toString()), new anchor.BN(0), new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc();
+      };
+
+      // Each operation must fold in the price held since the previous one, whatever kind it is
+      let state = await program.account.swapPool.fetch(pool.swapPool);
+      for (const operation of [addLiquidity, () => swapAToB(30_000_000), removeLiquidity, addLiquidity, removeLiquidity]) {
+        await sleep(2_000);
+        const [reserveA, reserveB] = await reserves();
+        const before = state;
+
+        await operation();
+        state = await program.account.swapPool.fetch(pool.swapPool);
+        const elapsed = BigInt(state.lastPriceUpdate.sub(before.lastPriceUpdate).toString());
+        expect(elapsed > 0n).to.be.true;
+        expect(state.priceCumulativeA.sub(before.priceCumulativeA).toString())
+          .to.equal((((reserveB * 1_000_000n) / reserveA) * elapsed).toString());
+        expect(state.priceCumulativeB.sub(before.priceCumulativeB).toString())
+          .to.equal((((reserveA * 1_000_000n) / reserveB) * elapsed).toString());
+      }
+
+      // Observed between operations, the cumulative extends from the last one at the current spot price
+      const observed = await observe();
+      const [reserveA, reserveB] = await reserves();
+      const tail = BigInt(observed.timestamp.sub(state.lastPriceUpdate).toString());
+      expect(observed.priceCumulativeA.sub(state.priceCumulativeA).toString())
+        .to.equal((((reserveB * 1_000_000n) / reserveA) * tail).toString());
+    });
+  });
+
+  describe("stable curve", () => {
+    const AMP = 100n;
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+
+    // Reference StableSwap math for two coins, written independently of the program
+    const stableD = (x: bigint, y: bigint, amp: bigint) => {
+      const ann = amp * 4n;
+      const sum = x + y;
+      let d = sum;
+      for (let i = 0; i < 255; i++) {
+        const dP = (((d * d) / (2n * x)) * d) / (2n * y);
+        const prev = d;
+        d = ((ann * sum + 2n * dP) * d) / ((ann - 1n) * d + 3n * dP);
+        if ((d > prev ? d - prev : prev - d) <= 1n) break;
+      }
+      return d;
+    };
+    const stableY = (x: bigint, d: bigint, amp: bigint) => {
+      const ann = amp * 4n;
+      const c = (((d * d) / (2n * x)) * d) / (2n * ann);
+      const b = x + d / ann;
+      let y = d;
+      for (let i = 0; i < 255; i++) {
+        const prev = y;
+        y = (y * y + c) / (2n * y + b - d);
+        if ((y > prev ? y - prev : prev - y) <= 1n) break;
+      }
+      return y;
+    };
+    const referenceOutput = (reserveIn: bigint, reserveOut: bigint, amountIn: bigint) => {
+      const y = stableY(reserveIn + amountIn, stableD(reserveIn, reserveOut, AMP), AMP);
+      const output = reserveOut - y - 1n;
+      return output - (output * BigInt(FEE_RATE)) / 10_000n;
+    };
+
+    const reserves = async () => [
+      (await getAccount(provider.connection, pool.vaultA)).amount,
+      (await getAccount(provider.connection, pool.vaultB)).amount,
+    ];
+    const swapAToB = async (amountIn: bigint) => {
+      const userB = pool.tokenAccountsOf(user1).b;
+      const before = (await getAccount(provider.connection, userB)).amount;
+      await program.methods
+        .swap(new anchor.BN(amountIn.toString()), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: userB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      return (await getAccount(provider.connection, userB)).amount - before;
+    };
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n, CURVE_STABLE, Number(AMP));
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      // Pushing the pool far off the peg takes a swap well past the default price impact cap
+      await program.methods
+        .updateMaxPriceImpactBps(new anchor.BN(0))
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Reports the curve through get_pool_stats", async () => {
+      const [, , , curveType] = await program.methods
+        .getPoolStats()
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+        })
+        .view();
+      expect(curveType).to.equal(CURVE_STABLE);
+    });
+
+    it("Matches the reference near the peg and beats constant product there", async () => {
+      const amountIn = 10_000_000n;
+      const [reserveA, reserveB] = await reserves();
+      const expected = referenceOutput(reserveA, reserveB, amountIn);
+
+      expect((await swapAToB(amountIn)).toString()).to.equal(expected.toString());
+
+      const constantProduct = reserveB - (reserveA * reserveB) / (reserveA + amountIn);
+      expect(expected > constantProduct).to.be.true;
+      // Near balance a stable swap fills close to 1:1, less the fee
+      expect(Number(expected)).to.be.closeTo(Number(amountIn) * 0.997, Number(amountIn) * 0.001);
+    });
+
+    it("Matches the reference far from balance, where slippage returns", async () => {
+      // Push the pool well past the peg
+      await swapAToB(900_000_000n);
+
+      const amountIn = 10_000_000n;
+      const [reserveA, reserveB] = await reserves();
+      const expected = referenceOutput(reserveA, reserveB, amountIn);
+
+      expect((await swapAToB(amountIn)).toString()).to.equal(expected.toString());
+      expect(Number(expected)).to.be.lessThan(Number(amountIn) * 0.9);
+    });
+  });
+
+  it("Rejects a swap that passes a mint other than the pool's", async () => {
+    // Same decimals as token A, so only the mint constraint can tell them apart
+    const impostorMint = await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS);
+    try {
+      await program.methods
+        .swap(new anchor.BN(1_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint: impostorMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      expect.fail("a mismatched mint should be rejected");
+    } catch (err) {
+      expect(err.error.errorCode.code).to.equal("InvalidToken");
+    }
+  });
+
+  describe("close_pool", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const closePool = () => {
+      const adminAccounts = pool.tokenAccountsOf(admin);
+      return program.methods
+        .closePool()
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          lpMint: pool.lpMint,
+          lockedLpToken: pool.accountsFor(admin).lockedLpToken,
+          positionLpVault: null,
+          adminTokenA: adminAccounts.a,
+          adminTokenB: adminAccounts.b,
+          poolAuthority: pool.accountsFor(admin).poolAuthority,
+          admin: admin.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc();
+    };
+
+    before(async () => {
+      pool = await createFreshPool([user1, admin], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Refuses to close while LPs still hold liquidity", async () => {
+      try {
+        await closePool();
+        expect.fail("a pool with outstanding LP should not close");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolNotEmpty");
+      }
+    });
+
+    it("Closes the pool and its vaults once fully withdrawn", async () => {
+      await program.methods
+        .removeAllLiquidity(new anchor.BN(0), new anchor.BN(0), false)
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      const adminA = pool.tokenAccountsOf(admin).a;
+      const residualA = (await getAccount(provider.connection, pool.vaultA)).amount;
+      const adminABefore = (await getAccount(provider.connection, adminA)).amount;
+      const lamportsBefore = await provider.connection.getBalance(admin.publicKey);
+
+      await closePool();
+
+      // The locked liquidity's share went to the admin along with the rent
+      expect((await getAccount(provider.connection, adminA)).amount - adminABefore).to.equal(residualA);
+      expect(await provider.connection.getBalance(admin.publicKey)).to.be.greaterThan(lamportsBefore);
+      expect(await program.account.swapPool.fetchNullable(pool.swapPool)).to.be.null;
+      expect(await provider.connection.getAccountInfo(pool.vaultA)).to.be.null;
+      expect(await provider.connection.getAccountInfo(pool.vaultB)).to.be.null;
+      expect(await provider.connection.getAccountInfo(pool.feeVaultA)).to.be.null;
+      expect(await provider.connection.getAccountInfo(pool.feeVaultB)).to.be.null;
+      expect((await getMint(provider.connection, pool.lpMint)).supply).to.equal(0n);
+    });
+  });
+
+  describe("max_price_impact_bps", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Rejects a swap that moves the price past the default cap", async () => {
+      const state = await program.account.swapPool.fetch(pool.swapPool);
+      expect(state.maxPriceImpactBps.toNumber()).to.equal(5_000);
+
+      // Adding half the A reserve cuts the B-per-A ratio to about 1 / 1.5^2, a ~56% move
+      try {
+        await swapAToB(500_000_000);
+        expect.fail("a swap moving the price by more than half should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PriceImpactTooHigh");
+      }
+
+      // A modest swap stays well inside the cap
+      await swapAToB(10_000_000);
+    });
+
+    it("Lets the admin raise the cap", async () => {
+      await program.methods
+        .updateMaxPriceImpactBps(new anchor.BN(8_000))
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await swapAToB(500_000_000);
+    });
+  });
+
+  describe("liquidity return values", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+
+    // Simulation runs the instruction without landing it; the return data shows up in the logs
+    const simulatedU64s = (logs: string[], count: number) => {
+      const prefix = `Program return: ${program.programId.toBase58()} `;
+      const data = Buffer.from(logs.find((log) => log.startsWith(prefix)).slice(prefix.length), "base64");
+      return Array.from({ length: count }, (_, i) => data.readBigUInt64LE(i * 8));
+    };
+    const lpBalance = async () =>
+      (await getAccount(provider.connection, getAssociatedTokenAddressSync(pool.lpMint, user1.publicKey))).amount;
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+    });
+
+    it("Returns the LP minted by add_initial_liquidity", async () => {
+      const seed = program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(4_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1]);
+
+      const [amountA, amountB, lpAmount] = simulatedU64s((await seed.simulate()).raw as string[], 3);
+      await seed.rpc();
+
+      expect(amountA).to.equal(1_000_000_000n);
+      expect(amountB).to.equal(4_000_000_000n);
+      // sqrt(1e9 * 4e9) less the locked minimum
+      expect(lpAmount).to.equal(2_000_000_000n - BigInt(MINIMUM_LIQUIDITY));
+      expect(await lpBalance()).to.equal(lpAmount);
+    });
+
+    it("Returns the LP minted by add_liquidity and the amounts removed by remove_liquidity", async () => {
+      const deposit = program.methods
+        .addLiquidity(
+          new anchor.BN(100_000_000),
+          new anchor.BN(400_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(pool.accountsFor(user1))
+        .signers([user1]);
+      const [, , minted] = simulatedU64s((await deposit.simulate()).raw as string[], 3);
+      const before = await lpBalance();
+      await deposit.rpc();
+      expect((await lpBalance()) - before).to.equal(minted);
+
+      const withdraw = program.methods
+        .removeLiquidity(new anchor.BN(minted.toString()), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1]);
+      const [removedA, removedB] = simulatedU64s((await withdraw.simulate()).raw as string[], 2);
+      const userA = pool.tokenAccountsOf(user1).a;
+      const userB = pool.tokenAccountsOf(user1).b;
+      const aBefore = (await getAccount(provider.connection, userA)).amount;
+      const bBefore = (await getAccount(provider.connection, userB)).amount;
+      await withdraw.rpc();
+      expect((await getAccount(provider.connection, userA)).amount - aBefore).to.equal(removedA);
+      expect((await getAccount(provider.connection, userB)).amount - bBefore).to.equal(removedB);
+    });
+  });
+
+  describe("swap cooldown", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const setCooldown = (secs: number) =>
+      program.methods
+        .updateSwapCooldownSecs(secs)
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    // Amounts differ between calls so back-to-back transactions never share a signature
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+          swapCooldown: PublicKey.findProgramAddressSync(
+            [Buffer.from("cooldown"), pool.swapPool.toBuffer(), user1.publicKey.toBuffer()],
+            program.programId
+          )[0],
+        })
+        .signers([user1])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Rejects a second swap inside the cooldown", async () => {
+      await setCooldown(60);
+      await swapAToB(1_000_000);
+      try {
+        await swapAToB(1_000_001);
+        expect.fail("a back-to-back swap should hit the cooldown");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("CooldownActive");
+      }
+    });
+
+    it("Allows back-to-back swaps once the cooldown is set to zero", async () => {
+      await setCooldown(0);
+      await swapAToB(1_000_002);
+      await swapAToB(1_000_003);
+    });
+  });
+
+  describe("granular pause", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const setPaused = (mask: number) =>
+      program.methods
+        .setPaused(mask)
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const swap = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    const deposit = (amount: number) =>
+      program.methods
+        .addLiquidity(new anchor.BN(amount), new anchor.BN(amount), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    const withdraw = (lpAmount: number) =>
+      program.methods
+        .removeLiquidity(new anchor.BN(lpAmount), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    const expectPaused = async (action: Promise<unknown>) => {
+      try {
+        await action;
+        expect.fail("a frozen operation should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolPaused");
+      }
+    };
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    afterEach(async () => {
+      await setPaused(0);
+    });
+
+    it("Freezes trading while LPs can still exit and enter", async () => {
+      await setPaused(PAUSE_SWAPS);
+      await expectPaused(swap(1_000_000));
+      // A single-sided exit swaps half the withdrawal, so it is frozen along with trading
+      await expectPaused(
+        program.methods
+          .removeLiquiditySingle(new anchor.BN(100_000), true, new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc()
+      );
+      await deposit(1_000_000);
+      await withdraw(100_000);
+    });
+
+    it("Freezes deposits alone", async () => {
+      await setPaused(PAUSE_DEPOSITS);
+      await expectPaused(deposit(1_000_001));
+      await swap(1_000_001);
+      await withdraw(100_001);
+    });
+
+    it("Freezes withdrawals alone", async () => {
+      await setPaused(PAUSE_WITHDRAWALS);
+      await expectPaused(withdraw(100_002));
+      await swap(1_000_002);
+      await deposit(1_000_002);
+    });
+
+    it("Freezes everything with pause_all", async () => {
+      await program.methods
+        .pauseAll()
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      await expectPaused(swap(1_000_003));
+      await expectPaused(deposit(1_000_003));
+      await expectPaused(withdraw(100_003));
+    });
+
+    it("Rejects a mask with unknown bits", async () => {
+      try {
+        await setPaused(1 << 3);
+        expect.fail("only the three operation bits are valid");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidAmount");
+      }
+    });
+  });
+
+  describe("mint validation at initialization", () => {
+    const initializeWith = (mintA: PublicKey, mintB: PublicKey) => {
+      const pool = Keypair.generate();
+      const poolLpMint = Keypair.generate();
+      const [authority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)],
+        program.programId
+      );
+      const vaultOf = (mint: PublicKey) =>
+        PublicKey.findProgramAddressSync(
+          [Buffer.from("token_vault"), authority.toBuffer(), mint.toBuffer()],
+          program.programId
+        )[0];
+      return program.methods
+        .initializePool(new anchor.BN(FEE_RATE), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
+        .accounts({
+          swapPool: pool.publicKey,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAVault: vaultOf(mintA),
+          tokenBVault: vaultOf(mintB),
+          feeVaultA: feeVaultOf(authority, mintA),
+          feeVaultB: feeVaultOf(authority, mintB),
+          lpMint: poolLpMint.publicKey,
+          poolAuthority: authority,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([admin, pool, poolLpMint])
+        .rpc();
+    };
+
+    it("Rejects a pool whose two mints are the same", async () => {
+      const [mint] = await createOrderedMints();
+      let rejected = false;
+      try {
+        await initializeWith(mint, mint);
+      } catch (err) {
+        // Both vaults derive to the same PDA, so the second init can fail before the handler's own check
+        rejected = true;
+      }
+      expect(rejected, "a single-mint pool should be rejected").to.be.true;
+    });
+
+    it("Rejects mints passed in non-canonical order", async () => {
+      const [mintA, mintB] = await createOrderedMints();
+      try {
+        await initializeWith(mintB, mintA);
+        expect.fail("swapped mints should be rejected");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("UnorderedMints");
+      }
+    });
+
+    it("Accepts the canonical order and stores it", async () => {
+      const [mintA, mintB] = await createOrderedMints();
+      await initializeWith(mintA, mintB);
+      expect(Buffer.compare(mintA.toBuffer(), mintB.toBuffer())).to.be.lessThan(0);
+    });
+  });
+
+  describe("calculate_required_input", () => {
+    const priceAccounts = () => ({ swapPool: swapPool.publicKey, tokenAVault, tokenBVault, poolAuthority });
+    const quoteOut = (amountIn: anchor.BN, isAToB: boolean) =>
+      program.methods.calculateSwapResult(amountIn, isAToB).accounts(priceAccounts()).view();
+    const quoteIn = (amountOut: anchor.BN, isAToB: boolean) =>
+      program.methods.calculateRequiredInput(amountOut, isAToB).accounts(priceAccounts()).view();
+
+    it("Recovers the original input from a forward quote, within rounding", async () => {
+      for (const isAToB of [true, false]) {
+        for (const amount of [1_000, 123_457, 25_000_000]) {
+          const amountIn = new anchor.BN(amount);
+          const amountOut = await quoteOut(amountIn, isAToB);
+          const required = await quoteIn(amountOut, isAToB);
+
+          // Many inputs floor to the same output, so the inverse finds the smallest of them
+          expect(required.lte(amountIn)).to.be.true;
+          expect((await quoteOut(required, isAToB)).gte(amountOut)).to.be.true;
+          if (required.gtn(1)) {
+            expect((await quoteOut(required.subn(1), isAToB)).lt(amountOut)).to.be.true;
+          }
+        }
+      }
+    });
+
+    it("Rejects an output the destination reserve can't cover", async () => {
+      const reserveB = (await getAccount(provider.connection, tokenBVault)).amount;
+      try {
+        await quoteIn(new anchor.BN(reserveB.toString()), true);
+        expect.fail("the whole reserve can never be bought");
+      } catch (err) {
+        expect(err.toString()).to.include("InsufficientLiquidity");
+      }
+    });
+  });
+
+  describe("swap_exact_out", () => {
+    const swapExactOut = (amountOut: anchor.BN, maxAmountIn: anchor.BN, isAToB = true) =>
+      program.methods
+        .swapExactOut(amountOut, maxAmountIn, isAToB)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          userTokenA: user1TokenA,
+          userTokenB: user1TokenB,
+          lpMint: lpMint.publicKey,
+          poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    const requiredInput = (amountOut: anchor.BN, isAToB = true) =>
+      program.methods
+        .calculateRequiredInput(amountOut, isAToB)
+        .accounts({ swapPool: swapPool.publicKey, tokenAVault, tokenBVault, poolAuthority })
+        .view();
+
+    it("Reverts when the required input exceeds max_amount_in", async () => {
+      const amountOut = new anchor.BN(200_000);
+      const required = await requiredInput(amountOut);
+      try {
+        await swapExactOut(amountOut, required.subn(1));
+        expect.fail("a cap below the required input should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+      }
+    });
+
+    it("Delivers at least amount_out for exactly the quoted input", async () => {
+      const amountOut = new anchor.BN(200_000);
+      const required = await requiredInput(amountOut);
+      const aBefore = (await getAccount(provider.connection, user1TokenA)).amount;
+      const bBefore = (await getAccount(provider.connection, user1TokenB)).amount;
+
+      await swapExactOut(amountOut, required.muln(2));
+
+      const spent = aBefore - (await getAccount(provider.connection, user1TokenA)).amount;
+      const received = (await getAccount(provider.connection, user1TokenB)).amount - bBefore;
+      expect(spent.toString()).to.equal(required.toString());
+      expect(received >= BigInt(amountOut.toString())).to.be.true;
+    });
+
+    it("Quotes and pulls from the B side for a B to A exact-output swap", async () => {
+      const amountOut = new anchor.BN(100_000);
+      const required = await requiredInput(amountOut, false);
+      const aBefore = (await getAccount(provider.connection, user1TokenA)).amount;
+      const bBefore = (await getAccount(provider.connection, user1TokenB)).amount;
+
+      await swapExactOut(amountOut, required, false);
+
+      const spent = bBefore - (await getAccount(provider.connection, user1TokenB)).amount;
+      const received = (await getAccount(provider.connection, user1TokenA)).amount - aBefore;
+      expect(spent.toString()).to.equal(required.toString());
+      expect(received >= BigInt(amountOut.toString())).to.be.true;
+    });
+  });
+
+  describe("pool metrics counters", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const metrics = () =>
+      program.methods
+        .getPoolMetrics()
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+        })
+        .view();
+    const swap = (amount: number) =>
+      program.methods
+        .swap(new anchor.BN(amount), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user2).a,
+          userTokenB: pool.tokenAccountsOf(user2).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user2).poolAuthority,
+          userAuthority: user2.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user2])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1, user2], 10_000_000_000n);
+    });
+
+    it("Starts both counters at zero", async () => {
+      const result = await metrics();
+      expect(result.swapCount.toNumber()).to.equal(0);
+      expect(result.liquidityEventCount.toNumber()).to.equal(0);
+    });
+
+    it("Counts deposits, swaps and withdrawals separately", async () => {
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      await program.methods
+        .addLiquidity(
+          new anchor.BN(100_000_000),
+          new anchor.BN(100_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(pool.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+      await swap(1_000_000);
+      await swap(2_000_000);
+      await swap(3_000_000);
+
+      let result = await metrics();
+      expect(result.swapCount.toNumber()).to.equal(3);
+      expect(result.liquidityEventCount.toNumber()).to.equal(2);
+
+      const lpBalance = (await getAccount(provider.connection, pool.accountsFor(user2).userLpToken)).amount;
+      await program.methods
+        .removeLiquidity(new anchor.BN((lpBalance / 2n).toString()), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+
+      result = await metrics();
+      expect(result.swapCount.toNumber()).to.equal(3);
+      expect(result.liquidityEventCount.toNumber()).to.equal(3);
+
+      // The view reports the same reserves and supply as the chain
+      expect(result.reserveA.toString()).to.equal((await getAccount(provider.connection, pool.vaultA)).amount.toString());
+      expect(result.reserveB.toString()).to.equal((await getAccount(provider.connection, pool.vaultB)).amount.toString());
+      expect(result.lpSupply.toString()).to.equal((await getMint(provider.connection, pool.lpMint)).supply.toString());
+    });
+  });
+
+  describe("spot prices", () => {
+    const priceAccounts = (pool: Awaited<ReturnType<typeof createFreshPool>>) => ({
+      swapPool: pool.swapPool,
+      tokenAVault: pool.vaultA,
+      tokenBVault: pool.vaultB,
+      poolAuthority: pool.accountsFor(user1).poolAuthority,
+    });
+    const expectInsufficientLiquidity = async (view: Promise<unknown>) => {
+      try {
+        await view;
+        expect.fail("a zero denominator reserve should be rejected");
+      } catch (err) {
+        expect(err.toString()).to.include("InsufficientLiquidity");
+      }
+    };
+
+    it("Returns both directions in one call, matching the single getters", async () => {
+      const accounts = { swapPool: swapPool.publicKey, tokenAVault, tokenBVault, poolAuthority };
+      const [priceA, priceB] = await program.methods.getSpotPrices().accounts(accounts).view();
+
+      expect(priceA.toString()).to.equal((await program.methods.getTokenAPrice().accounts(accounts).view()).toString());
+      expect(priceB.toString()).to.equal((await program.methods.getTokenBPrice().accounts(accounts).view()).toString());
+    });
+
+    it("Rejects pricing token A while its reserve is empty", async () => {
+      // Only token B is sitting in the vaults, so A has no price but B prices at zero
+      const pool = await createFreshPool([user1], 1_000_000_000n);
+      await transferChecked(provider.connection, user1, pool.tokenAccountsOf(user1).b, pool.mintB, pool.vaultB, user1, 1_000_000, TOKEN_DECIMALS);
+
+      await expectInsufficientLiquidity(program.methods.getTokenAPrice().accounts(priceAccounts(pool)).view());
+      await expectInsufficientLiquidity(program.methods.getSpotPrices().accounts(priceAccounts(pool)).view());
+      const priceB = await program.methods.getTokenBPrice().accounts(priceAccounts(pool)).view();
+      expect(priceB.toNumber()).to.equal(0);
+    });
+
+    it("Rejects pricing token B while its reserve is empty", async () => {
+      const pool = await createFreshPool([user1], 1_000_000_000n);
+      await transferChecked(provider.connection, user1, pool.tokenAccountsOf(user1).a, pool.mintA, pool.vaultA, user1, 1_000_000, TOKEN_DECIMALS);
+
+      await expectInsufficientLiquidity(program.methods.getTokenBPrice().accounts(priceAccounts(pool)).view());
+      await expectInsufficientLiquidity(program.methods.getSpotPrices().accounts(priceAccounts(pool)).view());
+      const priceA = await program.methods.getTokenAPrice().accounts(priceAccounts(pool)).view();
+      expect(priceA.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("add_initial_liquidity protection", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const seed = (amount: number, minLpOut: number) =>
+      program.methods
+        .addInitialLiquidity(new anchor.BN(amount), new anchor.BN(amount), new anchor.BN(minLpOut))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+    it("Rejects a seed that would mint less LP than min_lp_out", async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      // A 1:1 seed mints its amount in LP, minus the locked minimum
+      const expectedLp = 1_000_000_000 - MINIMUM_LIQUIDITY;
+      try {
+        await seed(1_000_000_000, expectedLp + 1);
+        expect.fail("a seed short of min_lp_out should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+      }
+
+      await seed(1_000_000_000, expectedLp);
+      const userLp = getAssociatedTokenAddressSync(pool.lpMint, user1.publicKey);
+      expect(Number((await getAccount(provider.connection, userLp)).amount)).to.equal(expectedLp);
+    });
+
+    it("Rejects seeding a pool that already holds reserves", async () => {
+      pool = await createFreshPool([user1, user2], 10_000_000_000n);
+      // Someone got a deposit in first
+      const accounts = pool.tokenAccountsOf(user2);
+      await transferChecked(provider.connection, user2, accounts.a, pool.mintA, pool.vaultA, user2, 1_000_000, TOKEN_DECIMALS);
+
+      try {
+        await seed(1_000_000_000, 0);
+        expect.fail("a pool with reserves should only accept add_liquidity");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolAlreadySeeded");
+      }
+    });
+  });
+
+  describe("re-entrancy guard", () => {
+    it("Clears the in-progress flag once each guarded operation completes", async () => {
+      const pool = await createFreshPool([user1], 10_000_000_000n);
+      const inProgress = async () => (await program.account.swapPool.fetch(pool.swapPool)).inProgress;
+      expect(await inProgress()).to.be.false;
+
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      await program.methods
+        .addLiquidity(
+          new anchor.BN(100_000_000),
+          new anchor.BN(100_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      expect(await inProgress()).to.be.false;
+
+      await program.methods
+        .swap(new anchor.BN(1_000_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      expect(await inProgress()).to.be.false;
+
+      await program.methods
+        .removeLiquidity(new anchor.BN(1_000_000), new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      expect(await inProgress()).to.be.false;
+    });
+
+    it("Rejects a flash swap or loan callback that re-enters the pool", async () => {
+      const pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      // While the vault is drained the callback tries to mint LP against the skewed reserves
+      const reenterIx = await program.methods
+        .addLiquidity(
+          new anchor.BN(100_000_000),
+          new anchor.BN(100_000_000),
+          new anchor.BN(0),
+          new anchor.BN(0),
+          null,
+          new anchor.BN(0)
+        )
+        .accounts(pool.accountsFor(user1))
+        .instruction();
+      const flashAccounts = {
+        swapPool: pool.swapPool,
+        tokenAMint: pool.mintA,
+        tokenBMint: pool.mintB,
+        tokenAVault: pool.vaultA,
+        tokenBVault: pool.vaultB,
+        feeVaultA: pool.feeVaultA,
+        feeVaultB: pool.feeVaultB,
+        receiverTokenA: pool.tokenAccountsOf(user1).a,
+        receiverTokenB: pool.tokenAccountsOf(user1).b,
+        poolAuthority: pool.accountsFor(user1).poolAuthority,
+        userAuthority: user1.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      };
+      const callback = [{ pubkey: program.programId, isSigner: false, isWritable: false }, ...reenterIx.keys];
+
+      for (const flash of [
+        program.methods.flashSwap(new anchor.BN(500_000_000), true, reenterIx.data),
+        program.methods.flashLoan(new anchor.BN(500_000_000), true, reenterIx.data),
+      ]) {
+        try {
+          await flash.accounts(flashAccounts).remainingAccounts(callback).signers([user1]).rpc();
+          expect.fail("the callback must not be able to re-enter the pool");
+        } catch (err) {
+          expect(err.error.errorCode.code).to.equal("ReentrancyDetected");
+        }
+      }
+      expect((await program.account.swapPool.fetch(pool.swapPool)).inProgress).to.be.false;
+    });
+  });
+
+  describe("fee_authority", () => {
+    const setFeeAuthority = (feeAuthority: PublicKey) =>
+      program.methods
+        .setFeeAuthority(feeAuthority)
+        .accounts({ swapPool: swapPool.publicKey, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    const collectAs = (collector: Keypair, collectorTokenA: PublicKey, collectorTokenB: PublicKey) =>
+      program.methods
+        .collectFees(null, null)
+        .accounts({
+          swapPool: swapPool.publicKey,
+          tokenAMint,
+          tokenBMint,
+          tokenAVault,
+          tokenBVault,
+          feeVaultA,
+          feeVaultB,
+          feeCollector: collector.publicKey,
+          feeCollectorTokenA: collectorTokenA,
+          feeCollectorTokenB: collectorTokenB,
+          poolAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([collector])
+        .rpc();
+
+    after(async () => {
+      await setFeeAuthority(admin.publicKey);
+    });
+
+    it("Defaults the fee authority to the admin", async () => {
+      const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+      expect(pool.feeAuthority.toString()).to.equal(admin.publicKey.toString());
+    });
+
+    it("Only lets the admin delegate fee collection", async () => {
+      try {
+        await program.methods
+          .setFeeAuthority(user2.publicKey)
+          .accounts({ swapPool: swapPool.publicKey, admin: user2.publicKey })
+          .signers([user2])
+          .rpc();
+        expect.fail("a non-admin shouldn't be able to redirect fees");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+    });
+
+    it("Lets the delegated collector collect and rejects everyone else", async () => {
+      await setFeeAuthority(user2.publicKey);
+
+      // The admin keeps every other power but no longer collects fees
+      try {
+        await collectAs(admin, adminTokenA, adminTokenB);
+        expect.fail("the admin is no longer the fee authority");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+
+      const accrued = await program.account.swapPool.fetch(swapPool.publicKey);
+      const before = (await getAccount(provider.connection, user2TokenA)).amount;
+      await collectAs(user2, user2TokenA, user2TokenB);
+
+      const collected = (await getAccount(provider.connection, user2TokenA)).amount - before;
+      expect(collected.toString()).to.equal(accrued.totalFeesA.toString());
+      const pool = await program.account.swapPool.fetch(swapPool.publicKey);
+      expect(pool.totalFeesA.toNumber()).to.equal(0);
+      expect(pool.totalFeesB.toNumber()).to.equal(0);
+    });
+  });
+
+  describe("fee tiers", () => {
+    const initializeTier = async (mintA: PublicKey, mintB: PublicKey, feeRate: number) => {
+      const pool = Keypair.generate();
+      const poolLpMint = Keypair.generate();
+      const [authority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(feeRate)],
+        program.programId
+      );
+      const vaultOf = (mint: PublicKey) =>
+        PublicKey.findProgramAddressSync(
+          [Buffer.from("token_vault"), authority.toBuffer(), mint.toBuffer()],
+          program.programId
+        )[0];
+      await program.methods
+        .initializePool(new anchor.BN(feeRate), CURVE_CONSTANT_PRODUCT, new anchor.BN(0), null)
+        .accounts({
+          swapPool: pool.publicKey,
+          tokenAMint: mintA,
+          tokenBMint: mintB,
+          tokenAVault: vaultOf(mintA),
+          tokenBVault: vaultOf(mintB),
+          feeVaultA: feeVaultOf(authority, mintA),
+          feeVaultB: feeVaultOf(authority, mintB),
+          lpMint: poolLpMint.publicKey,
+          poolAuthority: authority,
+          admin: admin.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          rent: SYSVAR_RENT_PUBKEY,
+        })
+        .signers([admin, pool, poolLpMint])
+        .rpc();
+      return { swapPool: pool.publicKey, authority, vaultA: vaultOf(mintA) };
+    };
+
+    it("Creates pools of different tiers for the same pair side by side", async () => {
+      const [mintA, mintB] = await createOrderedMints();
+      const low = await initializeTier(mintA, mintB, 5);
+      const standard = await initializeTier(mintA, mintB, 30);
+
+      expect(low.swapPool.toString()).to.not.equal(standard.swapPool.toString());
+      expect(low.authority.toString()).to.not.equal(standard.authority.toString());
+      expect(low.vaultA.toString()).to.not.equal(standard.vaultA.toString());
+
+      const [lowPool, standardPool] = await Promise.all([
+        program.account.swapPool.fetch(low.swapPool),
+        program.account.swapPool.fetch(standard.swapPool),
+      ]);
+      expect(lowPool.feeTier).to.equal(FEE_TIERS.indexOf(5));
+      expect(lowPool.feeRate.toNumber()).to.equal(5);
+      expect(standardPool.feeTier).to.equal(FEE_TIERS.indexOf(30));
+      expect(standardPool.poolAuthority.toString()).to.equal(standard.authority.toString());
+    });
+
+    it("Rejects a fee rate outside the supported tiers", async () => {
+      const [mintA, mintB] = await createOrderedMints();
+      try {
+        await initializeTier(mintA, mintB, 25);
+        expect.fail("25 bps isn't a supported tier");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidFeeTier");
+      }
+    });
+
+    it("Keeps a tier pool's fee rate at its tier", async () => {
+      const [mintA, mintB] = await createOrderedMints();
+      const { swapPool: tierPool } = await initializeTier(mintA, mintB, 30);
+      const updateFeeRate = (feeRate: number) =>
+        program.methods
+          .updateFeeRate(new anchor.BN(feeRate))
+          .accounts({ swapPool: tierPool, admin: admin.publicKey })
+          .signers([admin])
+          .rpc();
+
+      for (const feeRate of [5, 100, 0]) {
+        try {
+          await updateFeeRate(feeRate);
+          expect.fail(`${feeRate} bps doesn't match the pool's tier`);
+        } catch (err) {
+          expect(err.error.errorCode.code).to.equal("InvalidFeeTier");
+        }
+      }
+
+      await updateFeeRate(30);
+      expect((await program.account.swapPool.fetch(tierPool)).feeRate.toNumber()).to.equal(30);
+    });
+  });
+
+  describe("lp_to_tokens", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const lpToTokens = (lpAmount: anchor.BN) =>
+      program.methods
+        .lpToTokens(lpAmount)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+        })
+        .view();
+
+    before(async () => {
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(3_000_000_007), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Matches what remove_liquidity pays out for the same amount", async () => {
+      const lpAmount = new anchor.BN(123_456_789);
+      const [quotedA, quotedB] = await lpToTokens(lpAmount);
+
+      const accounts = pool.tokenAccountsOf(user1);
+      const aBefore = (await getAccount(provider.connection, accounts.a)).amount;
+      const bBefore = (await getAccount(provider.connection, accounts.b)).amount;
+      await program.methods
+        .removeLiquidity(lpAmount, new anchor.BN(0), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      const paidA = (await getAccount(provider.connection, accounts.a)).amount - aBefore;
+      const paidB = (await getAccount(provider.connection, accounts.b)).amount - bBefore;
+      expect(quotedA.toString()).to.equal(paidA.toString());
+      expect(quotedB.toString()).to.equal(paidB.toString());
+    });
+
+    it("Rejects more LP than exists", async () => {
+      const supply = (await getMint(provider.connection, pool.lpMint)).supply;
+      try {
+        await lpToTokens(new anchor.BN((supply + 1n).toString()));
+        expect.fail("LP beyond the supply can't be redeemed");
+      } catch (err) {
+        expect(err.toString()).to.include("InsufficientFunds");
+      }
+    });
+  });
+
+  describe("zero-output swaps", () => {
+    it("Reverts a dust swap that would pay out nothing", async () => {
+      // Token B is so scarce that a single unit of A is worth less than one unit of B
+      const pool = await createFreshPool([user1], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      const accounts = pool.tokenAccountsOf(user1);
+      const aBefore = (await getAccount(provider.connection, accounts.a)).amount;
+
+      try {
+        await program.methods
+          .swap(new anchor.BN(1), new anchor.BN(0), true, false)
+          .accounts({
+            swapPool: pool.swapPool,
+            tokenAMint: pool.mintA,
+            tokenBMint: pool.mintB,
+            tokenAVault: pool.vaultA,
+            tokenBVault: pool.vaultB,
+            feeVaultA: pool.feeVaultA,
+            feeVaultB: pool.feeVaultB,
+            userTokenA: accounts.a,
+            userTokenB: accounts.b,
+            lpMint: pool.lpMint,
+            poolAuthority: pool.accountsFor(user1).poolAuthority,
+            userAuthority: user1.publicKey,
+            feeDestinationA: null,
+            feeDestinationB: null,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            systemProgram: SystemProgram.programId,
+            feeExemption: null,
+          })
+          .signers([user1])
+          .rpc();
+        expect.fail("a swap paying out zero should revert");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidAmount");
+      }
+      expect((await getAccount(provider.connection, accounts.a)).amount).to.equal(aBefore);
+    });
+  });
+
+  describe("partial fee collection", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const accruedB = async () => (await program.account.swapPool.fetch(pool.swapPool)).totalFeesB;
+    const collect = (amountA: anchor.BN | null, amountB: anchor.BN | null) =>
+      program.methods
+        .collectFees(amountA, amountB)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          feeCollector: admin.publicKey,
+          feeCollectorTokenA: pool.tokenAccountsOf(admin).a,
+          feeCollectorTokenB: pool.tokenAccountsOf(admin).b,
+          poolAuthority: pool.accountsFor(admin).poolAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc();
+    const adminB = async () => (await getAccount(provider.connection, pool.tokenAccountsOf(admin).b)).amount;
+
+    before(async () => {
+      pool = await createFreshPool([user1, admin], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      // Swap fees are taken from the output, so they accrue in token B
+      await program.methods
+        .swap(new anchor.BN(100_000_000), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+      expect((await accruedB()).toNumber()).to.be.greaterThan(1_000);
+    });
+
+    it("Rejects collecting more than has accrued", async () => {
+      try {
+        await collect(null, (await accruedB()).addn(1));
+        expect.fail("collecting beyond the accrued fees should fail");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InsufficientFunds");
+      }
+    });
+
+    it("Collects exactly the requested amount and keeps the rest accrued", async () => {
+      const accrued = await accruedB();
+      const before = await adminB();
+
+      await collect(new anchor.BN(0), new anchor.BN(1_000));
+
+      expect((await adminB()) - before).to.equal(1_000n);
+      expect((await accruedB()).toString()).to.equal(accrued.subn(1_000).toString());
+    });
+
+    it("Collects everything that is left when no amount is given", async () => {
+      const accrued = await accruedB();
+      const before = await adminB();
+
+      await collect(null, null);
+
+      expect(((await adminB()) - before).toString()).to.equal(accrued.toString());
+      expect((await accruedB()).toNumber()).to.equal(0);
+    });
+  });
+
+  describe("fee vaults", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const balance = async (account: PublicKey) => (await getAccount(provider.connection, account)).amount;
+    const lpToTokens = (lpAmount: bigint) =>
+      program.methods
+        .lpToTokens(new anchor.BN(lpAmount.toString()))
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+        })
+        .view();
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          userTokenA: pool.tokenAccountsOf(user1).a,
+          userTokenB: pool.tokenAccountsOf(user1).b,
+          lpMint: pool.lpMint,
+          poolAuthority: pool.accountsFor(user1).poolAuthority,
+          userAuthority: user1.publicKey,
+          feeDestinationA: null,
+          feeDestinationB: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          feeExemption: null,
+        })
+        .signers([user1])
+        .rpc();
+    const collectAll = () =>
+      program.methods
+        .collectFees(null, null)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAMint: pool.mintA,
+          tokenBMint: pool.mintB,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          feeVaultA: pool.feeVaultA,
+          feeVaultB: pool.feeVaultB,
+          feeCollector: admin.publicKey,
+          feeCollectorTokenA: pool.tokenAccountsOf(admin).a,
+          feeCollectorTokenB: pool.tokenAccountsOf(admin).b,
+          poolAuthority: pool.accountsFor(admin).poolAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([admin])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1, admin], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      await swapAToB(100_000_000);
+      expect((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toNumber()).to.be.greaterThan(0);
+    });
+
+    it("Sweeps the protocol fee out of the reserves on every swap", async () => {
+      const feesBefore = BigInt((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toString());
+      const feeVaultBefore = await balance(pool.feeVaultB);
+      const vaultBefore = await balance(pool.vaultB);
+      const userBefore = await balance(pool.tokenAccountsOf(user1).b);
+
+      await swapAToB(10_000_000);
+
+      const feesAfter = BigInt((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toString());
+      const received = (await balance(pool.tokenAccountsOf(user1).b)) - userBefore;
+      expect(await balance(pool.feeVaultB)).to.equal(feesAfter);
+      expect((await balance(pool.feeVaultB)) - feeVaultBefore).to.equal(feesAfter - feesBefore);
+      expect(vaultBefore - (await balance(pool.vaultB))).to.equal(received + feesAfter - feesBefore);
+    });
+
+    it("Values LP against the main vaults alone", async () => {
+      const lpAmount = 250_000_000n;
+      const reserveA = await balance(pool.vaultA);
+      const reserveB = await balance(pool.vaultB);
+      const supply = (await getMint(provider.connection, pool.lpMint)).supply;
+
+      const [quotedA, quotedB] = await lpToTokens(lpAmount);
+      expect(quotedA.toString()).to.equal(((lpAmount * reserveA) / supply).toString());
+      expect(quotedB.toString()).to.equal(((lpAmount * reserveB) / supply).toString());
+    });
+
+    it("Leaves LP redemption value unchanged when the fees are collected", async () => {
+      const lpAmount = 250_000_000n;
+      const [beforeA, beforeB] = await lpToTokens(lpAmount);
+
+      await collectAll();
+      expect((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toNumber()).to.equal(0);
+      expect(await balance(pool.feeVaultB)).to.equal(0n);
+
+      const [afterA, afterB] = await lpToTokens(lpAmount);
+      expect(afterA.toString()).to.equal(beforeA.toString());
+      expect(afterB.toString()).to.equal(beforeB.toString());
+    });
+
+    it("Keeps the accrued fees in the fee vault when every LP withdraws", async () => {
+      await swapAToB(100_000_000);
+      const accrued = BigInt((await program.account.swapPool.fetch(pool.swapPool)).totalFeesB.toString());
+      expect(accrued > 0n).to.be.true;
+
+      await program.methods
+        .removeAllLiquidity(new anchor.BN(0), new anchor.BN(0), false)
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+
+      // The fees never sat with the reserves, so they are all still there to collect
+      expect(await balance(pool.feeVaultB)).to.equal(accrued);
+
+      const adminBefore = await balance(pool.tokenAccountsOf(admin).b);
+      await collectAll();
+      const collected = (await balance(pool.tokenAccountsOf(admin).b)) - adminBefore;
+      expect(collected).to.equal(accrued);
+    });
+  });
+
+  describe("wrapped SOL swaps", () => {
+    let accounts: any;
+    let solIsA: boolean;
+    let userWsol: PublicKey;
+    let userToken: PublicKey;
+    const lamports = (key: PublicKey) => provider.connection.getBalance(key);
+
+    const swap = (amountIn: bigint, solIn: boolean, unwrapSol: boolean) =>
+      program.methods
+        .swap(new anchor.BN(amountIn.toString()), new anchor.BN(0), solIn === solIsA, unwrapSol)
+        .accounts({ ...accounts, feeDestinationA: null, feeDestinationB: null, feeExemption: null })
+        .signers([user1])
+        .rpc();
+    const quote = async (amountIn: bigint, solIn: boolean) =>
+      BigInt(
+        (
+          await program.methods
+            .calculateSwapResult(new anchor.BN(amountIn.toString()), solIn === solIsA)
+            .accounts({
+              swapPool: accounts.swapPool,
+              tokenAVault: accounts.tokenAVault,
+              tokenBVault: accounts.tokenBVault,
+              poolAuthority: accounts.poolAuthority,
+            })
+            .view()
+        ).toString()
+      );
+
+    before(async () => {
+      const tokenMint = await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS);
+      const [mintA, mintB] = orderedMints(NATIVE_MINT, tokenMint);
+      solIsA = mintA.equals(NATIVE_MINT);
+
+      const pool = await createFreshPool([], 0n, CURVE_CONSTANT_PRODUCT, 0, null, [mintA, mintB]);
+      const [authority] = PublicKey.findProgramAddressSync(
+        [Buffer.from("pool_authority"), mintA.toBuffer(), mintB.toBuffer(), feeTierSeed(FEE_RATE)],
+        program.programId
+      );
+
+      // A plain (non-associated) wSOL account, so it can be closed without touching the user's ATA
+      userWsol = await createWrappedNativeAccount(
+        provider.connection,
+        user1,
+        user1.publicKey,
+        1_100_000_000,
+        Keypair.generate()
+      );
+      userToken = (await getOrCreateAssociatedTokenAccount(provider.connection, user1, tokenMint, user1.publicKey)).address;
+      await splMintTo(provider.connection, admin, tokenMint, userToken, admin, 10_000_000_000n);
+
+      accounts = {
+        swapPool: pool.swapPool,
+        tokenAMint: mintA,
+        tokenBMint: mintB,
+        tokenAVault: pool.vaultA,
+        tokenBVault: pool.vaultB,
+        feeVaultA: pool.feeVaultA,
+        feeVaultB: pool.feeVaultB,
+        userTokenA: solIsA ? userWsol : userToken,
+        userTokenB: solIsA ? userToken : userWsol,
+        lpMint: pool.lpMint,
+        userLpToken: getAssociatedTokenAddressSync(pool.lpMint, user1.publicKey),
+        poolAuthority: authority,
+        userAuthority: user1.publicKey,
+        lockedLpToken: lockedLpOf(authority, pool.lpMint),
+        lpHistory: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        rent: SYSVAR_RENT_PUBKEY,
+      };
+
+      // 1 SOL against 1,000 tokens, leaving 0.1 SOL wrapped
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(accounts)
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Wraps the lamports a SOL to token swap is short of", async () => {
+      const wsolBalance = (await getAccount(provider.connection, userWsol)).amount;
+      const amountIn = wsolBalance + 50_000_000n;
+      const expected = await quote(amountIn, true);
+      const lamportsBefore = await lamports(user1.publicKey);
+      const tokenBefore = (await getAccount(provider.connection, userToken)).amount;
+
+      await swap(amountIn, true, false);
+
+      // Only the shortfall came out of the user's native balance, the fees are paid by the provider wallet
+      expect(lamportsBefore - (await lamports(user1.publicKey))).to.equal(50_000_000);
+      expect((await getAccount(provider.connection, userWsol)).amount).to.equal(0n);
+      expect((await getAccount(provider.connection, userToken)).amount - tokenBefore).to.equal(expected);
+    });
+
+    it("Rejects unwrap_sol when the output isn't wrapped SOL", async () => {
+      try {
+        await swap(1_000_000n, true, true);
+        expect.fail("a token output can't be unwrapped");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("NotNativeMint");
+      }
+    });
+
+    it("Unwraps a token to SOL swap into native lamports", async () => {
+      const amountIn = 100_000_000n;
+      const expected = await quote(amountIn, false);
+      const wsolLamports = await lamports(userWsol);
+      const lamportsBefore = await lamports(user1.publicKey);
+
+      await swap(amountIn, false, true);
+
+      // The closed wSOL account hands over its rent and leftover balance along with the output
+      expect(expected > 0n).to.be.true;
+      expect(await lamports(user1.publicKey)).to.equal(lamportsBefore + wsolLamports + Number(expected));
+      expect(await provider.connection.getAccountInfo(userWsol)).to.be.null;
+    });
+  });
+
+  describe("rescue_tokens", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const rescue = (mint: PublicKey, source: PublicKey, destination: PublicKey, amount: number, signer = admin) =>
+      program.methods
+        .rescueTokens(new anchor.BN(amount))
+        .accounts({
+          swapPool: pool.swapPool,
+          mint,
+          source,
+          destination,
+          poolAuthority: pool.accountsFor(admin).poolAuthority,
+          admin: signer.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([signer])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1, admin], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Returns an unrelated token sent to the pool authority", async () => {
+      const strayMint = await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS);
+      const authority = pool.accountsFor(admin).poolAuthority;
+      const stray = (await getOrCreateAssociatedTokenAccount(provider.connection, admin, strayMint, authority, true)).address;
+      const adminStray = (await getOrCreateAssociatedTokenAccount(provider.connection, admin, strayMint, admin.publicKey)).address;
+      await splMintTo(provider.connection, admin, strayMint, stray, admin, 5_000_000n);
+
+      // Only the admin may rescue
+      try {
+        await rescue(strayMint, stray, adminStray, 5_000_000, user1);
+        expect.fail("a non-admin shouldn't be able to rescue tokens");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("Unauthorized");
+      }
+
+      await rescue(strayMint, stray, adminStray, 5_000_000);
+      expect((await getAccount(provider.connection, stray)).amount).to.equal(0n);
+      expect((await getAccount(provider.connection, adminStray)).amount).to.equal(5_000_000n);
+    });
+
+    it("Refuses to move a real vault's tokens", async () => {
+      const vaultBefore = (await getAccount(provider.connection, pool.vaultA)).amount;
+      try {
+        await rescue(pool.mintA, pool.vaultA, pool.tokenAccountsOf(admin).a, 1_000);
+        expect.fail("the reserves must not be rescuable");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidToken");
+      }
+      expect((await getAccount(provider.connection, pool.vaultA)).amount).to.equal(vaultBefore);
+    });
+  });
+
+  describe("price range", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const setPriceRange = (lowerTick: number, upperTick: number) =>
+      program.methods
+        .setPriceRange(lowerTick, upperTick)
+        .accounts({
+          swapPool: pool.swapPool,
+          tokenAVault: pool.vaultA,
+          tokenBVault: pool.vaultB,
+          admin: admin.publicKey,
+        })
+        .signers([admin])
+        .rpc();
+    const swapAToB = (amountIn: number) =>
+      program.methods
+        .swap(new anchor.BN(amountIn), new anchor.BN(0), true, false)
+        .accounts({ ...pool.accountsFor(user1), feeDestinationA: null, feeDestinationB: null, feeExemption: null })
+        .signers([user1])
+        .rpc();
+
+    before(async () => {
+      pool = await createFreshPool([user1, admin], 10_000_000_000n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      await program.methods
+        .setTickSpacing(10)
+        .accounts({ swapPool: pool.swapPool, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    });
+
+    it("Rejects a range that doesn't contain the current price", async () => {
+      try {
+        await setPriceRange(100, 200);
+        expect.fail("the pool price (tick 0) sits below this range");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidTickRange");
+      }
+    });
+
+    it("Lets swaps move the price within the range", async () => {
+      // Ticks -100..100 cover roughly 1% either side of the 1:1 price
+      await setPriceRange(-100, 100);
+      await swapAToB(1_000_000);
+    });
+
+    it("Rejects a swap that exhausts the range", async () => {
+      try {
+        await swapAToB(20_000_000);
+        expect.fail("a 2% swap should push the price below the range");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PriceRangeExhausted");
+      }
+    });
+
+    it("Allows the same swap again once the range is cleared", async () => {
+      await setPriceRange(0, 0);
+      await swapAToB(20_000_000);
+    });
+  });
+
+  describe("zero LP supply", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+
+    before(async () => {
+      // The locked minimum keeps a seeded pool's supply above zero, so an unseeded pool stands in for
+      // one whose supply was driven to zero; a donation gives it reserves but no LP
+      pool = await createFreshPool([user1], 10_000_000_000n);
+      const accounts = pool.tokenAccountsOf(user1);
+      await transferChecked(provider.connection, user1, accounts.a, pool.mintA, pool.vaultA, user1, 1_000_000, TOKEN_DECIMALS);
+      await transferChecked(provider.connection, user1, accounts.b, pool.mintB, pool.vaultB, user1, 1_000_000, TOKEN_DECIMALS);
+      await getOrCreateAssociatedTokenAccount(provider.connection, user1, pool.lpMint, user1.publicKey);
+      expect((await getMint(provider.connection, pool.lpMint)).supply).to.equal(0n);
+    });
+
+    it("Reports an empty share instead of dividing by the supply", async () => {
+      const [sharePercentage, shareA, shareB] = await program.methods
+        .getUserPoolShare()
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .view();
+      expect(sharePercentage.toNumber()).to.equal(0);
+      expect(shareA.toNumber()).to.equal(0);
+      expect(shareB.toNumber()).to.equal(0);
+    });
+
+    it("Fails cleanly when valuing or depositing against no supply", async () => {
+      try {
+        await program.methods
+          .lpToTokens(new anchor.BN(1_000))
+          .accounts({
+            swapPool: pool.swapPool,
+            tokenAVault: pool.vaultA,
+            tokenBVault: pool.vaultB,
+            lpMint: pool.lpMint,
+            poolAuthority: pool.accountsFor(user1).poolAuthority,
+          })
+          .view();
+        expect.fail("there is no LP supply to value against");
+      } catch (err) {
+        expect(err.toString()).to.include("InsufficientFunds");
+      }
+
+      try {
+        await program.methods
+          .addLiquidity(new anchor.BN(1_000), new anchor.BN(1_000), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc();
+        expect.fail("deposits into a pool without LP supply must go through add_initial_liquidity");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("PoolNotSeeded");
+      }
+    });
+  });
+
+  describe("arithmetic near u64::MAX", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const HUGE = 2n ** 62n;
+    const priceAccounts = () => ({
+      swapPool: pool.swapPool,
+      tokenAVault: pool.vaultA,
+      tokenBVault: pool.vaultB,
+      poolAuthority: pool.accountsFor(user1).poolAuthority,
+    });
+    const expectCalculationFailure = async (call: Promise<unknown>) => {
+      try {
+        await call;
+        expect.fail("the result doesn't fit in a u64");
+      } catch (err) {
+        expect(err.toString()).to.include("CalculationFailure");
+      }
+    };
+
+    before(async () => {
+      // A tiny token A reserve against a near-max token B reserve puts every ratio at its extreme
+      pool = await createFreshPool([user1], 2n ** 63n);
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000), new anchor.BN(HUGE.toString()), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Fails get_token_a_price cleanly when the price overflows", async () => {
+      await expectCalculationFailure(program.methods.getTokenAPrice().accounts(priceAccounts()).view());
+      const priceB = await program.methods.getTokenBPrice().accounts(priceAccounts()).view();
+      expect(priceB.toNumber()).to.equal(0);
+    });
+
+    it("Fails add_liquidity cleanly when the matching deposit overflows", async () => {
+      await expectCalculationFailure(
+        program.methods
+          .addLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc()
+      );
+    });
+
+    it("Computes get_user_pool_share exactly at huge reserves", async () => {
+      const lp = (await getAccount(provider.connection, pool.accountsFor(user1).userLpToken)).amount;
+      const supply = (await getMint(provider.connection, pool.lpMint)).supply;
+      const reserveB = (await getAccount(provider.connection, pool.vaultB)).amount;
+
+      const [, , shareB] = await program.methods
+        .getUserPoolShare()
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .view();
+      expect(shareB.toString()).to.equal(((lp * reserveB) / supply).toString());
+    });
+
+    it("Withdraws a huge position without overflowing", async () => {
+      const before = (await getAccount(provider.connection, pool.tokenAccountsOf(user1).b)).amount;
+      await program.methods
+        .removeAllLiquidity(new anchor.BN(0), new anchor.BN(0), false)
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      const received = (await getAccount(provider.connection, pool.tokenAccountsOf(user1).b)).amount - before;
+      expect(received > HUGE / 2n).to.be.true;
+    });
+  });
+
+  describe("add_liquidity over-crediting", () => {
+    // Deterministic pseudo-random sequence so a failure reproduces
+    let seed = 42;
+    const nextRandom = (max: number) => {
+      seed = (seed * 1_103_515_245 + 12_345) % 2_147_483_648;
+      return 1 + (seed % max);
+    };
+
+    it("Never mints LP worth more than the deposit across many reserve/deposit mixes", async () => {
+      for (const [seedA, seedB] of [
+        [1_000_000, 1_000_000],
+        [1_000_003, 7_777_777],
+        [987_654_321, 1_234_567],
+        [50_000_000, 999_999_937],
+      ]) {
+        const pool = await createFreshPool([user1], 100_000_000_000n);
+        const accounts = pool.accountsFor(user1);
+        await program.methods
+          .addInitialLiquidity(new anchor.BN(seedA), new anchor.BN(seedB), new anchor.BN(0))
+          .accounts(accounts)
+          .signers([user1])
+          .rpc();
+
+        for (let i = 0; i < 6; i++) {
+          // Mix dust and sizeable deposits, each side picked independently
+          const scale = i % 2 === 0 ? 1_000 : 50_000_000;
+          const amountA = nextRandom(scale);
+          const amountB = nextRandom(scale);
+
+          const balanceA = (await getAccount(provider.connection, accounts.userTokenA)).amount;
+          const balanceB = (await getAccount(provider.connection, accounts.userTokenB)).amount;
+          const lpBefore = (await getAccount(provider.connection, accounts.userLpToken)).amount;
+          try {
+            await program.methods
+              .addLiquidity(new anchor.BN(amountA), new anchor.BN(amountB), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+              .accounts(accounts)
+              .signers([user1])
+              .rpc();
+          } catch (err) {
+            // A dust deposit may round to nothing; that is rejected, never over-credited
+            expect(err.error.errorCode.code).to.be.oneOf(["InvalidAmount", "SlippageExceeded"]);
+            continue;
+          }
+
+          const depositedA = balanceA - (await getAccount(provider.connection, accounts.userTokenA)).amount;
+          const depositedB = balanceB - (await getAccount(provider.connection, accounts.userTokenB)).amount;
+          const minted = (await getAccount(provider.connection, accounts.userLpToken)).amount - lpBefore;
+
+          const [valueA, valueB] = await program.methods
+            .lpToTokens(new anchor.BN(minted.toString()))
+            .accounts({
+              swapPool: pool.swapPool,
+              tokenAVault: pool.vaultA,
+              tokenBVault: pool.vaultB,
+              lpMint: pool.lpMint,
+              poolAuthority: accounts.poolAuthority,
+            })
+            .view();
+          expect(BigInt(valueA.toString()) <= depositedA + 1n).to.be.true;
+          expect(BigInt(valueB.toString()) <= depositedB + 1n).to.be.true;
+        }
+      }
+    });
+  });
+
+  describe("configurable LP decimals", () => {
+    it("Defaults the LP mint to the larger token decimals", async () => {
+      const pool = await createFreshPool([user1], 1_000_000_000n);
+      expect((await getMint(provider.connection, pool.lpMint)).decimals).to.equal(TOKEN_DECIMALS);
+      expect((await program.account.swapPool.fetch(pool.swapPool)).lpDecimals).to.equal(TOKEN_DECIMALS);
+    });
+
+    it("Rejects LP decimals above 9", async () => {
+      try {
+        await createFreshPool([user1], 1_000_000_000n, CURVE_CONSTANT_PRODUCT, 0, 10);
+        expect.fail("10 LP decimals is above the cap");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InvalidAmount");
+      }
+    });
+
+    it("Round-trips deposits through a 9-decimal LP mint", async () => {
+      const pool = await createFreshPool([user1, user2], 10_000_000_000n, CURVE_CONSTANT_PRODUCT, 0, 9);
+      expect((await getMint(provider.connection, pool.lpMint)).decimals).to.equal(9);
+      expect((await program.account.swapPool.fetch(pool.swapPool)).lpDecimals).to.equal(9);
+
+      // Initial LP is the geometric mean expressed in 9 decimals: 1,000 tokens each side mint 1,000 LP
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(1_000_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+      expect((await getMint(provider.connection, pool.lpMint)).supply).to.equal(1_000_000_000_000n);
+
+      const accounts = pool.tokenAccountsOf(user2);
+      const balanceA = (await getAccount(provider.connection, accounts.a)).amount;
+      const balanceB = (await getAccount(provider.connection, accounts.b)).amount;
+      await program.methods
+        .addLiquidity(new anchor.BN(123_456_789), new anchor.BN(123_456_789), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+        .accounts(pool.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+      await program.methods
+        .removeAllLiquidity(new anchor.BN(0), new anchor.BN(0), false)
+        .accounts(pool.accountsFor(user2))
+        .signers([user2])
+        .rpc();
+
+      // The extra LP precision leaves at most one unit of rounding per side
+      expect(balanceA - (await getAccount(provider.connection, accounts.a)).amount <= 1n).to.be.true;
+      expect(balanceB - (await getAccount(provider.connection, accounts.b)).amount <= 1n).to.be.true;
+    });
+  });
+
+  describe("swap_route", () => {
+    // Pool 1 trades X for Y and pool 2 trades Y for Z, so Y is the intermediate token
+    let first: Awaited<ReturnType<typeof createFreshPool>>;
+    let second: Awaited<ReturnType<typeof createFreshPool>>;
+
+    // A hop's accounts in Swap order; optional accounts left out are passed as the program id
+    const hopAccounts = (pool: Awaited<ReturnType<typeof createFreshPool>>) => {
+      const accounts = pool.accountsFor(user1);
+      const meta = (pubkey: PublicKey, isWritable = false, isSigner = false) => ({ pubkey, isWritable, isSigner });
+      return [
+        meta(accounts.swapPool, true),
+        meta(accounts.tokenAMint, true),
+        meta(accounts.tokenBMint, true),
+        meta(accounts.tokenAVault, true),
+        meta(accounts.tokenBVault, true),
+        meta(accounts.feeVaultA, true),
+        meta(accounts.feeVaultB, true),
+        meta(accounts.userTokenA, true),
+        meta(accounts.userTokenB, true),
+        meta(accounts.lpMint),
+        meta(accounts.poolAuthority),
+        meta(user1.publicKey, true, true),
+        meta(program.programId),
+        meta(program.programId),
+        meta(TOKEN_PROGRAM_ID),
+        meta(SystemProgram.programId),
+        meta(program.programId),
+        meta(program.programId),
+      ];
+    };
+    const swapRoute = (amountIn: number, minAmountOut: number | anchor.BN, reverse = false) =>
+      program.methods
+        .swapRoute(new anchor.BN(amountIn), new anchor.BN(minAmountOut.toString()), [!reverse, !reverse])
+        .accounts({ userAuthority: user1.publicKey })
+        .remainingAccounts(reverse ? [...hopAccounts(second), ...hopAccounts(first)] : [...hopAccounts(first), ...hopAccounts(second)])
+        .signers([user1])
+        .rpc();
+    const quoteRoute = (amountIn: number, reverse = false) =>
+      program.methods
+        .calculateRouteResult(new anchor.BN(amountIn))
+        .remainingAccounts(
+          (reverse ? [second, first] : [first, second]).flatMap((pool) =>
+            (reverse ? [pool.swapPool, pool.vaultB, pool.vaultA] : [pool.swapPool, pool.vaultA, pool.vaultB]).map(
+              (pubkey) => ({ pubkey, isWritable: false, isSigner: false })
+            )
+          )
+        )
+        .view();
+    const balanceOfZ = async () => (await getAccount(provider.connection, second.tokenAccountsOf(user1).b)).amount;
+
+    before(async () => {
+      const [x, y, z] = [
+        await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS),
+        await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS),
+        await createMint(provider.connection, admin, admin.publicKey, null, TOKEN_DECIMALS),
+      ].sort((left, right) => Buffer.compare(left.toBuffer(), right.toBuffer()));
+      first = await createFreshPool([user1], 10_000_000_000n, CURVE_CONSTANT_PRODUCT, 0, null, [x, y]);
+      second = await createFreshPool([user1], 10_000_000_000n, CURVE_CONSTANT_PRODUCT, 0, null, [y, z]);
+
+      for (const pool of [first, second]) {
+        await program.methods
+          .addInitialLiquidity(new anchor.BN(1_000_000_000), new anchor.BN(2_000_000_000), new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc();
+      }
+    });
+
+    it("Reverts the whole route when the final output misses the minimum", async () => {
+      const quoted = await quoteRoute(1_000_000);
+      const yBefore = (await getAccount(provider.connection, first.tokenAccountsOf(user1).b)).amount;
+      const zBefore = await balanceOfZ();
+
+      try {
+        await swapRoute(1_000_000, quoted.addn(1));
+        expect.fail("the route can't pay more than quoted");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("SlippageExceeded");
+      }
+      expect((await getAccount(provider.connection, first.tokenAccountsOf(user1).b)).amount).to.equal(yBefore);
+      expect(await balanceOfZ()).to.equal(zBefore);
+    });
+
+    it("Delivers the quoted amount of C through both pools", async () => {
+      const quoted = await quoteRoute(1_000_000);
+      const yBefore = (await getAccount(provider.connection, first.tokenAccountsOf(user1).b)).amount;
+      const zBefore = await balanceOfZ();
+
+      await swapRoute(1_000_000, quoted);
+
+      expect((await balanceOfZ()) - zBefore).to.equal(BigInt(quoted.toString()));
+      // The intermediate token only passed through
+      expect((await getAccount(provider.connection, first.tokenAccountsOf(user1).b)).amount).to.equal(yBefore);
+    });
+
+    it("Routes back from Z to X with both hops swapping B to A", async () => {
+      const quoted = await quoteRoute(1_000_000, true);
+      const xBefore = (await getAccount(provider.connection, first.tokenAccountsOf(user1).a)).amount;
+      const zBefore = await balanceOfZ();
+
+      await swapRoute(1_000_000, quoted, true);
+
+      expect(zBefore - (await balanceOfZ())).to.equal(1_000_000n);
+      expect((await getAccount(provider.connection, first.tokenAccountsOf(user1).a)).amount - xBefore).to.equal(
+        BigInt(quoted.toString())
+      );
+    });
+  });
+
+  describe("insufficient user balance", () => {
+    let pool: Awaited<ReturnType<typeof createFreshPool>>;
+    const expectInsufficientFunds = async (call: Promise<unknown>) => {
+      try {
+        await call;
+        expect.fail("the user doesn't hold that much");
+      } catch (err) {
+        expect(err.error.errorCode.code).to.equal("InsufficientFunds");
+      }
+    };
+
+    before(async () => {
+      // user2 gets only 1,000 tokens of each side
+      pool = await createFreshPool([user1, user2], 1_000_000_000n);
+    });
+
+    it("Rejects an initial deposit larger than the balance", async () => {
+      await expectInsufficientFunds(
+        program.methods
+          .addInitialLiquidity(new anchor.BN(1_000_000_001), new anchor.BN(1_000_000), new anchor.BN(0))
+          .accounts(pool.accountsFor(user1))
+          .signers([user1])
+          .rpc()
+      );
+      await program.methods
+        .addInitialLiquidity(new anchor.BN(500_000_000), new anchor.BN(500_000_000), new anchor.BN(0))
+        .accounts(pool.accountsFor(user1))
+        .signers([user1])
+        .rpc();
+    });
+
+    it("Rejects a deposit larger than the balance", async () => {
+      await expectInsufficientFunds(
+        program.methods
+          .addLiquidity(new anchor.BN(2_000_000_000), new anchor.BN(2_000_000_000), new anchor.BN(0), new anchor.BN(0), null, new anchor.BN(0))
+          .accounts(pool.accountsFor(user2))
+          .signers([user2])
+          .rpc()
+      );
+    });
+
+    it("Rejects a swap larger than the balance", async () => {
+      await expectInsufficientFunds(
+        program.methods
+          .swap(new anchor.BN(1_000_000_001), new anchor.BN(0), true, false)
+          .accounts({ ...pool.accountsFor(user2), feeDestinationA: null, feeDestinationB: null, feeExemption: null })
+          .signers([user2])
+          .rpc()
+      );
+    });
   });
 });